@@ -0,0 +1,71 @@
+/// The audio processing unit. No sound channels are emulated yet: the
+/// sound registers are plain I/O bytes on the bus. What runs is the frame
+/// sequencer, which will clock the channels' length counters, sweep and
+/// envelopes, kept in step with DIV like on hardware.
+pub struct Apu {
+    /// The DIV bit the frame sequencer watches, as of the last tick
+    div_bit: bool,
+    /// Step 0-7 of the frame sequencer
+    frame_step: u8,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            div_bit: false,
+            frame_step: 0,
+        }
+    }
+
+    /// Advances one M-cycle, given the timer's internal counter after its
+    /// tick. The frame sequencer steps on each falling edge of DIV bit 4
+    /// (bit 5 at double speed), 512 times a second, so DIV writes can
+    /// step it early.
+    pub fn tick(&mut self, div_counter: u16, double_speed: bool) {
+        let bit = if double_speed { 13 } else { 12 };
+        let div_bit = div_counter & (1 << bit) != 0;
+        if self.div_bit && !div_bit {
+            self.frame_step = (self.frame_step + 1) % 8;
+        }
+        self.div_bit = div_bit;
+    }
+
+    pub fn frame_step(&self) -> u8 {
+        self.frame_step
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_sequencer_follows_div() {
+        let mut apu = Apu::new();
+        apu.tick(0x1000, false);
+        assert_eq!(apu.frame_step(), 0);
+        apu.tick(0x2000, false);
+        assert_eq!(apu.frame_step(), 1);
+        // a DIV reset while the bit is set steps it too
+        apu.tick(0x1000, false);
+        apu.tick(0x0000, false);
+        assert_eq!(apu.frame_step(), 2);
+        // at double speed, DIV runs twice as fast and bit 5 is watched
+        apu.tick(0x1000, true);
+        apu.tick(0x2000, true);
+        assert_eq!(apu.frame_step(), 2);
+        apu.tick(0x4000, true);
+        assert_eq!(apu.frame_step(), 3);
+        for _ in 0..5 {
+            apu.tick(0x2000, true);
+            apu.tick(0x4000, true);
+        }
+        assert_eq!(apu.frame_step(), 0);
+    }
+}
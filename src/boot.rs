@@ -0,0 +1,233 @@
+use std::fmt;
+
+use crate::cartridge::{Cartridge, CgbSupport};
+use crate::model::Model;
+use crate::register::{CpuFlags, Registers};
+
+/// Size of the DMG, MGB, SGB and SGB2 boot ROMs.
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+/// Size of the CGB and AGB boot ROMs, which skip 0x0100-0x01FF so the
+/// cartridge header shows through.
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BootRomError {
+    /// Neither a DMG (256 bytes) nor a CGB (2304 bytes) boot ROM.
+    Size { len: usize },
+}
+
+impl fmt::Display for BootRomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BootRomError::Size { len } => write!(
+                f,
+                "boot ROM is {len} bytes, expected {DMG_BOOT_ROM_SIZE} or {CGB_BOOT_ROM_SIZE}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BootRomError {}
+
+/// A boot ROM image, mapped over the cartridge from power on until a write
+/// to 0xFF50 unmaps it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BootRom {
+    bytes: Vec<u8>,
+}
+
+impl BootRom {
+    pub fn new(bytes: Vec<u8>) -> Result<BootRom, BootRomError> {
+        match bytes.len() {
+            DMG_BOOT_ROM_SIZE | CGB_BOOT_ROM_SIZE => Ok(BootRom { bytes }),
+            len => Err(BootRomError::Size { len }),
+        }
+    }
+
+    /// The byte the boot ROM puts at `address`, or `None` where the
+    /// cartridge shows through.
+    pub fn read(&self, address: u16) -> Option<u8> {
+        match address {
+            0x0100..=0x01FF => None,
+            _ => self.bytes.get(address as usize).copied(),
+        }
+    }
+}
+
+/// CPU registers as `model`'s boot ROM leaves them when it jumps to 0x0100.
+///
+/// Some depend on the cartridge: the DMG and MGB boot ROMs leave H and C set
+/// unless the header checksum is 0, and a CGB running a DMG game leaves the
+/// title checksum of Nintendo games in B. Without a cartridge, the
+/// checksums are taken to be nonzero, as they are for nearly every game.
+pub fn post_boot_registers(model: Model, cartridge: Option<&Cartridge>) -> Registers {
+    let header = cartridge.map(|c| &c.header);
+    let header_checksum = header.map_or(0xFF, |h| h.header_checksum);
+    let dmg_flags = if header_checksum == 0 {
+        CpuFlags::Z as u8
+    } else {
+        CpuFlags::Z as u8 | CpuFlags::H as u8 | CpuFlags::C as u8
+    };
+    let cgb_game = header.is_none_or(|h| h.cgb != CgbSupport::None);
+    // a, f, b, c, d, e, h, l
+    let values = match model {
+        Model::Dmg => [0x01, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+        Model::Mgb => [0xFF, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+        Model::Sgb => [0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60],
+        Model::Sgb2 => [0xFF, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60],
+        Model::Cgb | Model::Agb if cgb_game => {
+            [0x11, CpuFlags::Z as u8, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D]
+        }
+        Model::Cgb | Model::Agb => {
+            let b = cartridge.map_or(0, title_checksum);
+            [0x11, CpuFlags::Z as u8, b, 0x00, 0x00, 0x08, 0x00, 0x7C]
+        }
+    };
+    let [a, f, b, c, d, e, h, l] = values;
+    let mut registers = Registers {
+        a,
+        f,
+        b,
+        c,
+        d,
+        e,
+        h,
+        l,
+        pc: 0x0100,
+        sp: 0xFFFE,
+    };
+    if model == Model::Agb {
+        // the AGB boot ROM ends with an INC B, which also sets the flags
+        registers.b = b.wrapping_add(1);
+        registers.set_flag(CpuFlags::Z, registers.b == 0);
+        registers.set_flag(CpuFlags::H, registers.b & 0x0F == 0);
+    }
+    registers
+}
+
+/// Sum of the title bytes, which the CGB boot ROM computes to pick a
+/// palette for Nintendo's DMG games, and 0 for everyone else's.
+fn title_checksum(cartridge: &Cartridge) -> u8 {
+    let header = &cartridge.header;
+    let nintendo = header.old_licensee_code == 0x01
+        || (header.old_licensee_code == 0x33 && header.new_licensee_code == *b"01");
+    if !nintendo {
+        return 0;
+    }
+    cartridge.rom()[0x134..0x144]
+        .iter()
+        .fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+/// The internal counter behind DIV (0xFF04) when the boot ROM hands over.
+///
+/// The SGB boot ROM waits on the SNES and the CGB one takes longer for DMG
+/// games it colorizes, so those values vary; these are the usual ones.
+pub fn post_boot_div_counter(model: Model) -> u16 {
+    match model {
+        Model::Dmg | Model::Mgb => 0xABCC,
+        Model::Sgb | Model::Sgb2 => 0x0000,
+        Model::Cgb | Model::Agb => 0x267C,
+    }
+}
+
+/// I/O registers in 0xFF00-0xFF7F that `model`'s boot ROM leaves with a
+/// value other than their power-on one, as (address, value) pairs. IF
+/// (0xFF0F) has the VBlank flag set.
+pub fn post_boot_io(model: Model) -> Vec<(u16, u8)> {
+    let mut io = vec![
+        (0xFF01, 0x00),
+        (0xFF02, if model.is_cgb() { 0x7F } else { 0x7E }),
+        (0xFF0F, 0xE1),
+        (0xFF10, 0x80),
+        (0xFF11, 0xBF),
+        (0xFF12, 0xF3),
+        (0xFF13, 0xFF),
+        (0xFF14, 0xBF),
+        (0xFF16, 0x3F),
+        (0xFF17, 0x00),
+        (0xFF18, 0xFF),
+        (0xFF19, 0xBF),
+        (0xFF1A, 0x7F),
+        (0xFF1B, 0xFF),
+        (0xFF1C, 0x9F),
+        (0xFF1D, 0xFF),
+        (0xFF1E, 0xBF),
+        (0xFF20, 0xFF),
+        (0xFF21, 0x00),
+        (0xFF22, 0x00),
+        (0xFF23, 0xBF),
+        (0xFF24, 0x77),
+        (0xFF25, 0xF3),
+        (0xFF26, if model.is_sgb() { 0xF0 } else { 0xF1 }),
+        (0xFF40, 0x91),
+        (0xFF41, 0x85),
+        (0xFF46, if model.is_cgb() { 0x00 } else { 0xFF }),
+        (0xFF47, 0xFC),
+    ];
+    io.extend([0xFF42, 0xFF43, 0xFF44, 0xFF45, 0xFF4A, 0xFF4B].map(|a| (a, 0x00)));
+    io
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{fix_checksums, test_rom};
+
+    fn cartridge(patch: impl Fn(&mut Vec<u8>)) -> Cartridge {
+        let mut rom = test_rom(0x00, 0x00, 0x00);
+        patch(&mut rom);
+        fix_checksums(&mut rom);
+        Cartridge::from_bytes(rom).unwrap()
+    }
+
+    #[test]
+    fn test_boot_rom_sizes_and_header_window() {
+        assert_eq!(
+            BootRom::new(vec![0; 0x200]),
+            Err(BootRomError::Size { len: 0x200 })
+        );
+        let dmg = BootRom::new(vec![0xAA; DMG_BOOT_ROM_SIZE]).unwrap();
+        assert_eq!(dmg.read(0x00FF), Some(0xAA));
+        assert_eq!(dmg.read(0x0100), None);
+        assert_eq!(dmg.read(0x0200), None);
+        let cgb = BootRom::new(vec![0xBB; CGB_BOOT_ROM_SIZE]).unwrap();
+        assert_eq!(cgb.read(0x0150), None);
+        assert_eq!(cgb.read(0x0200), Some(0xBB));
+        assert_eq!(cgb.read(0x0900), None);
+    }
+
+    #[test]
+    fn test_post_boot_registers_per_model() {
+        let dmg_game = cartridge(|_| {});
+        let dmg = post_boot_registers(Model::Dmg, Some(&dmg_game));
+        assert_eq!((dmg.a, dmg.f), (0x01, 0xB0));
+        assert_eq!(post_boot_registers(Model::Mgb, Some(&dmg_game)).a, 0xFF);
+        let sgb = post_boot_registers(Model::Sgb, Some(&dmg_game));
+        assert_eq!((sgb.a, sgb.f, sgb.get_hl()), (0x01, 0x00, 0xC060));
+
+        // games check A for 0x11 and B bit 0 to tell CGB and AGB apart
+        let cgb_game = cartridge(|rom| rom[0x143] = 0x80);
+        let cgb = post_boot_registers(Model::Cgb, Some(&cgb_game));
+        assert_eq!(
+            (cgb.a, cgb.f, cgb.b, cgb.get_de()),
+            (0x11, 0x80, 0x00, 0xFF56)
+        );
+        let agb = post_boot_registers(Model::Agb, Some(&cgb_game));
+        assert_eq!((agb.a, agb.f, agb.b), (0x11, 0x00, 0x01));
+
+        // a DMG game on a CGB
+        let cgb_dmg = post_boot_registers(Model::Cgb, Some(&dmg_game));
+        assert_eq!((cgb_dmg.get_de(), cgb_dmg.get_hl()), (0x0008, 0x007C));
+    }
+
+    #[test]
+    fn test_zero_header_checksum_clears_h_and_c() {
+        // the version byte is part of the checksum: pick one that zeroes it
+        let game = (0..=0xFF)
+            .map(|version| cartridge(|rom| rom[0x14C] = version))
+            .find(|c| c.header.header_checksum == 0)
+            .unwrap();
+        assert_eq!(post_boot_registers(Model::Dmg, Some(&game)).f, 0x80);
+    }
+}
@@ -0,0 +1,890 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::mbc::rtc::Rtc;
+use crate::mbc::{
+    HuC1, HuC3, MBC2_RAM_SIZE, MBC7_EEPROM_SIZE, Mbc, Mbc1, Mbc2, Mbc3, Mbc5, Mbc6, Mbc7, Mmm01,
+    NoMbc, PocketCamera, TAMA5_RAM_SIZE, Tama5,
+};
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+/// The logo at 0x104-0x133 that the boot ROM compares against.
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+/// End of the header; anything shorter is not a cartridge image.
+const HEADER_END: usize = 0x150;
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(std::io::Error),
+    /// The image ends before the header does.
+    Truncated {
+        len: usize,
+    },
+    /// 0x104-0x133 differs from the Nintendo logo.
+    BadLogo,
+    /// The checksum at 0x14D does not match the header bytes. The boot ROM
+    /// refuses to start such a cartridge.
+    HeaderChecksum {
+        expected: u8,
+        actual: u8,
+    },
+    /// The checksum at 0x14E-0x14F does not match the image. Hardware never
+    /// checks it, so only `Cartridge::verify_global_checksum` reports it.
+    GlobalChecksum {
+        expected: u16,
+        actual: u16,
+    },
+    UnknownCartridgeType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    /// Save data is neither the size of the cartridge RAM nor that plus the
+    /// mapper's own state (RTC, flash).
+    SaveSize {
+        expected: usize,
+        actual: usize,
+    },
+    /// The image is not as large as the ROM size in the header says.
+    SizeMismatch {
+        header: usize,
+        actual: usize,
+    },
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::Io(e) => write!(f, "{e}"),
+            CartridgeError::Truncated { len } => {
+                write!(f, "image is {len} bytes, too short for a cartridge header")
+            }
+            CartridgeError::BadLogo => write!(f, "Nintendo logo in the header does not match"),
+            CartridgeError::HeaderChecksum { expected, actual } => write!(
+                f,
+                "header checksum is {actual:#04X}, the header says {expected:#04X}"
+            ),
+            CartridgeError::GlobalChecksum { expected, actual } => write!(
+                f,
+                "global checksum is {actual:#06X}, the header says {expected:#06X}"
+            ),
+            CartridgeError::UnknownCartridgeType(code) => {
+                write!(f, "unknown cartridge type {code:#04X}")
+            }
+            CartridgeError::UnknownRomSize(code) => write!(f, "unknown ROM size code {code:#04X}"),
+            CartridgeError::UnknownRamSize(code) => write!(f, "unknown RAM size code {code:#04X}"),
+            CartridgeError::SaveSize { expected, actual } => write!(
+                f,
+                "save data is {actual} bytes, expected {expected} for this cartridge"
+            ),
+            CartridgeError::SizeMismatch { header, actual } => write!(
+                f,
+                "image is {actual} bytes but the header declares {header} bytes of ROM"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CartridgeError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CartridgeError {
+    fn from(e: std::io::Error) -> Self {
+        CartridgeError::Io(e)
+    }
+}
+
+/// Memory bank controller on the cartridge.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mapper {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mmm01,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    PocketCamera,
+    Tama5,
+    HuC3,
+    HuC1,
+}
+
+/// Cartridge type byte (0x147): the mapper and what else is on the board.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mapper: Mapper,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+}
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> Option<CartridgeType> {
+        use Mapper::*;
+        // (mapper, ram, battery, timer, rumble)
+        let (mapper, ram, battery, timer, rumble) = match code {
+            0x00 => (RomOnly, false, false, false, false),
+            0x01 => (Mbc1, false, false, false, false),
+            0x02 => (Mbc1, true, false, false, false),
+            0x03 => (Mbc1, true, true, false, false),
+            0x05 => (Mbc2, false, false, false, false),
+            0x06 => (Mbc2, false, true, false, false),
+            0x08 => (RomOnly, true, false, false, false),
+            0x09 => (RomOnly, true, true, false, false),
+            0x0B => (Mmm01, false, false, false, false),
+            0x0C => (Mmm01, true, false, false, false),
+            0x0D => (Mmm01, true, true, false, false),
+            0x0F => (Mbc3, false, true, true, false),
+            0x10 => (Mbc3, true, true, true, false),
+            0x11 => (Mbc3, false, false, false, false),
+            0x12 => (Mbc3, true, false, false, false),
+            0x13 => (Mbc3, true, true, false, false),
+            0x19 => (Mbc5, false, false, false, false),
+            0x1A => (Mbc5, true, false, false, false),
+            0x1B => (Mbc5, true, true, false, false),
+            0x1C => (Mbc5, false, false, false, true),
+            0x1D => (Mbc5, true, false, false, true),
+            0x1E => (Mbc5, true, true, false, true),
+            0x20 => (Mbc6, true, true, false, false),
+            0x22 => (Mbc7, true, true, false, true),
+            0xFC => (PocketCamera, true, true, false, false),
+            0xFD => (Tama5, true, true, true, false),
+            0xFE => (HuC3, true, true, true, false),
+            0xFF => (HuC1, true, true, false, false),
+            _ => return None,
+        };
+        Some(CartridgeType {
+            code,
+            mapper,
+            ram,
+            battery,
+            timer,
+            rumble,
+        })
+    }
+}
+
+/// CGB flag (0x143).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CgbSupport {
+    /// A DMG game; bytes 0x13F-0x143 are part of the title.
+    None,
+    /// 0x80: uses CGB features but also runs on a DMG.
+    Compatible,
+    /// 0xC0: refuses to run on a DMG.
+    Only,
+}
+
+/// The cartridge header at 0x100-0x14F.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub title: String,
+    /// Four uppercase characters at 0x13F-0x142 on later CGB cartridges.
+    pub manufacturer_code: Option<String>,
+    pub cgb: CgbSupport,
+    /// SGB flag (0x146) is 0x03.
+    pub sgb: bool,
+    /// Old licensee code (0x14B). 0x33 means the new code is used instead.
+    pub old_licensee_code: u8,
+    /// New licensee code (0x144-0x145), two ASCII characters.
+    pub new_licensee_code: [u8; 2],
+    pub cartridge_type: CartridgeType,
+    /// ROM size in bytes.
+    pub rom_size: usize,
+    /// External RAM size in bytes, as declared by 0x149. MBC2 and MBC7 have
+    /// their RAM built in and declare none.
+    pub ram_size: usize,
+    pub destination_japan: bool,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl Header {
+    /// Parses the header of `rom`, checking the logo and header checksum.
+    pub fn parse(rom: &[u8]) -> Result<Header, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::Truncated { len: rom.len() });
+        }
+        if rom[0x104..0x134] != NINTENDO_LOGO {
+            return Err(CartridgeError::BadLogo);
+        }
+        let actual = header_checksum(rom);
+        if actual != rom[0x14D] {
+            return Err(CartridgeError::HeaderChecksum {
+                expected: rom[0x14D],
+                actual,
+            });
+        }
+        let cgb = match rom[0x143] {
+            0xC0 => CgbSupport::Only,
+            flag if flag & 0x80 != 0 => CgbSupport::Compatible,
+            _ => CgbSupport::None,
+        };
+        let manufacturer = &rom[0x13F..0x143];
+        let manufacturer_code = (cgb != CgbSupport::None
+            && manufacturer.iter().all(u8::is_ascii_uppercase))
+        .then(|| String::from_utf8_lossy(manufacturer).into_owned());
+        let title_end = match (cgb, &manufacturer_code) {
+            (CgbSupport::None, _) => 0x144,
+            (_, Some(_)) => 0x13F,
+            (_, None) => 0x143,
+        };
+        let title = rom[0x134..title_end]
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '?'
+                }
+            })
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+        let cartridge_type = CartridgeType::from_code(rom[0x147])
+            .ok_or(CartridgeError::UnknownCartridgeType(rom[0x147]))?;
+        Ok(Header {
+            title,
+            manufacturer_code,
+            cgb,
+            sgb: rom[0x146] == 0x03,
+            old_licensee_code: rom[0x14B],
+            new_licensee_code: [rom[0x144], rom[0x145]],
+            cartridge_type,
+            rom_size: rom_size(rom[0x148])?,
+            ram_size: ram_size(rom[0x149])?,
+            destination_japan: rom[0x14A] == 0x00,
+            version: rom[0x14C],
+            header_checksum: rom[0x14D],
+            global_checksum: u16::from_be_bytes([rom[0x14E], rom[0x14F]]),
+        })
+    }
+
+    /// The licensee code as printed in licensee tables: the two-character
+    /// new code when 0x14B is 0x33, the old code in hex otherwise.
+    pub fn licensee_code(&self) -> String {
+        if self.old_licensee_code == 0x33 {
+            String::from_utf8_lossy(&self.new_licensee_code).into_owned()
+        } else {
+            format!("{:02X}", self.old_licensee_code)
+        }
+    }
+
+    pub fn rom_banks(&self) -> usize {
+        self.rom_size / ROM_BANK_SIZE
+    }
+}
+
+/// A cartridge image with a valid header, plus its external RAM.
+pub struct Cartridge {
+    pub header: Header,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mbc: Box<dyn Mbc>,
+    /// Where battery-backed RAM is persisted, for cartridges opened with
+    /// `open` that have a battery
+    save_path: Option<PathBuf>,
+    /// RAM or RTC written since the save file was last written
+    dirty: bool,
+    last_save: Instant,
+}
+
+impl Cartridge {
+    /// Validates `rom` and wraps it. The image must be at least as large as
+    /// the header declares; overdumps and padding past that stay unmapped.
+    ///
+    /// MMM01 multicarts are recognized by the header of their menu, in the
+    /// last 32 KiB, and take their size from the image.
+    pub fn from_bytes(mut rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        let header = match mmm01_header(&rom) {
+            Some(header) => header,
+            None => {
+                let header = Header::parse(&rom)?;
+                if rom.len() < header.rom_size {
+                    return Err(CartridgeError::SizeMismatch {
+                        header: header.rom_size,
+                        actual: rom.len(),
+                    });
+                }
+                rom.truncate(header.rom_size);
+                header
+            }
+        };
+        let mbc: Box<dyn Mbc> = match header.cartridge_type.mapper {
+            Mapper::RomOnly => Box::new(NoMbc),
+            Mapper::Mbc1 => Box::new(Mbc1::new(Mbc1::is_multicart(&rom))),
+            Mapper::Mbc2 => Box::new(Mbc2::new()),
+            Mapper::Mbc3 => {
+                let mbc30 =
+                    header.rom_size > 128 * ROM_BANK_SIZE || header.ram_size > 4 * RAM_BANK_SIZE;
+                Box::new(Mbc3::new(mbc30, header.cartridge_type.timer))
+            }
+            Mapper::Mbc5 => Box::new(Mbc5::new(header.cartridge_type.rumble)),
+            Mapper::Mmm01 => Box::new(Mmm01::new(rom.len() / ROM_BANK_SIZE)),
+            Mapper::Mbc6 => Box::new(Mbc6::new()),
+            Mapper::Mbc7 => Box::new(Mbc7::new()),
+            Mapper::PocketCamera => Box::new(PocketCamera::new()),
+            Mapper::Tama5 => Box::new(Tama5::new()),
+            Mapper::HuC3 => Box::new(HuC3::new()),
+            Mapper::HuC1 => Box::new(HuC1::new()),
+        };
+        // mappers whose memory the header does not describe
+        let ram_size = match header.cartridge_type.mapper {
+            Mapper::Mbc2 => MBC2_RAM_SIZE,
+            Mapper::Mbc7 => MBC7_EEPROM_SIZE,
+            Mapper::Tama5 => TAMA5_RAM_SIZE,
+            _ => header.ram_size,
+        };
+        let ram = vec![0x00; ram_size];
+        Ok(Cartridge {
+            header,
+            rom,
+            ram,
+            mbc,
+            save_path: None,
+            dirty: false,
+            last_save: Instant::now(),
+        })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Cartridge, CartridgeError> {
+        Cartridge::from_bytes(fs::read(path)?)
+    }
+
+    /// Loads the ROM at `path` and, if the cartridge has a battery, the
+    /// `.sav` file next to it. The save is written back by `flush_save`
+    /// and `autosave`; call `flush_save` before exiting to learn whether
+    /// the last save made it to disk.
+    pub fn open(path: impl AsRef<Path>) -> Result<Cartridge, CartridgeError> {
+        let path = path.as_ref();
+        let mut cartridge = Cartridge::from_file(path)?;
+        if cartridge.header.cartridge_type.battery {
+            let save_path = path.with_extension("sav");
+            if save_path.exists() {
+                cartridge.load_save_data(&fs::read(&save_path)?, unix_now())?;
+            }
+            cartridge.save_path = Some(save_path);
+        }
+        Ok(cartridge)
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// CPU read from 0x0000-0x7FFF, through the mapper.
+    pub fn read_rom(&self, address: u16) -> u8 {
+        self.mbc.read_rom(&self.rom, address)
+    }
+    /// CPU write to 0x0000-0x7FFF. ROM is read-only; the mapper listens here.
+    pub fn write_rom(&mut self, address: u16, value: u8) {
+        self.mbc.write_rom(address, value);
+        self.dirty |= self.mbc.take_dirty();
+    }
+
+    /// CPU read from 0xA000-0xBFFF. Reads 0xFF when there is no RAM or it
+    /// is disabled; a RAM smaller than the window is mirrored.
+    pub fn read_ram(&self, address: u16) -> u8 {
+        self.mbc.read_ram(&self.ram, address)
+    }
+    pub fn write_ram(&mut self, address: u16, value: u8) {
+        self.dirty |= self.mbc.write_ram(&mut self.ram, address, value);
+        self.dirty |= self.mbc.take_dirty();
+    }
+
+    /// ROM bank the mapper currently has at `address`.
+    pub fn rom_bank(&self, address: u16) -> u16 {
+        self.mbc.rom_bank(address)
+    }
+
+    /// Advances on-cartridge clocks by `cycles` base clock cycles (4 MiHz).
+    pub fn tick(&mut self, cycles: u32) {
+        self.mbc.tick(cycles);
+    }
+    pub fn rtc(&self) -> Option<&Rtc> {
+        self.mbc.rtc()
+    }
+    /// The new state of the rumble motor if the game switched it since the
+    /// last call. Frontends poll this to drive force feedback.
+    pub fn take_rumble_change(&mut self) -> Option<bool> {
+        self.mbc.take_rumble_change()
+    }
+    /// Feeds the accelerometer of tilt cartridges (MBC7), in g.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y);
+    }
+    /// Feeds the Game Boy Camera sensor: 128x112 gray levels, 0 black.
+    pub fn set_camera_image(&mut self, image: &[u8]) {
+        self.mbc.set_camera_image(image);
+    }
+
+    /// The state a battery keeps: the RAM, followed by whatever else the
+    /// mapper persists (the RTC stamped with `now`, in seconds since the
+    /// UNIX epoch, or flash).
+    pub fn save_data(&self, now: u64) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        data.extend_from_slice(&self.mbc.save_extra(now));
+        data
+    }
+
+    /// Restores what `save_data` produced. A saved clock catches up on the
+    /// time between its timestamp and `now`; a save with only the RAM, as
+    /// emulators without RTC support write, leaves the rest alone.
+    pub fn load_save_data(&mut self, data: &[u8], now: u64) -> Result<(), CartridgeError> {
+        let ram_len = self.ram.len();
+        let size_error = CartridgeError::SaveSize {
+            expected: ram_len + self.mbc.extra_save_size(),
+            actual: data.len(),
+        };
+        if data.len() < ram_len {
+            return Err(size_error);
+        }
+        let (ram, footer) = data.split_at(ram_len);
+        if !footer.is_empty() && !self.mbc.load_extra(footer, now) {
+            return Err(size_error);
+        }
+        self.ram.copy_from_slice(ram);
+        Ok(())
+    }
+
+    pub fn save_path(&self) -> Option<&Path> {
+        self.save_path.as_deref()
+    }
+    /// Whether RAM or the RTC was written since the last save.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Writes the save file if anything changed since the last write. The
+    /// file is replaced atomically so a crash never leaves half a save.
+    /// This is the way to save: dropping the cartridge only tries it once
+    /// more and has nowhere to report a failure.
+    pub fn flush_save(&mut self) -> Result<(), CartridgeError> {
+        let Some(path) = &self.save_path else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+        let temporary = path.with_extension("sav.tmp");
+        fs::write(&temporary, self.save_data(unix_now()))?;
+        fs::rename(&temporary, path)?;
+        self.dirty = false;
+        self.last_save = Instant::now();
+        Ok(())
+    }
+
+    /// `flush_save`, at most once per `interval`. Call it regularly, e.g.
+    /// once per frame, so a crash loses at most `interval` of progress.
+    pub fn autosave(&mut self, interval: Duration) -> Result<(), CartridgeError> {
+        if self.last_save.elapsed() < interval {
+            return Ok(());
+        }
+        self.flush_save()
+    }
+
+    /// Patches the ROM image, for tests that place code in ROM.
+    #[cfg(test)]
+    pub(crate) fn rom_mut(&mut self) -> &mut [u8] {
+        &mut self.rom
+    }
+
+    /// Sum of every ROM byte except the two checksum bytes themselves.
+    pub fn global_checksum(&self) -> u16 {
+        global_checksum(&self.rom)
+    }
+
+    pub fn verify_global_checksum(&self) -> Result<(), CartridgeError> {
+        let actual = self.global_checksum();
+        if actual == self.header.global_checksum {
+            Ok(())
+        } else {
+            Err(CartridgeError::GlobalChecksum {
+                expected: self.header.global_checksum,
+                actual,
+            })
+        }
+    }
+}
+
+impl Drop for Cartridge {
+    /// A last, best-effort save for callers that did not flush; errors
+    /// are ignored.
+    fn drop(&mut self) {
+        let _ = self.flush_save();
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// The boot ROM's check over 0x134-0x14C.
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134..0x14D]
+        .iter()
+        .fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1))
+}
+
+fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 0x14E && *i != 0x14F)
+        .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16))
+}
+
+fn rom_size(code: u8) -> Result<usize, CartridgeError> {
+    match code {
+        0x00..=0x08 => Ok(0x8000 << code),
+        // unofficial sizes, listed in some documentation
+        0x52 => Ok(72 * ROM_BANK_SIZE),
+        0x53 => Ok(80 * ROM_BANK_SIZE),
+        0x54 => Ok(96 * ROM_BANK_SIZE),
+        _ => Err(CartridgeError::UnknownRomSize(code)),
+    }
+}
+
+fn ram_size(code: u8) -> Result<usize, CartridgeError> {
+    match code {
+        0x00 => Ok(0),
+        0x01 => Ok(0x800),
+        0x02 => Ok(RAM_BANK_SIZE),
+        0x03 => Ok(4 * RAM_BANK_SIZE),
+        0x04 => Ok(16 * RAM_BANK_SIZE),
+        0x05 => Ok(8 * RAM_BANK_SIZE),
+        _ => Err(CartridgeError::UnknownRamSize(code)),
+    }
+}
+
+/// The header of an MMM01 multicart. It lives in the menu at the end of the
+/// image; the one at 0x0100 belongs to the first game.
+fn mmm01_header(rom: &[u8]) -> Option<Header> {
+    let menu = rom.len().checked_sub(2 * ROM_BANK_SIZE)?;
+    let header = Header::parse(&rom[menu..]).ok()?;
+    (header.cartridge_type.mapper == Mapper::Mmm01).then_some(header)
+}
+
+/// A ROM image with a valid header and checksums, for tests: the logo, the
+/// title "TEST", the given type and size codes, `nop; jp $0150` at the
+/// entry point and `jr @` at 0x0150.
+#[cfg(test)]
+pub(crate) fn test_rom(cartridge_type: u8, rom_size_code: u8, ram_size_code: u8) -> Vec<u8> {
+    let mut rom = vec![0x00; rom_size(rom_size_code).unwrap()];
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+    rom[0x134..0x138].copy_from_slice(b"TEST");
+    rom[0x147] = cartridge_type;
+    rom[0x148] = rom_size_code;
+    rom[0x149] = ram_size_code;
+    rom[0x150..0x152].copy_from_slice(&[0x18, 0xFE]);
+    fix_checksums(&mut rom);
+    rom
+}
+
+/// Recomputes both checksums after a test has edited `rom`.
+#[cfg(test)]
+pub(crate) fn fix_checksums(rom: &mut [u8]) {
+    rom[0x14D] = header_checksum(rom);
+    let global = global_checksum(rom);
+    rom[0x14E..0x150].copy_from_slice(&global.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::MBC6_FLASH_SIZE;
+    use crate::mbc::rtc::RTC_SAVE_SIZE;
+
+    #[test]
+    fn test_parse_header() {
+        let mut rom = test_rom(0x1B, 0x02, 0x03);
+        rom[0x14B] = 0x33;
+        rom[0x144..0x146].copy_from_slice(b"01");
+        rom[0x146] = 0x03;
+        rom[0x14A] = 0x01;
+        rom[0x14C] = 0x02;
+        fix_checksums(&mut rom);
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(header.title, "TEST");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.cgb, CgbSupport::None);
+        assert!(header.sgb);
+        assert_eq!(header.licensee_code(), "01");
+        assert_eq!(header.cartridge_type.mapper, Mapper::Mbc5);
+        assert!(header.cartridge_type.battery);
+        assert!(!header.cartridge_type.rumble);
+        assert_eq!(header.rom_size, 128 * 1024);
+        assert_eq!(header.rom_banks(), 8);
+        assert_eq!(header.ram_size, 32 * 1024);
+        assert!(!header.destination_japan);
+        assert_eq!(header.version, 2);
+    }
+
+    #[test]
+    fn test_cgb_title_and_manufacturer() {
+        let mut rom = test_rom(0x00, 0x00, 0x00);
+        rom[0x134..0x13F].copy_from_slice(b"POKEMON_SLV");
+        rom[0x13F..0x143].copy_from_slice(b"AAXE");
+        rom[0x143] = 0x80;
+        fix_checksums(&mut rom);
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAXE"));
+        assert_eq!(header.cgb, CgbSupport::Compatible);
+        assert_eq!(header.licensee_code(), "00");
+    }
+
+    #[test]
+    fn test_rejects_bad_images() {
+        assert!(matches!(
+            Cartridge::from_bytes(vec![0; 0x100]),
+            Err(CartridgeError::Truncated { len: 0x100 })
+        ));
+
+        let mut rom = test_rom(0x00, 0x00, 0x00);
+        rom[0x104] = 0x00;
+        assert!(matches!(Header::parse(&rom), Err(CartridgeError::BadLogo)));
+
+        let mut rom = test_rom(0x00, 0x00, 0x00);
+        rom[0x134] = b'X';
+        assert!(matches!(
+            Header::parse(&rom),
+            Err(CartridgeError::HeaderChecksum { .. })
+        ));
+
+        let mut rom = test_rom(0x00, 0x00, 0x00);
+        rom[0x147] = 0x42;
+        fix_checksums(&mut rom);
+        assert!(matches!(
+            Header::parse(&rom),
+            Err(CartridgeError::UnknownCartridgeType(0x42))
+        ));
+
+        let mut rom = test_rom(0x01, 0x01, 0x00);
+        rom.truncate(0x8000);
+        assert!(matches!(
+            Cartridge::from_bytes(rom),
+            Err(CartridgeError::SizeMismatch {
+                header: 0x10000,
+                actual: 0x8000
+            })
+        ));
+    }
+
+    #[test]
+    fn test_overdumped_image_is_cut_to_header_size() {
+        let mut rom = test_rom(0x01, 0x00, 0x00);
+        rom.resize(0x10000, 0xAA);
+        let mut cartridge = Cartridge::from_bytes(rom).unwrap();
+        assert_eq!(cartridge.rom().len(), 0x8000);
+        // bank 2 is past the declared size: it wraps to bank 0, not padding
+        cartridge.write_rom(0x2000, 0x02);
+        assert_eq!(cartridge.read_rom(0x4150), 0x18);
+    }
+
+    #[test]
+    fn test_mmm01_found_by_its_menu_header() {
+        // the first game's MBC1 header at 0x0100, the menu's at the end
+        let mut rom = test_rom(0x01, 0x00, 0x00);
+        let mut menu = test_rom(0x0B, 0x00, 0x00);
+        menu[0x0200] = 0x42;
+        rom.extend_from_slice(&menu);
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        assert_eq!(cartridge.header.cartridge_type.mapper, Mapper::Mmm01);
+        assert_eq!(cartridge.rom().len(), 0x10000);
+        // unmapped at power on, showing the menu
+        assert_eq!(cartridge.read_rom(0x0200), 0x42);
+    }
+
+    #[test]
+    fn test_rom_is_read_only_and_ram_mirrors() {
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x09, 0x00, 0x01)).unwrap();
+        cartridge.write_rom(0x0150, 0x42);
+        assert_eq!(cartridge.read_rom(0x0150), 0x18);
+        cartridge.write_ram(0xA000, 0x42);
+        assert_eq!(cartridge.read_ram(0xA800), 0x42);
+
+        let cartridge = Cartridge::from_bytes(test_rom(0x00, 0x00, 0x00)).unwrap();
+        assert_eq!(cartridge.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_mbc1_switches_banks() {
+        let mut rom = test_rom(0x03, 0x02, 0x02);
+        rom[3 * ROM_BANK_SIZE] = 0x33;
+        fix_checksums(&mut rom);
+        let mut cartridge = Cartridge::from_bytes(rom).unwrap();
+        cartridge.write_rom(0x2000, 0x03);
+        assert_eq!(cartridge.read_rom(0x4000), 0x33);
+        assert_eq!(cartridge.rom_bank(0x4000), 3);
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x42);
+        assert_eq!(cartridge.ram()[0], 0x42);
+    }
+
+    #[test]
+    fn test_mbc2_has_built_in_ram() {
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x06, 0x03, 0x00)).unwrap();
+        assert_eq!(cartridge.ram().len(), 512);
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA001, 0x3C);
+        assert_eq!(cartridge.read_ram(0xB201), 0xFC);
+    }
+
+    #[test]
+    fn test_mbc3_rtc_save_round_trip() {
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x10, 0x06, 0x03)).unwrap();
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x42);
+        cartridge.write_rom(0x4000, 0x0A); // hours
+        cartridge.write_ram(0xA000, 5);
+        let save = cartridge.save_data(100);
+        assert_eq!(save.len(), 32 * 1024 + RTC_SAVE_SIZE);
+
+        let mut reloaded = Cartridge::from_bytes(test_rom(0x10, 0x06, 0x03)).unwrap();
+        reloaded.load_save_data(&save, 100 + 3 * 3600).unwrap();
+        assert_eq!(reloaded.ram()[0], 0x42);
+        assert_eq!(reloaded.rtc().unwrap().current().hours, 8);
+
+        assert!(matches!(
+            reloaded.load_save_data(&save[..100], 0),
+            Err(CartridgeError::SaveSize { .. })
+        ));
+        // a save from an emulator without RTC support keeps the clock
+        reloaded.load_save_data(&save[..32 * 1024], 0).unwrap();
+        assert_eq!(reloaded.rtc().unwrap().current().hours, 8);
+    }
+
+    #[test]
+    fn test_mbc5_rumble_event() {
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x1E, 0x05, 0x04)).unwrap();
+        assert_eq!(cartridge.header.cartridge_type.mapper, Mapper::Mbc5);
+        cartridge.write_rom(0x4000, 0x08);
+        assert_eq!(cartridge.take_rumble_change(), Some(true));
+    }
+
+    #[test]
+    fn test_mbc6_flash_is_saved() {
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x20, 0x00, 0x03)).unwrap();
+        cartridge.write_rom(0x0C00, 0x01); // flash enable
+        cartridge.write_rom(0x1000, 0x01); // flash write enable
+        cartridge.write_rom(0x2800, 0x08); // flash at 0x4000
+        for (bank, address, value) in [(2, 0x5555, 0xAA), (1, 0x4AAA, 0x55), (2, 0x5555, 0xA0)] {
+            cartridge.write_rom(0x2000, bank);
+            cartridge.write_rom(address, value);
+        }
+        assert!(!cartridge.is_dirty());
+        cartridge.write_rom(0x4000, 0x12);
+        assert!(cartridge.is_dirty());
+
+        let save = cartridge.save_data(0);
+        assert_eq!(save.len(), 32 * 1024 + MBC6_FLASH_SIZE);
+        assert_eq!(save[32 * 1024 + 2 * 0x2000], 0x12);
+    }
+
+    #[test]
+    fn test_mbc7_eeprom_and_tama5_ram_sizes() {
+        let cartridge = Cartridge::from_bytes(test_rom(0x22, 0x00, 0x00)).unwrap();
+        assert_eq!(cartridge.ram().len(), MBC7_EEPROM_SIZE);
+        let cartridge = Cartridge::from_bytes(test_rom(0xFD, 0x00, 0x00)).unwrap();
+        assert_eq!(cartridge.ram().len(), TAMA5_RAM_SIZE);
+    }
+
+    #[test]
+    fn test_dropped_ram_writes_leave_save_clean() {
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x03, 0x01, 0x02)).unwrap();
+        cartridge.write_ram(0xA000, 0x42);
+        assert!(!cartridge.is_dirty());
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x42);
+        assert!(cartridge.is_dirty());
+    }
+
+    #[test]
+    fn test_battery_save_file() {
+        let dir = std::env::temp_dir().join(format!("rustedboy-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("game.gb");
+        fs::write(&rom_path, test_rom(0x03, 0x01, 0x02)).unwrap();
+
+        let mut cartridge = Cartridge::open(&rom_path).unwrap();
+        assert_eq!(cartridge.save_path(), Some(dir.join("game.sav").as_path()));
+        assert!(!cartridge.is_dirty());
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA123, 0x42);
+        assert!(cartridge.is_dirty());
+        cartridge.autosave(Duration::from_secs(3600)).unwrap();
+        assert!(cartridge.is_dirty());
+        cartridge.flush_save().unwrap();
+        assert!(!cartridge.is_dirty());
+        let save = fs::read(dir.join("game.sav")).unwrap();
+        assert_eq!(save.len(), 8 * 1024);
+        assert_eq!(save[0x123], 0x42);
+
+        // dropping still saves, as a fallback
+        cartridge.write_ram(0xA124, 0x43);
+        drop(cartridge);
+        let cartridge = Cartridge::open(&rom_path).unwrap();
+        assert_eq!(cartridge.ram()[0x123..0x125], [0x42, 0x43]);
+        drop(cartridge);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_no_save_file_without_battery() {
+        let dir = std::env::temp_dir().join(format!("rustedboy-nosave-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("game.gb");
+        fs::write(&rom_path, test_rom(0x02, 0x01, 0x02)).unwrap();
+        let mut cartridge = Cartridge::open(&rom_path).unwrap();
+        assert_eq!(cartridge.save_path(), None);
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x42);
+        cartridge.flush_save().unwrap();
+        assert!(!dir.join("game.sav").exists());
+        drop(cartridge);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_global_checksum_mismatch() {
+        let mut rom = test_rom(0x00, 0x00, 0x00);
+        let cartridge = Cartridge::from_bytes(rom.clone()).unwrap();
+        assert!(cartridge.verify_global_checksum().is_ok());
+        rom[0x7FFF] = 0x01;
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        let error = cartridge.verify_global_checksum().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "global checksum is {:#06X}, the header says {:#06X}",
+                cartridge.header.global_checksum.wrapping_add(1),
+                cartridge.header.global_checksum
+            )
+        );
+    }
+}
@@ -1,30 +1,356 @@
+use std::fmt;
+
+use crate::boot::{self, BootRom};
+use crate::cartridge::Cartridge;
+use crate::instruction::{AluOp, Condition, Indirect, Instruction, Reg8, Reg16, ShiftOp, StackReg};
+use crate::interrupts::Interrupt;
 use crate::memorybus::MemoryBus;
+use crate::model::Model;
 use crate::register::CpuFlags;
 use crate::register::Registers;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuError {
+    /// One of the opcodes with no instruction behind them (0xD3, 0xDB, 0xDD,
+    /// 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD) was executed while
+    /// the policy is `IllegalOpcodePolicy::Error`.
+    IllegalOpcode { opcode: u8, pc: u16, bank: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { opcode, pc, bank } => {
+                write!(f, "illegal opcode {opcode:#04X} at {bank:02X}:{pc:04X}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+/// What the CPU does when it runs into an illegal opcode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IllegalOpcodePolicy {
+    /// Hang like the hardware does: no more instructions and no interrupts,
+    /// while the rest of the machine keeps running.
+    Lockup,
+    /// Report a `CpuError::IllegalOpcode` to the caller of `step`.
+    Error,
+}
+
+/// When the rest of the machine observes the CPU's memory accesses.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BusTiming {
+    /// All accesses of an instruction happen at once, then the bus is ticked
+    /// for the whole instruction. Reads see the timer and PPU as they were
+    /// when the instruction started, and writes land before any of its
+    /// cycles pass, so effects in the middle of an instruction are lost.
+    PerInstruction,
+    /// Every access lands on its own M-cycle, with the bus ticked before it,
+    /// so peripherals see reads and writes at the same point in the
+    /// instruction as on hardware (mooneye mem_timing, STAT/OAM races).
+    PerAccess,
+}
+
 pub struct Cpu {
     registers: Registers,
     bus: MemoryBus,
+    ime: bool,
+    /// Set by EI: IME turns on once the instruction after EI has completed.
+    ime_scheduled: bool,
+    /// HALT: no instructions run until `IE & IF != 0`.
+    halted: bool,
+    /// HALT executed with IME=0 and an interrupt already pending: the CPU
+    /// does not halt, but fails to increment PC on the next opcode fetch.
+    halt_bug: bool,
+    /// STOP: everything but the joypad is frozen until a button is pressed.
+    stopped: bool,
+    /// M-cycles left of the pause that follows a CGB speed switch.
+    speed_switch_stall: u16,
+    /// Hung after an illegal opcode; only a reset gets out of this.
+    locked_up: bool,
+    illegal_opcode_policy: IllegalOpcodePolicy,
+    timing: BusTiming,
+    /// M-cycles of the current instruction already ticked by `PerAccess` accesses.
+    cycles_ticked: u8,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cpu {
+    /// A DMG CPU with the registers the boot ROM leaves.
     pub fn new() -> Cpu {
+        Cpu::with_model(Model::Dmg)
+    }
+    /// A CPU on a `model` bus, with the registers its boot ROM leaves.
+    pub fn with_model(model: Model) -> Cpu {
         Cpu {
-            registers: Registers::new(),
-            bus: MemoryBus::new(),
+            registers: boot::post_boot_registers(model, None),
+            bus: MemoryBus::with_model(model),
+            ime: false,
+            ime_scheduled: false,
+            halted: false,
+            halt_bug: false,
+            stopped: false,
+            speed_switch_stall: 0,
+            locked_up: false,
+            illegal_opcode_policy: IllegalOpcodePolicy::Lockup,
+            timing: BusTiming::PerInstruction,
+            cycles_ticked: 0,
         }
     }
-    pub fn step(&mut self) {
-        let opcode = self.bus.read_data(self.registers.pc);
-        let bytes = self.execute(opcode);
-        self.registers.increment_pc(bytes);
+    /// A CPU with `cartridge` inserted, powered on as `model`, or as the
+    /// model the cartridge header asks for if `None`. With a boot ROM it
+    /// starts at 0x0000 in it; without one it starts at 0x0100 in the state
+    /// the model's boot ROM would have left behind.
+    pub fn power_on(model: Option<Model>, cartridge: Cartridge, boot_rom: Option<BootRom>) -> Cpu {
+        let model = model.unwrap_or_else(|| Model::for_header(&cartridge.header));
+        let mut cpu = Cpu::with_model(model);
+        match boot_rom {
+            Some(boot_rom) => {
+                cpu.registers = Registers::zeroed();
+                cpu.bus.boot_rom = Some(boot_rom);
+            }
+            None => {
+                cpu.registers = boot::post_boot_registers(model, Some(&cartridge));
+                cpu.bus.skip_boot();
+            }
+        }
+        cpu.bus.load_cartridge(cartridge);
+        cpu
     }
-
-    fn alu_add(&mut self, value: u8) {
-        let c = if self.registers.get_flag(CpuFlags::C) == true {
+    /// The bus, for hosts to reach the peripherals on it.
+    pub fn bus(&self) -> &MemoryBus {
+        &self.bus
+    }
+    pub fn bus_mut(&mut self) -> &mut MemoryBus {
+        &mut self.bus
+    }
+    pub fn set_bus_timing(&mut self, timing: BusTiming) {
+        self.timing = timing;
+    }
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.illegal_opcode_policy = policy;
+    }
+    /// Runs one instruction (or services one interrupt), advances the rest of
+    /// the machine by the time it took, and returns that time in T-cycles of
+    /// the 4 MiHz base clock, so an M-cycle counts 2 in CGB double speed.
+    /// While halted, stopped or locked up the CPU idles for a single M-cycle
+    /// per call. On error, PC points just past the offending opcode.
+    pub fn step(&mut self) -> Result<u32, CpuError> {
+        self.cycles_ticked = 0;
+        if self.stopped {
+            if !self.bus.joypad.any_line_low() {
+                // the system clock is off: nothing on the bus advances
+                return Ok(4);
+            }
+            self.stopped = false;
+        }
+        let pending = self.bus.pending_interrupts() != 0;
+        if self.halted && pending {
+            self.halted = false;
+        }
+        let m_cycles = if self.locked_up {
+            1
+        } else if self.speed_switch_stall > 0 {
+            self.speed_switch_stall -= 1;
+            1
+        } else if self.ime && pending {
+            self.service_interrupt()
+        } else if self.halted {
             1
         } else {
-            0
+            let enable_ime = self.ime_scheduled;
+            let instruction = Instruction::decode(&mut || self.fetch_byte());
+            let m_cycles = self.execute(instruction)?;
+            // a DI right after EI cancels the pending enable
+            if enable_ime && self.ime_scheduled {
+                self.ime = true;
+                self.ime_scheduled = false;
+            }
+            m_cycles
         };
+        // Whatever the accesses did not tick yet: everything in
+        // `PerInstruction` mode, trailing internal cycles in `PerAccess` mode.
+        self.bus.tick(m_cycles - self.cycles_ticked);
+        let t_cycles_per_m_cycle = if self.bus.double_speed { 2 } else { 4 };
+        Ok(m_cycles as u32 * t_cycles_per_m_cycle)
+    }
+
+    /// The 5 M-cycle interrupt dispatch: two internal cycles, PC pushed high
+    /// byte first, then the jump. Which interrupt gets serviced is decided
+    /// between the two pushes, so a high-byte push that lands on IE (SP =
+    /// 0x0000) can cancel the dispatch, in which case PC ends up at 0x0000
+    /// and IF is left alone.
+    fn service_interrupt(&mut self) -> u8 {
+        self.ime = false;
+        self.ime_scheduled = false;
+        self.internal_cycle();
+        self.internal_cycle();
+        if self.halt_bug {
+            // EI immediately followed by HALT: return to the HALT itself
+            self.halt_bug = false;
+            self.registers.pc = self.registers.pc.wrapping_sub(1);
+        }
+        let pc = self.registers.pc;
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(self.registers.sp, (pc >> 8) as u8);
+        let interrupt = Interrupt::highest_priority(self.bus.pending_interrupts());
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(self.registers.sp, pc as u8);
+        self.registers.pc = match interrupt {
+            Some(interrupt) => {
+                self.bus.acknowledge_interrupt(interrupt);
+                interrupt.vector()
+            }
+            None => 0x0000,
+        };
+        5
+    }
+
+    fn read(&mut self, address: u16) -> u8 {
+        self.access_cycle();
+        self.bus.read_data(address)
+    }
+    fn write(&mut self, address: u16, value: u8) {
+        self.access_cycle();
+        self.bus.write_data(address, value);
+    }
+    /// An M-cycle in which the CPU works internally without using the bus.
+    /// Only needed where such a cycle precedes a later access.
+    fn internal_cycle(&mut self) {
+        self.access_cycle();
+    }
+    fn access_cycle(&mut self) {
+        if self.timing == BusTiming::PerAccess {
+            self.bus.tick(1);
+            self.cycles_ticked += 1;
+        }
+    }
+
+    fn fetch_byte(&mut self) -> u8 {
+        let value = self.read(self.registers.pc);
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.registers.increment_pc(1);
+        }
+        value
+    }
+
+    fn read_r8(&mut self, r: Reg8) -> u8 {
+        match r {
+            Reg8::B => self.registers.b,
+            Reg8::C => self.registers.c,
+            Reg8::D => self.registers.d,
+            Reg8::E => self.registers.e,
+            Reg8::H => self.registers.h,
+            Reg8::L => self.registers.l,
+            Reg8::HlIndirect => self.read(self.registers.get_hl()),
+            Reg8::A => self.registers.a,
+        }
+    }
+    fn write_r8(&mut self, r: Reg8, value: u8) {
+        match r {
+            Reg8::B => self.registers.b = value,
+            Reg8::C => self.registers.c = value,
+            Reg8::D => self.registers.d = value,
+            Reg8::E => self.registers.e = value,
+            Reg8::H => self.registers.h = value,
+            Reg8::L => self.registers.l = value,
+            Reg8::HlIndirect => self.write(self.registers.get_hl(), value),
+            Reg8::A => self.registers.a = value,
+        }
+    }
+
+    fn read_r16(&self, rr: Reg16) -> u16 {
+        match rr {
+            Reg16::BC => self.registers.get_bc(),
+            Reg16::DE => self.registers.get_de(),
+            Reg16::HL => self.registers.get_hl(),
+            Reg16::SP => self.registers.sp,
+        }
+    }
+    fn write_r16(&mut self, rr: Reg16, value: u16) {
+        match rr {
+            Reg16::BC => self.registers.set_bc(value),
+            Reg16::DE => self.registers.set_de(value),
+            Reg16::HL => self.registers.set_hl(value),
+            Reg16::SP => self.registers.sp = value,
+        }
+    }
+
+    fn read_stack_reg(&self, rr: StackReg) -> u16 {
+        match rr {
+            StackReg::BC => self.registers.get_bc(),
+            StackReg::DE => self.registers.get_de(),
+            StackReg::HL => self.registers.get_hl(),
+            StackReg::AF => self.registers.get_af(),
+        }
+    }
+    fn write_stack_reg(&mut self, rr: StackReg, value: u16) {
+        match rr {
+            StackReg::BC => self.registers.set_bc(value),
+            StackReg::DE => self.registers.set_de(value),
+            StackReg::HL => self.registers.set_hl(value),
+            StackReg::AF => self.registers.set_af(value),
+        }
+    }
+
+    /// Evaluates a branch condition; no condition means always.
+    fn condition(&self, condition: Option<Condition>) -> bool {
+        match condition {
+            None => true,
+            Some(Condition::NZ) => !self.registers.get_flag(CpuFlags::Z),
+            Some(Condition::Z) => self.registers.get_flag(CpuFlags::Z),
+            Some(Condition::NC) => !self.registers.get_flag(CpuFlags::C),
+            Some(Condition::C) => self.registers.get_flag(CpuFlags::C),
+        }
+    }
+
+    /// Address of the memory operand of `LD (rr), A` / `LD A, (rr)`,
+    /// applying the HL post-increment or post-decrement.
+    fn indirect_address(&mut self, indirect: Indirect) -> u16 {
+        match indirect {
+            Indirect::BC => self.registers.get_bc(),
+            Indirect::DE => self.registers.get_de(),
+            Indirect::HlIncrement => {
+                let hl = self.registers.get_hl();
+                self.registers.set_hl(hl.wrapping_add(1));
+                hl
+            }
+            Indirect::HlDecrement => {
+                let hl = self.registers.get_hl();
+                self.registers.set_hl(hl.wrapping_sub(1));
+                hl
+            }
+        }
+    }
+
+    /// Pushes `value`, including the internal M-cycle that PUSH, CALL and RST
+    /// spend decrementing SP before the first write.
+    fn push(&mut self, value: u16) {
+        self.internal_cycle();
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(self.registers.sp, (value >> 8) as u8);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(self.registers.sp, value as u8);
+    }
+    fn pop(&mut self) -> u16 {
+        let low = self.read(self.registers.sp) as u16;
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        let high = self.read(self.registers.sp) as u16;
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        (high << 8) | low
+    }
+
+    fn alu_add(&mut self, value: u8, with_carry: bool) {
+        let c = u8::from(with_carry && self.registers.get_flag(CpuFlags::C));
         let a = self.registers.a;
         let r = a.wrapping_add(value).wrapping_add(c);
         self.registers.set_flag(CpuFlags::Z, r == 0);
@@ -36,12 +362,13 @@ impl Cpu {
 
         self.registers.a = r;
     }
-    fn alu_sub(&mut self, value: u8) {
-        let c = if self.registers.get_flag(CpuFlags::C) == true {
-            1
-        } else {
-            0
-        };
+    fn alu_sub(&mut self, value: u8, with_carry: bool) {
+        let r = self.alu_compare(value, with_carry);
+        self.registers.a = r;
+    }
+    /// Subtraction that only updates the flags; shared by SUB/SBC and CP.
+    fn alu_compare(&mut self, value: u8, with_carry: bool) -> u8 {
+        let c = u8::from(with_carry && self.registers.get_flag(CpuFlags::C));
         let a = self.registers.a;
         let r = a.wrapping_sub(value).wrapping_sub(c);
         self.registers.set_flag(CpuFlags::Z, r == 0);
@@ -50,137 +377,324 @@ impl Cpu {
         self.registers.set_flag(CpuFlags::N, true);
         self.registers
             .set_flag(CpuFlags::C, (a as u16) < (value as u16 + c as u16));
-        self.registers.a = r;
+        r
+    }
+    fn alu_and(&mut self, value: u8) {
+        self.registers.a &= value;
+        self.registers.f = 0;
+        self.registers.set_flag(CpuFlags::Z, self.registers.a == 0);
+        self.registers.set_flag(CpuFlags::H, true);
+    }
+    fn alu_xor(&mut self, value: u8) {
+        self.registers.a ^= value;
+        self.registers.f = 0;
+        self.registers.set_flag(CpuFlags::Z, self.registers.a == 0);
+    }
+    fn alu_or(&mut self, value: u8) {
+        self.registers.a |= value;
+        self.registers.f = 0;
+        self.registers.set_flag(CpuFlags::Z, self.registers.a == 0);
+    }
+    fn alu_op(&mut self, operation: AluOp, value: u8) {
+        match operation {
+            AluOp::Add => self.alu_add(value, false),
+            AluOp::Adc => self.alu_add(value, true),
+            AluOp::Sub => self.alu_sub(value, false),
+            AluOp::Sbc => self.alu_sub(value, true),
+            AluOp::And => self.alu_and(value),
+            AluOp::Xor => self.alu_xor(value),
+            AluOp::Or => self.alu_or(value),
+            AluOp::Cp => {
+                self.alu_compare(value, false);
+            }
+        }
+    }
+    fn alu_inc(&mut self, value: u8) -> u8 {
+        let r = value.wrapping_add(1);
+        self.registers.set_flag(CpuFlags::Z, r == 0);
+        self.registers.set_flag(CpuFlags::N, false);
+        self.registers.set_flag(CpuFlags::H, (value & 0xF) == 0xF);
+        r
+    }
+    fn alu_dec(&mut self, value: u8) -> u8 {
+        let r = value.wrapping_sub(1);
+        self.registers.set_flag(CpuFlags::Z, r == 0);
+        self.registers.set_flag(CpuFlags::N, true);
+        self.registers.set_flag(CpuFlags::H, (value & 0xF) == 0);
+        r
+    }
+    fn alu_add_hl(&mut self, value: u16) {
+        let hl = self.registers.get_hl();
+        let r = hl.wrapping_add(value);
+        self.registers.set_flag(CpuFlags::N, false);
+        self.registers
+            .set_flag(CpuFlags::H, (hl & 0x0FFF) + (value & 0x0FFF) > 0x0FFF);
+        self.registers
+            .set_flag(CpuFlags::C, (hl as u32) + (value as u32) > 0xFFFF);
+        self.registers.set_hl(r);
+    }
+    /// SP plus a signed immediate, as used by ADD SP,e8 and LD HL,SP+e8.
+    /// H and C come from the unsigned addition of the low byte.
+    fn alu_add_sp(&mut self, offset: i8) -> u16 {
+        let sp = self.registers.sp;
+        let r = sp.wrapping_add(offset as u16);
+        let offset = offset as u8;
+        self.registers.f = 0;
+        self.registers
+            .set_flag(CpuFlags::H, (sp & 0x0F) + (offset as u16 & 0x0F) > 0x0F);
+        self.registers
+            .set_flag(CpuFlags::C, (sp & 0xFF) + (offset as u16) > 0xFF);
+        r
+    }
+    fn alu_daa(&mut self) {
+        let mut a = self.registers.a;
+        let mut carry = self.registers.get_flag(CpuFlags::C);
+        if !self.registers.get_flag(CpuFlags::N) {
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if self.registers.get_flag(CpuFlags::H) || (a & 0x0F) > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
+        } else {
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+            if self.registers.get_flag(CpuFlags::H) {
+                a = a.wrapping_sub(0x06);
+            }
+        }
+        self.registers.set_flag(CpuFlags::Z, a == 0);
+        self.registers.set_flag(CpuFlags::H, false);
+        self.registers.set_flag(CpuFlags::C, carry);
+        self.registers.a = a;
+    }
+    /// The eight CB-prefixed shifts: RLC, RRC, RL, RR, SLA, SRA, SWAP, SRL.
+    /// Sets Z from the result.
+    fn alu_shift(&mut self, operation: ShiftOp, value: u8) -> u8 {
+        let carry_in = u8::from(self.registers.get_flag(CpuFlags::C));
+        let (r, carry) = match operation {
+            ShiftOp::Rlc => (value.rotate_left(1), value & 0x80 != 0),
+            ShiftOp::Rrc => (value.rotate_right(1), value & 0x01 != 0),
+            ShiftOp::Rl => ((value << 1) | carry_in, value & 0x80 != 0),
+            ShiftOp::Rr => ((value >> 1) | (carry_in << 7), value & 0x01 != 0),
+            ShiftOp::Sla => (value << 1, value & 0x80 != 0),
+            ShiftOp::Sra => ((value >> 1) | (value & 0x80), value & 0x01 != 0),
+            ShiftOp::Swap => (value.rotate_left(4), false),
+            ShiftOp::Srl => (value >> 1, value & 0x01 != 0),
+        };
+        self.registers.f = 0;
+        self.registers.set_flag(CpuFlags::Z, r == 0);
+        self.registers.set_flag(CpuFlags::C, carry);
+        r
+    }
+
+    /// Executes a decoded instruction (whose bytes have already been fetched)
+    /// and returns its duration in M-cycles as given by
+    /// `Instruction::cycles`, fetches included.
+    pub fn execute(&mut self, instruction: Instruction) -> Result<u8, CpuError> {
+        let mut branch_taken = false;
+        match instruction {
+            Instruction::Nop => { /*no operation :3*/ }
+            Instruction::LdR16Imm(rr, value) => self.write_r16(rr, value),
+            Instruction::LdIndirectA(indirect) => {
+                let address = self.indirect_address(indirect);
+                self.write(address, self.registers.a);
+            }
+            Instruction::LdAIndirect(indirect) => {
+                let address = self.indirect_address(indirect);
+                self.registers.a = self.read(address);
+            }
+            Instruction::IncR16(rr) => {
+                /* no flags affected */
+                let value = self.read_r16(rr).wrapping_add(1);
+                self.write_r16(rr, value);
+            }
+            Instruction::DecR16(rr) => {
+                let value = self.read_r16(rr).wrapping_sub(1);
+                self.write_r16(rr, value);
+            }
+            Instruction::IncR8(r) => {
+                let value = self.read_r8(r);
+                let result = self.alu_inc(value);
+                self.write_r8(r, result);
+            }
+            Instruction::DecR8(r) => {
+                let value = self.read_r8(r);
+                let result = self.alu_dec(value);
+                self.write_r8(r, result);
+            }
+            Instruction::LdR8Imm(r, value) => self.write_r8(r, value),
+            Instruction::RotateA(operation) => {
+                /* RLCA/RRCA/RLA/RRA - like their CB counterparts, but Z is always cleared */
+                self.registers.a = self.alu_shift(operation, self.registers.a);
+                self.registers.set_flag(CpuFlags::Z, false);
+            }
+            Instruction::LdAddrSp(address) => {
+                let sp = self.registers.sp;
+                self.write(address, sp as u8);
+                self.write(address.wrapping_add(1), (sp >> 8) as u8);
+            }
+            Instruction::AddHl(rr) => {
+                let value = self.read_r16(rr);
+                self.alu_add_hl(value);
+            }
+            Instruction::Stop => {
+                /* STOP - the second byte of the instruction is skipped.
+                On CGB with KEY1 armed this switches CPU speed instead. */
+                self.registers.increment_pc(1);
+                self.bus.write_data(0xFF04, 0);
+                if self.bus.speed_switch_armed {
+                    self.bus.speed_switch_armed = false;
+                    self.bus.double_speed = !self.bus.double_speed;
+                    self.speed_switch_stall = 2050;
+                } else {
+                    self.stopped = true;
+                }
+            }
+            Instruction::Jr(condition, offset) => {
+                if self.condition(condition) {
+                    self.registers.pc = self.registers.pc.wrapping_add(offset as u16);
+                    branch_taken = true;
+                }
+            }
+            Instruction::Daa => self.alu_daa(),
+            Instruction::Cpl => {
+                self.registers.a = !self.registers.a;
+                self.registers.set_flag(CpuFlags::N, true);
+                self.registers.set_flag(CpuFlags::H, true);
+            }
+            Instruction::Scf => {
+                self.registers.set_flag(CpuFlags::N, false);
+                self.registers.set_flag(CpuFlags::H, false);
+                self.registers.set_flag(CpuFlags::C, true);
+            }
+            Instruction::Ccf => {
+                let carry = self.registers.get_flag(CpuFlags::C);
+                self.registers.set_flag(CpuFlags::N, false);
+                self.registers.set_flag(CpuFlags::H, false);
+                self.registers.set_flag(CpuFlags::C, !carry);
+            }
+            Instruction::Halt => {
+                if !self.ime && self.bus.pending_interrupts() != 0 {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
+            }
+            Instruction::LdR8R8(dst, src) => {
+                let value = self.read_r8(src);
+                self.write_r8(dst, value);
+            }
+            Instruction::Alu(operation, r) => {
+                let value = self.read_r8(r);
+                self.alu_op(operation, value);
+            }
+            Instruction::AluImm(operation, value) => self.alu_op(operation, value),
+            Instruction::Ret(condition) => {
+                if condition.is_some() {
+                    // evaluating the condition costs a cycle of its own
+                    self.internal_cycle();
+                }
+                if self.condition(condition) {
+                    self.registers.pc = self.pop();
+                    branch_taken = true;
+                }
+            }
+            Instruction::Reti => {
+                /* RETI - return and re-enable interrupts, without EI's delay */
+                self.registers.pc = self.pop();
+                self.ime = true;
+            }
+            Instruction::Pop(rr) => {
+                /* POP AF drops the low nibble of F */
+                let value = self.pop();
+                self.write_stack_reg(rr, value);
+            }
+            Instruction::Push(rr) => {
+                let value = self.read_stack_reg(rr);
+                self.push(value);
+            }
+            Instruction::Jp(condition, address) => {
+                if self.condition(condition) {
+                    self.registers.pc = address;
+                    branch_taken = true;
+                }
+            }
+            Instruction::JpHl => self.registers.pc = self.registers.get_hl(),
+            Instruction::Call(condition, address) => {
+                if self.condition(condition) {
+                    self.push(self.registers.pc);
+                    self.registers.pc = address;
+                    branch_taken = true;
+                }
+            }
+            Instruction::Rst(vector) => {
+                self.push(self.registers.pc);
+                self.registers.pc = vector as u16;
+            }
+            Instruction::LdhAddrA(low) => self.write(0xFF00 | low as u16, self.registers.a),
+            Instruction::LdhAAddr(low) => self.registers.a = self.read(0xFF00 | low as u16),
+            Instruction::LdhCA => self.write(0xFF00 | self.registers.c as u16, self.registers.a),
+            Instruction::LdhAC => self.registers.a = self.read(0xFF00 | self.registers.c as u16),
+            Instruction::LdAddrA(address) => self.write(address, self.registers.a),
+            Instruction::LdAAddr(address) => self.registers.a = self.read(address),
+            Instruction::AddSp(offset) => self.registers.sp = self.alu_add_sp(offset),
+            Instruction::LdHlSpOffset(offset) => {
+                /* same flags as ADD SP, e8 */
+                let value = self.alu_add_sp(offset);
+                self.registers.set_hl(value);
+            }
+            Instruction::LdSpHl => self.registers.sp = self.registers.get_hl(),
+            Instruction::Di => {
+                self.ime = false;
+                self.ime_scheduled = false;
+            }
+            Instruction::Ei => {
+                /* takes effect after the next instruction */
+                self.ime_scheduled = true;
+            }
+            Instruction::Shift(operation, r) => {
+                let value = self.read_r8(r);
+                let result = self.alu_shift(operation, value);
+                self.write_r8(r, result);
+            }
+            Instruction::Bit(bit, r) => {
+                /* C flag is left untouched */
+                let value = self.read_r8(r);
+                self.registers
+                    .set_flag(CpuFlags::Z, value & (1 << bit) == 0);
+                self.registers.set_flag(CpuFlags::N, false);
+                self.registers.set_flag(CpuFlags::H, true);
+            }
+            Instruction::Res(bit, r) => {
+                let value = self.read_r8(r);
+                self.write_r8(r, value & !(1 << bit));
+            }
+            Instruction::Set(bit, r) => {
+                let value = self.read_r8(r);
+                self.write_r8(r, value | (1 << bit));
+            }
+            Instruction::Illegal(opcode) => return self.illegal_opcode(opcode),
+        }
+        Ok(instruction.cycles(branch_taken))
     }
 
-    pub fn execute(&mut self, opcode: u8) -> u16 {
-        match opcode {
-            0x00 => {
-                /*no operation :3*/
-                1
-            }
-            0x78 => {
-                /* LD A, B - Load the value of register B into register A */
-                self.registers.a = self.registers.b;
-                1
-            }
-            0x06 => {
-                /* LD B, d8 - Load an immediate 8-bit value into register B */
-                self.registers.b = self.bus.read_data(self.registers.pc.wrapping_add(1));
-                2
-            }
-            0x02 => {
-                /* LD (BC), A - Store the value of register A into the memory address in BC */
-                self.bus
-                    .write_data(self.registers.get_bc(), self.registers.a);
-                1
-            }
-            0x3E => {
-                /*LD A, d8, load 8-bit immediate value into a*/
-                self.registers.a = self.bus.read_data(self.registers.pc.wrapping_add(1));
-                2
-            }
-            0x2E => {
-                /*LD A, d8, load 8-bit immediate value into l*/
-                self.registers.l = self.bus.read_data(self.registers.pc.wrapping_add(1));
-                2
-            }
-            0x1E => {
-                /*LD A, d8, load 8-bit immediate value into e*/
-                self.registers.e = self.bus.read_data(self.registers.pc.wrapping_add(1));
-                2
-            }
-            0x0E => {
-                /*LD A, d8, load 8-bit immediate value into c*/
-                self.registers.c = self.bus.read_data(self.registers.pc.wrapping_add(1));
-                2
-            }
-            0x80 => {
-                /*ADD A, B, Add contents of register B to contents of register A, store result in A*/
-                self.alu_add(self.registers.b);
-                1
-            }
-            0x81 => {
-                /*ADD A, C, Add contents of register B to contents of register A, store result in A*/
-                self.alu_add(self.registers.c);
-                1
-            }
-            0x82 => {
-                /*ADD A, D, Add contents of register B to contents of register A, store result in A*/
-                self.alu_add(self.registers.d);
-                1
-            }
-            0x83 => {
-                /*ADD A, E, Add contents of register B to contents of register A, store result in A*/
-                self.alu_add(self.registers.e);
-                1
-            }
-            0x84 => {
-                /* ADD A, H */
-                self.alu_add(self.registers.h);
-                1
-            }
-            0x85 => {
-                /* ADD A, L */
-                self.alu_add(self.registers.l);
-                1
-            }
-            0x86 => {
-                /* ADD A, (HL) */
-                let value = self.bus.read_data(self.registers.get_hl());
-                self.alu_add(value);
-                1
-            }
-            0x87 => {
-                /* ADD A, A */
-                self.alu_add(self.registers.a);
-                1
-            }
-            0x90 => {
-                /*SUB B*/
-                self.alu_sub(self.registers.b);
-                1
-            }
-            0x91 => {
-                // SUB C
-                self.alu_sub(self.registers.c);
-                1
-            }
-            0x92 => {
-                // SUB D
-                self.alu_sub(self.registers.d);
-                1
-            }
-            0x93 => {
-                // SUB E
-                self.alu_sub(self.registers.e);
-                1
-            }
-            0x94 => {
-                // SUB H
-                self.alu_sub(self.registers.h);
-                1
-            }
-            0x95 => {
-                // SUB L
-                self.alu_sub(self.registers.l);
-                1
-            }
-            0x96 => {
-                // SUB (HL)
-                let value = self.bus.read_data(self.registers.get_hl());
-                self.alu_sub(value);
-                2
-            }
-            0x97 => {
-                // SUB A
-                self.alu_sub(self.registers.a);
-                1
-            }
-            _ => {
-                panic!(
-                    "oh there's panic on the streets of london, panic on the streets of burningham"
-                );
+    /// Handles the 11 unused opcodes according to `illegal_opcode_policy`.
+    fn illegal_opcode(&mut self, opcode: u8) -> Result<u8, CpuError> {
+        match self.illegal_opcode_policy {
+            IllegalOpcodePolicy::Lockup => {
+                self.locked_up = true;
+                Ok(1)
+            }
+            IllegalOpcodePolicy::Error => {
+                let pc = self.registers.pc.wrapping_sub(1);
+                Err(CpuError::IllegalOpcode {
+                    opcode,
+                    pc,
+                    bank: self.bus.rom_bank(pc),
+                })
             }
         }
     }
@@ -188,122 +702,172 @@ impl Cpu {
 #[cfg(test)]
 mod cpu_tests {
     use super::*;
+    use crate::boot::DMG_BOOT_ROM_SIZE;
+    use crate::cartridge::test_rom;
+    use crate::joypad::Button;
+
+    /// A DMG CPU with a blank 32 KiB cartridge inserted; tests `poke` their
+    /// code into its ROM.
+    fn test_cpu() -> Cpu {
+        test_cpu_with_model(Model::Dmg)
+    }
+    fn test_cpu_with_model(model: Model) -> Cpu {
+        let mut cpu = Cpu::with_model(model);
+        cpu.bus
+            .load_cartridge(Cartridge::from_bytes(test_rom(0x00, 0x00, 0x00)).unwrap());
+        for address in [0x0100, 0x0101, 0x0102, 0x0103, 0x0150, 0x0151] {
+            cpu.bus.poke(address, 0x00);
+        }
+        cpu
+    }
+
+    #[test]
+    fn test_boot_rom_hands_over_to_cartridge() {
+        let mut boot_rom = vec![0x00; DMG_BOOT_ROM_SIZE];
+        // ld a, $01; ldh [$50], a
+        boot_rom[..4].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+        let cartridge = Cartridge::from_bytes(test_rom(0x00, 0x00, 0x00)).unwrap();
+        let mut cpu = Cpu::power_on(
+            Some(Model::Dmg),
+            cartridge,
+            Some(BootRom::new(boot_rom).unwrap()),
+        );
+        assert_eq!((cpu.registers.pc, cpu.registers.a), (0x0000, 0x00));
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.bus.boot_rom.is_none());
+        assert_eq!(cpu.registers.pc, 0x0004);
+    }
+    #[test]
+    fn test_power_on_without_boot_rom() {
+        let cartridge = Cartridge::from_bytes(test_rom(0x00, 0x00, 0x00)).unwrap();
+        let cpu = Cpu::power_on(Some(Model::Cgb), cartridge, None);
+        assert_eq!((cpu.registers.pc, cpu.registers.a), (0x0100, 0x11));
+        assert_eq!(cpu.bus.read_data(0xFF04), 0x26);
+        assert_eq!(cpu.bus.read_data(0xFF40), 0x91);
 
+        // a DMG game picks the DMG
+        let cartridge = Cartridge::from_bytes(test_rom(0x00, 0x00, 0x00)).unwrap();
+        let cpu = Cpu::power_on(None, cartridge, None);
+        assert_eq!(cpu.bus.model(), Model::Dmg);
+        assert_eq!(cpu.registers.a, 0x01);
+    }
     #[test]
     fn test_nop_instruction() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         let pc_before = cpu.registers.pc;
-        cpu.bus.write_data(pc_before, 0x00); // NOP
-        cpu.step();
+        cpu.bus.poke(pc_before, 0x00); // NOP
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.pc, pc_before + 1);
     }
     #[test]
     fn test_ld_a_to_b_instruction() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.b = 0x42;
         cpu.registers.a = 0x51;
-        cpu.bus.write_data(cpu.registers.pc, 0x78);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x78);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x42)
     }
     #[test]
     fn test_ld_value_into_b() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.b = 0x42;
-        cpu.bus.write_data(cpu.registers.pc, 0x06);
-        cpu.bus.write_data(cpu.registers.pc + 1, 0xF);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x06);
+        cpu.bus.poke(cpu.registers.pc + 1, 0xF);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.b, 0xF)
     }
     #[test]
     fn test_ld_a_into_address_space_bc() {
-        let mut cpu = Cpu::new();
-        cpu.registers.set_bc(0xFF);
-        cpu.bus.write_data(0xFF, 0x1);
-        cpu.bus.write_data(cpu.registers.pc, 0x02);
-        cpu.step();
+        let mut cpu = test_cpu();
+        cpu.registers.set_bc(0xC0FF);
+        cpu.registers.a = 0x42;
+        cpu.bus.poke(0xC0FF, 0x1);
+        cpu.bus.poke(cpu.registers.pc, 0x02);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, cpu.bus.read_data(cpu.registers.get_bc()))
     }
     #[test]
     fn test_add_b_to_a() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x01;
         cpu.registers.b = 0x42;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x80);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x80);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x01 + 0x42);
         assert_eq!(cpu.registers.f, 0x0);
     }
     #[test]
     fn test_add_b_to_a_hc_flag() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0xF;
         cpu.registers.b = 0xF;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x80);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x80);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0xF + 0xF);
         assert_eq!(cpu.registers.f, CpuFlags::H as u8);
     }
     #[test]
     fn test_add_b_to_a_c_flag() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0xFF;
         cpu.registers.b = 0xFF;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x80);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x80);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0xFE);
-        assert_eq!(cpu.registers.get_flag(CpuFlags::C), true);
-        assert_eq!(cpu.registers.get_flag(CpuFlags::H), true);
+        assert!(cpu.registers.get_flag(CpuFlags::C));
+        assert!(cpu.registers.get_flag(CpuFlags::H));
     } // ADD A, C
     #[test]
     fn test_add_c_to_a() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x10;
         cpu.registers.c = 0x20;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x81);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x81);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x30);
         assert_eq!(cpu.registers.f, 0x0);
     }
 
     #[test]
     fn test_add_c_to_a_hc_flag() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0xF;
         cpu.registers.c = 0x1;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x81);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x81);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x10);
         assert_eq!(cpu.registers.f, 0b00100000);
     }
 
     #[test]
     fn test_add_c_to_a_c_flag() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0xFF;
         cpu.registers.c = 0x01;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x81);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x81);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x00);
-        assert_eq!(cpu.registers.get_flag(CpuFlags::C), true);
-        assert_eq!(cpu.registers.get_flag(CpuFlags::H), true);
+        assert!(cpu.registers.get_flag(CpuFlags::C));
+        assert!(cpu.registers.get_flag(CpuFlags::H));
     }
 
     // ADD A, D
     #[test]
     fn test_add_d_to_a() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x10;
         cpu.registers.d = 0x20;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x82);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x82);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x30);
         assert_eq!(cpu.registers.f, 0x0);
     }
@@ -311,12 +875,12 @@ mod cpu_tests {
     // ADD A, E
     #[test]
     fn test_add_e_to_a() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x01;
         cpu.registers.e = 0x02;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x83);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x83);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x03);
         assert_eq!(cpu.registers.f, 0x0);
     }
@@ -324,12 +888,12 @@ mod cpu_tests {
     // ADD A, H
     #[test]
     fn test_add_h_to_a() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x05;
         cpu.registers.h = 0x05;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x84);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x84);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x0A);
         assert_eq!(cpu.registers.f, 0x0);
     }
@@ -337,12 +901,12 @@ mod cpu_tests {
     // ADD A, L
     #[test]
     fn test_add_l_to_a() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x02;
         cpu.registers.l = 0x03;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x85);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x85);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x05);
         assert_eq!(cpu.registers.f, 0x0);
     }
@@ -350,13 +914,13 @@ mod cpu_tests {
     // ADD A, (HL)
     #[test]
     fn test_add_hl_to_a() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x01;
         cpu.registers.set_hl(0x1000);
-        cpu.bus.write_data(0x1000, 0x02);
+        cpu.bus.poke(0x1000, 0x02);
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x86);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x86);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x03);
         assert_eq!(cpu.registers.f, 0x0);
     }
@@ -364,128 +928,128 @@ mod cpu_tests {
     // ADD A, A
     #[test]
     fn test_add_a_to_a() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x03;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x87);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x87);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x06);
         assert_eq!(cpu.registers.f, 0x0);
     }
     #[test]
     fn test_sub_no_carry() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x0A;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x97);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x97);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x00);
         assert_eq!(cpu.registers.f, 0xC0); // Z and N flags set, H and C cleared
     }
 
     #[test]
     fn test_sub_b_a() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0xF;
         cpu.registers.b = 0x5;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x90);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x90);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0xA);
         assert_eq!(cpu.registers.f, 0x40); // Z and N flags
     }
     #[test]
     fn test_sub_c() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x8;
         cpu.registers.c = 0x3;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x91);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x91);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x5);
         assert_eq!(cpu.registers.f & 0x40, 0x40); // N set
     }
 
     #[test]
     fn test_sub_d() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x7;
         cpu.registers.d = 0x2;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x92);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x92);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x5);
         assert_eq!(cpu.registers.f & 0x40, 0x40); // N set
     }
 
     #[test]
     fn test_sub_e() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0xA;
         cpu.registers.e = 0xA;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x93);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x93);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x00);
         assert_eq!(cpu.registers.f & 0xC0, 0xC0); // Z and N set
     }
 
     #[test]
     fn test_sub_h() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0xF;
         cpu.registers.h = 0x1;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x94);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x94);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0xE);
         assert_eq!(cpu.registers.f & 0x40, 0x40); // N set
     }
 
     #[test]
     fn test_sub_l() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x3;
         cpu.registers.l = 0x3;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x95);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x95);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x00);
         assert_eq!(cpu.registers.f & 0xC0, 0xC0); // Z and N set
     }
 
     #[test]
     fn test_sub_hl() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x5;
         cpu.registers.h = 0x10;
         cpu.registers.l = 0x00;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.get_hl(), 0x2); // memory at HL
-        cpu.bus.write_data(cpu.registers.pc, 0x96);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.get_hl(), 0x2); // memory at HL
+        cpu.bus.poke(cpu.registers.pc, 0x96);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x3);
         assert_eq!(cpu.registers.f & 0x40, 0x40); // N set
     }
 
     #[test]
     fn test_sub_a() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x7;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x97);
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x97);
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x00);
         assert_eq!(cpu.registers.f & 0xC0, 0xC0); // Z and N set
     }
     #[test]
     fn test_sub_b_carry() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x05;
         cpu.registers.b = 0x0A; // A < B -> carry set
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x90); // SUB B
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x90); // SUB B
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0xFB); // 5 - 10 = -5 = 0xFB
         assert_eq!(cpu.registers.f & 0x10, 0x10); // C flag set
         assert_eq!(cpu.registers.f & 0x40, 0x40); // N flag set
@@ -493,12 +1057,12 @@ mod cpu_tests {
 
     #[test]
     fn test_sub_b_half_carry() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x10;
         cpu.registers.b = 0x01; // borrow from bit 4
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x90); // SUB B
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x90); // SUB B
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x0F);
         assert_eq!(cpu.registers.f & 0x20, 0x20); // H flag set
         assert_eq!(cpu.registers.f & 0x40, 0x40); // N flag set
@@ -507,11 +1071,11 @@ mod cpu_tests {
 
     #[test]
     fn test_sub_a_no_carry_no_half() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x15;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.pc, 0x97); // SUB A
-        cpu.step();
+        cpu.bus.poke(cpu.registers.pc, 0x97); // SUB A
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0x00);
         assert_eq!(cpu.registers.f & 0xC0, 0xC0); // Z and N set
         assert_eq!(cpu.registers.f & 0x30, 0x00); // H and C cleared
@@ -519,18 +1083,840 @@ mod cpu_tests {
 
     #[test]
     fn test_sub_hl_carry_half() {
-        let mut cpu = Cpu::new();
+        let mut cpu = test_cpu();
         cpu.registers.a = 0x10;
         cpu.registers.h = 0x00;
         cpu.registers.l = 0x01;
         cpu.registers.f = 0x00;
-        cpu.bus.write_data(cpu.registers.get_hl(), 0x11); // value > A
-        cpu.bus.write_data(cpu.registers.pc, 0x96); // SUB (HL)
-        cpu.step();
+        cpu.bus.poke(cpu.registers.get_hl(), 0x11); // value > A
+        cpu.bus.poke(cpu.registers.pc, 0x96); // SUB (HL)
+        cpu.step().unwrap();
         assert_eq!(cpu.registers.a, 0xFF); // 0x10 - 0x11 = -1 = 0xFF
         assert_eq!(cpu.registers.f & 0x10, 0x10); // C set
         assert_eq!(cpu.registers.f & 0x20, 0x20); // H set
         assert_eq!(cpu.registers.f & 0x40, 0x40); // N set
         assert_eq!(cpu.registers.f & 0x80, 0x00); // Z cleared
     }
+
+    #[test]
+    fn test_adc_uses_carry() {
+        let mut cpu = test_cpu();
+        cpu.registers.a = 0x0F;
+        cpu.registers.b = 0x00;
+        cpu.registers.f = CpuFlags::C as u8;
+        cpu.bus.poke(cpu.registers.pc, 0x88); // ADC A, B
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x10);
+        assert_eq!(cpu.registers.f, CpuFlags::H as u8);
+    }
+
+    #[test]
+    fn test_add_ignores_carry() {
+        let mut cpu = test_cpu();
+        cpu.registers.a = 0x01;
+        cpu.registers.b = 0x01;
+        cpu.registers.f = CpuFlags::C as u8;
+        cpu.bus.poke(cpu.registers.pc, 0x80); // ADD A, B
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x02);
+        assert_eq!(cpu.registers.f, 0x00);
+    }
+
+    #[test]
+    fn test_sbc_uses_carry() {
+        let mut cpu = test_cpu();
+        cpu.registers.a = 0x10;
+        cpu.registers.c = 0x0F;
+        cpu.registers.f = CpuFlags::C as u8;
+        cpu.bus.poke(cpu.registers.pc, 0x99); // SBC A, C
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x00);
+        assert_eq!(cpu.registers.f, 0xE0); // Z, N and H set
+    }
+
+    #[test]
+    fn test_and_or_xor_cp() {
+        let mut cpu = test_cpu();
+        let pc = cpu.registers.pc;
+        cpu.registers.a = 0xF0;
+        cpu.registers.b = 0x3C;
+        cpu.bus.poke(pc, 0xA0); // AND B
+        cpu.bus.poke(pc + 1, 0xB0); // OR B
+        cpu.bus.poke(pc + 2, 0xA8); // XOR B
+        cpu.bus.poke(pc + 3, 0xFE); // CP d8
+        cpu.bus.poke(pc + 4, 0xF0);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x30);
+        assert_eq!(cpu.registers.f, CpuFlags::H as u8);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x3C);
+        assert_eq!(cpu.registers.f, 0x00);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x00);
+        assert_eq!(cpu.registers.f, CpuFlags::Z as u8);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x00); // CP leaves A alone
+        assert_eq!(cpu.registers.f, 0x50); // N and C set
+        assert_eq!(cpu.registers.pc, pc + 5);
+    }
+
+    #[test]
+    fn test_inc_dec_keep_carry() {
+        let mut cpu = test_cpu();
+        cpu.registers.d = 0xFF;
+        cpu.registers.e = 0x10;
+        cpu.registers.f = CpuFlags::C as u8;
+        cpu.bus.poke(cpu.registers.pc, 0x14); // INC D
+        cpu.bus.poke(cpu.registers.pc + 1, 0x1D); // DEC E
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.d, 0x00);
+        assert_eq!(cpu.registers.f, 0xB0); // Z, H and C
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.e, 0x0F);
+        assert_eq!(cpu.registers.f, 0x70); // N, H and C
+    }
+
+    #[test]
+    fn test_ld_rr_d16_and_inc_rr() {
+        let mut cpu = test_cpu();
+        let pc = cpu.registers.pc;
+        cpu.bus.poke(pc, 0x11); // LD DE, 0x12FF
+        cpu.bus.poke(pc + 1, 0xFF);
+        cpu.bus.poke(pc + 2, 0x12);
+        cpu.bus.poke(pc + 3, 0x13); // INC DE
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.get_de(), 0x12FF);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.get_de(), 0x1300);
+    }
+
+    #[test]
+    fn test_ld_hl_increment_and_decrement() {
+        let mut cpu = test_cpu();
+        cpu.registers.a = 0x77;
+        cpu.registers.set_hl(0xC000);
+        cpu.bus.poke(cpu.registers.pc, 0x22); // LD (HL+), A
+        cpu.bus.poke(cpu.registers.pc + 1, 0x3A); // LD A, (HL-)
+        cpu.step().unwrap();
+        assert_eq!(cpu.bus.read_data(0xC000), 0x77);
+        assert_eq!(cpu.registers.get_hl(), 0xC001);
+        cpu.bus.poke(0xC001, 0x12);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x12);
+        assert_eq!(cpu.registers.get_hl(), 0xC000);
+    }
+
+    #[test]
+    fn test_ld_r_r_to_and_from_hl() {
+        let mut cpu = test_cpu();
+        cpu.registers.set_hl(0xC100);
+        cpu.registers.e = 0x5A;
+        cpu.bus.poke(cpu.registers.pc, 0x73); // LD (HL), E
+        cpu.bus.poke(cpu.registers.pc + 1, 0x4E); // LD C, (HL)
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.bus.read_data(0xC100), 0x5A);
+        assert_eq!(cpu.registers.c, 0x5A);
+    }
+
+    #[test]
+    fn test_jp_and_jr() {
+        let mut cpu = test_cpu();
+        cpu.bus.poke(0x0100, 0xC3); // JP 0x0200
+        cpu.bus.poke(0x0101, 0x00);
+        cpu.bus.poke(0x0102, 0x02);
+        cpu.bus.poke(0x0200, 0x18); // JR -2
+        cpu.bus.poke(0x0201, 0xFE);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0200);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0200);
+    }
+
+    #[test]
+    fn test_conditional_jumps() {
+        let mut cpu = test_cpu();
+        cpu.registers.f = 0x00;
+        cpu.bus.poke(0x0100, 0x28); // JR Z, +4 (not taken)
+        cpu.bus.poke(0x0101, 0x04);
+        cpu.bus.poke(0x0102, 0xD2); // JP NC, 0x0300 (taken)
+        cpu.bus.poke(0x0103, 0x00);
+        cpu.bus.poke(0x0104, 0x03);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0102);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0300);
+    }
+
+    #[test]
+    fn test_call_and_ret() {
+        let mut cpu = test_cpu();
+        cpu.registers.sp = 0xD000;
+        cpu.bus.poke(0x0100, 0xCD); // CALL 0x0150
+        cpu.bus.poke(0x0101, 0x50);
+        cpu.bus.poke(0x0102, 0x01);
+        cpu.bus.poke(0x0150, 0xC9); // RET
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0150);
+        assert_eq!(cpu.registers.sp, 0xCFFE);
+        assert_eq!(cpu.bus.read_data(0xCFFF), 0x01);
+        assert_eq!(cpu.bus.read_data(0xCFFE), 0x03);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0103);
+        assert_eq!(cpu.registers.sp, 0xD000);
+    }
+
+    #[test]
+    fn test_rst() {
+        let mut cpu = test_cpu();
+        cpu.registers.sp = 0xD000;
+        cpu.bus.poke(cpu.registers.pc, 0xEF); // RST 0x28
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0028);
+        assert_eq!(cpu.registers.sp, 0xCFFE);
+        assert_eq!(cpu.bus.read_data(0xCFFE), 0x01);
+    }
+
+    #[test]
+    fn test_push_pop_af_masks_low_nibble() {
+        let mut cpu = test_cpu();
+        cpu.registers.sp = 0xD000;
+        cpu.registers.set_bc(0x12FF);
+        cpu.bus.poke(cpu.registers.pc, 0xC5); // PUSH BC
+        cpu.bus.poke(cpu.registers.pc + 1, 0xF1); // POP AF
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x12);
+        assert_eq!(cpu.registers.f, 0xF0);
+        assert_eq!(cpu.registers.sp, 0xD000);
+    }
+
+    #[test]
+    fn test_ldh_and_ld_c() {
+        let mut cpu = test_cpu();
+        cpu.registers.a = 0x42;
+        cpu.registers.c = 0x81;
+        cpu.bus.poke(cpu.registers.pc, 0xE0); // LDH (0x80), A
+        cpu.bus.poke(cpu.registers.pc + 1, 0x80);
+        cpu.bus.poke(cpu.registers.pc + 2, 0xE2); // LD (C), A
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.bus.read_data(0xFF80), 0x42);
+        assert_eq!(cpu.bus.read_data(0xFF81), 0x42);
+    }
+
+    #[test]
+    fn test_add_hl_rr() {
+        let mut cpu = test_cpu();
+        cpu.registers.set_hl(0x0FFF);
+        cpu.registers.set_bc(0x0001);
+        cpu.registers.f = CpuFlags::Z as u8;
+        cpu.bus.poke(cpu.registers.pc, 0x09); // ADD HL, BC
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.get_hl(), 0x1000);
+        assert_eq!(cpu.registers.f, 0xA0); // Z untouched, H set
+    }
+
+    #[test]
+    fn test_add_sp_and_ld_hl_sp_offset() {
+        let mut cpu = test_cpu();
+        cpu.registers.sp = 0xFFF8;
+        cpu.bus.poke(cpu.registers.pc, 0xF8); // LD HL, SP-1
+        cpu.bus.poke(cpu.registers.pc + 1, 0xFF);
+        cpu.bus.poke(cpu.registers.pc + 2, 0xE8); // ADD SP, 8
+        cpu.bus.poke(cpu.registers.pc + 3, 0x08);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.get_hl(), 0xFFF7);
+        assert_eq!(cpu.registers.f, 0x30); // H and C from the low byte
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.sp, 0x0000);
+        assert_eq!(cpu.registers.f, 0x30);
+    }
+
+    #[test]
+    fn test_ld_a16_sp() {
+        let mut cpu = test_cpu();
+        cpu.registers.sp = 0xBEEF;
+        cpu.bus.poke(cpu.registers.pc, 0x08);
+        cpu.bus.poke(cpu.registers.pc + 1, 0x00);
+        cpu.bus.poke(cpu.registers.pc + 2, 0xC0);
+        cpu.step().unwrap();
+        assert_eq!(cpu.bus.read_data(0xC000), 0xEF);
+        assert_eq!(cpu.bus.read_data(0xC001), 0xBE);
+    }
+
+    #[test]
+    fn test_daa_after_add_and_sub() {
+        let mut cpu = test_cpu();
+        cpu.registers.a = 0x45;
+        cpu.registers.b = 0x38;
+        cpu.registers.f = 0x00;
+        cpu.bus.poke(cpu.registers.pc, 0x80); // ADD A, B
+        cpu.bus.poke(cpu.registers.pc + 1, 0x27); // DAA
+        cpu.bus.poke(cpu.registers.pc + 2, 0x90); // SUB B
+        cpu.bus.poke(cpu.registers.pc + 3, 0x27); // DAA
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x83);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x45);
+    }
+
+    #[test]
+    fn test_cpl_scf_ccf() {
+        let mut cpu = test_cpu();
+        cpu.registers.a = 0x35;
+        cpu.registers.f = 0x00;
+        cpu.bus.poke(cpu.registers.pc, 0x2F); // CPL
+        cpu.bus.poke(cpu.registers.pc + 1, 0x37); // SCF
+        cpu.bus.poke(cpu.registers.pc + 2, 0x3F); // CCF
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0xCA);
+        assert_eq!(cpu.registers.f, 0x60);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.f, CpuFlags::C as u8);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.f, 0x00);
+    }
+
+    #[test]
+    fn test_di_ei_and_halt() {
+        let mut cpu = test_cpu();
+        let pc = cpu.registers.pc;
+        cpu.bus.poke(pc, 0xFB); // EI
+        cpu.bus.poke(pc + 1, 0x00); // NOP
+        cpu.bus.poke(pc + 2, 0xF3); // DI
+        cpu.bus.poke(pc + 3, 0x76); // HALT
+        cpu.step().unwrap();
+        assert!(!cpu.ime);
+        cpu.step().unwrap();
+        assert!(cpu.ime);
+        cpu.step().unwrap();
+        assert!(!cpu.ime);
+        cpu.step().unwrap();
+        assert!(cpu.halted);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, pc + 4);
+    }
+
+    #[test]
+    fn test_rlca_clears_zero_flag() {
+        let mut cpu = test_cpu();
+        cpu.registers.a = 0x00;
+        cpu.registers.f = CpuFlags::Z as u8;
+        cpu.bus.poke(cpu.registers.pc, 0x07); // RLCA
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x00);
+        assert_eq!(cpu.registers.f, 0x00);
+    }
+
+    #[test]
+    fn test_rla_and_rra_through_carry() {
+        let mut cpu = test_cpu();
+        cpu.registers.a = 0x80;
+        cpu.registers.f = 0x00;
+        cpu.bus.poke(cpu.registers.pc, 0x17); // RLA
+        cpu.bus.poke(cpu.registers.pc + 1, 0x1F); // RRA
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x00);
+        assert_eq!(cpu.registers.f, CpuFlags::C as u8);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x80);
+        assert_eq!(cpu.registers.f, 0x00);
+    }
+
+    #[test]
+    fn test_cb_rlc_sets_zero_flag() {
+        let mut cpu = test_cpu();
+        cpu.registers.b = 0x00;
+        cpu.registers.f = CpuFlags::C as u8;
+        cpu.bus.poke(cpu.registers.pc, 0xCB);
+        cpu.bus.poke(cpu.registers.pc + 1, 0x00); // RLC B
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.b, 0x00);
+        assert_eq!(cpu.registers.f, CpuFlags::Z as u8);
+        assert_eq!(cpu.registers.pc, 0x0102);
+    }
+
+    #[test]
+    fn test_cb_shifts() {
+        let mut cpu = test_cpu();
+        cpu.registers.c = 0x81;
+        cpu.registers.d = 0x81;
+        cpu.registers.e = 0x81;
+        cpu.registers.a = 0xF1;
+        cpu.registers.f = 0x00;
+        let pc = cpu.registers.pc;
+        cpu.bus.poke(pc, 0xCB);
+        cpu.bus.poke(pc + 1, 0x21); // SLA C
+        cpu.bus.poke(pc + 2, 0xCB);
+        cpu.bus.poke(pc + 3, 0x2A); // SRA D
+        cpu.bus.poke(pc + 4, 0xCB);
+        cpu.bus.poke(pc + 5, 0x3B); // SRL E
+        cpu.bus.poke(pc + 6, 0xCB);
+        cpu.bus.poke(pc + 7, 0x37); // SWAP A
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.c, 0x02);
+        assert!(cpu.registers.get_flag(CpuFlags::C));
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.d, 0xC0);
+        assert!(cpu.registers.get_flag(CpuFlags::C));
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.e, 0x40);
+        assert!(cpu.registers.get_flag(CpuFlags::C));
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x1F);
+        assert_eq!(cpu.registers.f, 0x00);
+    }
+
+    #[test]
+    fn test_cb_rl_rr_through_carry() {
+        let mut cpu = test_cpu();
+        cpu.registers.h = 0x01;
+        cpu.registers.f = CpuFlags::C as u8;
+        cpu.bus.poke(cpu.registers.pc, 0xCB);
+        cpu.bus.poke(cpu.registers.pc + 1, 0x1C); // RR H
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.h, 0x80);
+        assert!(cpu.registers.get_flag(CpuFlags::C));
+    }
+
+    #[test]
+    fn test_cb_bit_keeps_carry() {
+        let mut cpu = test_cpu();
+        cpu.registers.l = 0x7F;
+        cpu.registers.f = CpuFlags::C as u8;
+        cpu.bus.poke(cpu.registers.pc, 0xCB);
+        cpu.bus.poke(cpu.registers.pc + 1, 0x7D); // BIT 7, L
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.f, 0xB0); // Z, H and C
+    }
+
+    #[test]
+    fn test_cb_res_set_on_hl() {
+        let mut cpu = test_cpu();
+        cpu.registers.set_hl(0xC000);
+        cpu.bus.poke(0xC000, 0xFF);
+        let pc = cpu.registers.pc;
+        cpu.bus.poke(pc, 0xCB);
+        cpu.bus.poke(pc + 1, 0x86); // RES 0, (HL)
+        cpu.bus.poke(pc + 2, 0xCB);
+        cpu.bus.poke(pc + 3, 0xC6); // SET 0, (HL)
+        cpu.step().unwrap();
+        assert_eq!(cpu.bus.read_data(0xC000), 0xFE);
+        cpu.step().unwrap();
+        assert_eq!(cpu.bus.read_data(0xC000), 0xFF);
+    }
+
+    #[test]
+    fn test_cb_cycle_counts() {
+        let mut cpu = test_cpu();
+        cpu.registers.set_hl(0xC000);
+        assert_eq!(cpu.execute(Instruction::decode_slice(&[0xCB, 0x00])), Ok(2)); // RLC B
+        assert_eq!(cpu.execute(Instruction::decode_slice(&[0xCB, 0x46])), Ok(3)); // BIT 0, (HL)
+        assert_eq!(cpu.execute(Instruction::decode_slice(&[0xCB, 0x86])), Ok(4)); // RES 0, (HL)
+        assert_eq!(cpu.execute(Instruction::decode_slice(&[0xCB, 0x36])), Ok(4)); // SWAP (HL)
+        assert_eq!(cpu.execute(Instruction::decode_slice(&[0xCB, 0xFF])), Ok(2)); // SET 7, A
+    }
+
+    #[test]
+    fn test_step_returns_t_cycles() {
+        let mut cpu = test_cpu();
+        let pc = cpu.registers.pc;
+        cpu.registers.set_hl(0xC000);
+        cpu.bus.poke(pc, 0x00); // NOP
+        cpu.bus.poke(pc + 1, 0x36); // LD (HL), d8
+        cpu.bus.poke(pc + 2, 0x12);
+        cpu.bus.poke(pc + 3, 0x34); // INC (HL)
+        cpu.bus.poke(pc + 4, 0xCB); // BIT 0, (HL)
+        cpu.bus.poke(pc + 5, 0x46);
+        cpu.bus.poke(pc + 6, 0x08); // LD (a16), SP
+        cpu.bus.poke(pc + 7, 0x00);
+        cpu.bus.poke(pc + 8, 0xC1);
+        assert_eq!(cpu.step(), Ok(4));
+        assert_eq!(cpu.step(), Ok(12));
+        assert_eq!(cpu.step(), Ok(12));
+        assert_eq!(cpu.step(), Ok(12));
+        assert_eq!(cpu.step(), Ok(20));
+    }
+
+    #[test]
+    fn test_conditional_branch_cycles() {
+        let mut cpu = test_cpu();
+        cpu.registers.sp = 0xD000;
+        cpu.registers.f = 0x00;
+        cpu.bus.poke(0x0100, 0x20); // JR NZ, +0 (taken)
+        cpu.bus.poke(0x0101, 0x00);
+        cpu.bus.poke(0x0102, 0xCC); // CALL Z, a16 (not taken)
+        cpu.bus.poke(0x0103, 0x00);
+        cpu.bus.poke(0x0104, 0x02);
+        cpu.bus.poke(0x0105, 0xC4); // CALL NZ, 0x0200 (taken)
+        cpu.bus.poke(0x0106, 0x00);
+        cpu.bus.poke(0x0107, 0x02);
+        cpu.bus.poke(0x0200, 0xD8); // RET C (not taken)
+        cpu.bus.poke(0x0201, 0xD0); // RET NC (taken)
+        cpu.bus.poke(0x0108, 0xCA); // JP Z, a16 (not taken)
+        assert_eq!(cpu.step(), Ok(12));
+        assert_eq!(cpu.step(), Ok(12));
+        assert_eq!(cpu.step(), Ok(24));
+        assert_eq!(cpu.step(), Ok(8));
+        assert_eq!(cpu.step(), Ok(20));
+        assert_eq!(cpu.registers.pc, 0x0108);
+        assert_eq!(cpu.step(), Ok(12));
+    }
+
+    #[test]
+    fn test_step_drives_the_timer() {
+        let mut cpu = test_cpu();
+        cpu.bus.poke(0xFF07, 0x05); // 16 T-cycles per TIMA increment
+        for offset in 0..4 {
+            cpu.bus.poke(cpu.registers.pc + offset, 0xC5); // PUSH BC
+        }
+        cpu.registers.sp = 0xD000;
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.bus.read_data(0xFF05), 4);
+    }
+
+    #[test]
+    fn test_halted_cpu_still_advances_time() {
+        let mut cpu = test_cpu();
+        cpu.bus.poke(cpu.registers.pc, 0x76); // HALT
+        cpu.step().unwrap();
+        assert_eq!(cpu.step(), Ok(4));
+        assert_eq!(cpu.bus.read_data(0xFF04), 0);
+        for _ in 0..62 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.bus.read_data(0xFF04), 1);
+    }
+
+    #[test]
+    fn test_per_access_timing_reads_on_the_last_cycle() {
+        let program = [0xFA, 0x05, 0xFF]; // LD A, (0xFF05)
+        let mut results = Vec::new();
+        for timing in [BusTiming::PerInstruction, BusTiming::PerAccess] {
+            let mut cpu = test_cpu();
+            cpu.set_bus_timing(timing);
+            for (offset, byte) in program.iter().enumerate() {
+                cpu.bus.poke(cpu.registers.pc + offset as u16, *byte);
+            }
+            cpu.bus.poke(0xFF07, 0x05); // TIMA +1 every 4 M-cycles
+            assert_eq!(cpu.step(), Ok(16));
+            results.push(cpu.registers.a);
+        }
+        // the read is the 4th M-cycle, by which point TIMA has ticked once
+        assert_eq!(results, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_per_access_timing_writes_after_internal_cycle() {
+        let mut cpu = test_cpu();
+        cpu.set_bus_timing(BusTiming::PerAccess);
+        cpu.bus.poke(0xFF07, 0x05);
+        cpu.registers.sp = 0xFF07; // PUSH writes 0xFF06 then 0xFF05
+        cpu.registers.set_bc(0x0000);
+        cpu.bus.poke(cpu.registers.pc, 0xC5); // PUSH BC
+        assert_eq!(cpu.step(), Ok(16));
+        // fetch, internal, write TMA, write TIMA: the TIMA write happens on
+        // the 4th cycle and overrides the increment from that same cycle
+        assert_eq!(cpu.bus.read_data(0xFF05), 0);
+    }
+
+    #[test]
+    fn test_per_access_timing_ticks_same_total() {
+        for timing in [BusTiming::PerInstruction, BusTiming::PerAccess] {
+            let mut cpu = test_cpu();
+            cpu.set_bus_timing(timing);
+            cpu.registers.sp = 0xD000;
+            cpu.registers.f = 0x00;
+            cpu.bus.poke(0x0100, 0xCD); // CALL 0x0200
+            cpu.bus.poke(0x0101, 0x00);
+            cpu.bus.poke(0x0102, 0x02);
+            cpu.bus.poke(0x0200, 0xC0); // RET NZ
+            let mut total = 0;
+            for _ in 0..2 {
+                total += cpu.step().unwrap();
+            }
+            assert_eq!(total, 44);
+            assert_eq!(cpu.bus.timer.read(0xFF04), 0);
+            for _ in 0..9 {
+                cpu.bus.poke(cpu.registers.pc, 0xC5); // PUSH BC
+                cpu.step().unwrap();
+            }
+            // 11 + 36 = 47 M-cycles, 64 per DIV increment
+            assert_eq!(cpu.bus.timer.read(0xFF04), 0);
+            for _ in 0..5 {
+                cpu.bus.poke(cpu.registers.pc, 0xC5);
+                cpu.step().unwrap();
+            }
+            assert_eq!(cpu.bus.timer.read(0xFF04), 1);
+        }
+    }
+
+    #[test]
+    fn test_interrupt_dispatch() {
+        let mut cpu = test_cpu();
+        cpu.ime = true;
+        cpu.registers.sp = 0xD000;
+        cpu.bus.poke(0xFFFF, 0x1F);
+        cpu.bus.request_interrupt(Interrupt::Timer);
+        cpu.bus.request_interrupt(Interrupt::Joypad);
+        assert_eq!(cpu.step(), Ok(20));
+        assert_eq!(cpu.registers.pc, 0x0050);
+        assert_eq!(cpu.registers.sp, 0xCFFE);
+        assert_eq!(cpu.bus.read_data(0xCFFF), 0x01);
+        assert_eq!(cpu.bus.read_data(0xCFFE), 0x00);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.bus.interrupt_flag, Interrupt::Joypad.bit());
+    }
+
+    #[test]
+    fn test_interrupt_needs_ime_and_enable() {
+        let mut cpu = test_cpu();
+        cpu.registers.sp = 0xD000;
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+        cpu.ime = true;
+        cpu.step().unwrap(); // IE = 0: NOP runs
+        assert_eq!(cpu.registers.pc, 0x0101);
+        cpu.ime = false;
+        cpu.bus.poke(0xFFFF, 0x01);
+        cpu.step().unwrap(); // IME = 0: NOP runs
+        assert_eq!(cpu.registers.pc, 0x0102);
+        assert_eq!(cpu.bus.interrupt_flag, 0x01);
+    }
+
+    #[test]
+    fn test_ei_delay() {
+        let mut cpu = test_cpu();
+        cpu.registers.sp = 0xD000;
+        cpu.bus.poke(0xFFFF, 0x01);
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+        cpu.bus.poke(0x0100, 0xFB); // EI
+        cpu.bus.poke(0x0101, 0x00); // NOP
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        // the NOP after EI still runs before the interrupt is taken
+        assert_eq!(cpu.registers.pc, 0x0102);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0040);
+        assert_eq!(cpu.bus.read_data(0xCFFE), 0x02);
+    }
+
+    #[test]
+    fn test_ei_di_never_enables() {
+        let mut cpu = test_cpu();
+        cpu.bus.poke(0xFFFF, 0x01);
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+        cpu.bus.poke(0x0100, 0xFB); // EI
+        cpu.bus.poke(0x0101, 0xF3); // DI
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(!cpu.ime);
+        assert_eq!(cpu.registers.pc, 0x0103);
+    }
+
+    #[test]
+    fn test_reti_enables_immediately() {
+        let mut cpu = test_cpu();
+        cpu.registers.sp = 0xCFFE;
+        cpu.bus.poke(0xCFFE, 0x00);
+        cpu.bus.poke(0xCFFF, 0x02);
+        cpu.bus.poke(0xFFFF, 0x04);
+        cpu.bus.request_interrupt(Interrupt::Timer);
+        cpu.bus.poke(0x0100, 0xD9); // RETI
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0200);
+        assert!(cpu.ime);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0050);
+    }
+
+    #[test]
+    fn test_interrupt_push_cancellation() {
+        let mut cpu = test_cpu();
+        cpu.ime = true;
+        cpu.registers.pc = 0x0200;
+        cpu.registers.sp = 0x0000; // high byte of PC is pushed onto IE
+        cpu.bus.poke(0xFFFF, 0x01);
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+        cpu.step().unwrap();
+        assert_eq!(cpu.bus.read_data(0xFFFF), 0x02);
+        assert_eq!(cpu.registers.pc, 0x0000);
+        assert_eq!(cpu.bus.interrupt_flag, 0x01);
+    }
+
+    #[test]
+    fn test_interrupt_push_retargets_to_newly_enabled_source() {
+        let mut cpu = test_cpu();
+        cpu.ime = true;
+        cpu.registers.pc = 0x0400;
+        cpu.registers.sp = 0x0000;
+        cpu.bus.poke(0xFFFF, 0x05);
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+        cpu.bus.request_interrupt(Interrupt::Timer);
+        cpu.step().unwrap();
+        // IE became 0x04, so the timer interrupt is serviced instead
+        assert_eq!(cpu.registers.pc, 0x0050);
+        assert_eq!(cpu.bus.interrupt_flag, 0x01);
+    }
+
+    #[test]
+    fn test_halt_wakes_without_ime() {
+        let mut cpu = test_cpu();
+        cpu.bus.poke(0xFFFF, 0x04);
+        cpu.bus.poke(0x0100, 0x76); // HALT
+        cpu.bus.poke(0x0101, 0x3C); // INC A
+        cpu.registers.a = 0x00;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.halted);
+        assert_eq!(cpu.registers.pc, 0x0101);
+        cpu.bus.request_interrupt(Interrupt::Timer);
+        cpu.step().unwrap();
+        // woken up, but with IME=0 execution just continues
+        assert!(!cpu.halted);
+        assert_eq!(cpu.registers.a, 0x01);
+        assert_eq!(cpu.bus.interrupt_flag, 0x04);
+    }
+
+    #[test]
+    fn test_halt_with_ime_services_interrupt() {
+        let mut cpu = test_cpu();
+        cpu.ime = true;
+        cpu.registers.sp = 0xD000;
+        cpu.bus.poke(0xFFFF, 0x01);
+        cpu.bus.poke(0x0100, 0x76); // HALT
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.halted);
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0040);
+        assert_eq!(cpu.bus.read_data(0xCFFE), 0x01);
+        assert_eq!(cpu.bus.read_data(0xCFFF), 0x01);
+    }
+
+    #[test]
+    fn test_halt_bug_reads_next_byte_twice() {
+        let mut cpu = test_cpu();
+        cpu.bus.poke(0xFFFF, 0x01);
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+        cpu.registers.a = 0x00;
+        cpu.bus.poke(0x0100, 0x76); // HALT
+        cpu.bus.poke(0x0101, 0x3C); // INC A
+        cpu.bus.poke(0x0102, 0x00); // NOP
+        cpu.step().unwrap();
+        assert!(!cpu.halted);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.a, 0x02);
+        assert_eq!(cpu.registers.pc, 0x0102);
+    }
+
+    #[test]
+    fn test_halt_bug_after_ei_returns_to_halt() {
+        let mut cpu = test_cpu();
+        cpu.registers.sp = 0xD000;
+        cpu.bus.poke(0xFFFF, 0x01);
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+        cpu.bus.poke(0x0100, 0xFB); // EI
+        cpu.bus.poke(0x0101, 0x76); // HALT
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0040);
+        assert_eq!(cpu.bus.read_data(0xCFFE), 0x01);
+        assert_eq!(cpu.bus.read_data(0xCFFF), 0x01);
+    }
+
+    #[test]
+    fn test_stop_waits_for_joypad() {
+        let mut cpu = test_cpu();
+        cpu.bus.poke(0xFF00, 0x10); // select action buttons
+        cpu.bus.poke(0x0100, 0x10); // STOP
+        cpu.bus.poke(0x0101, 0x00);
+        cpu.bus.poke(0x0102, 0x3C); // INC A
+        cpu.registers.a = 0x00;
+        for _ in 0..100 {
+            cpu.step().unwrap();
+        }
+        assert!(cpu.stopped);
+        assert_eq!(cpu.registers.pc, 0x0102);
+        assert_eq!(cpu.bus.read_data(0xFF04), 0x00);
+        cpu.bus.press_button(Button::A);
+        cpu.step().unwrap();
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.registers.a, 0x01);
+    }
+
+    #[test]
+    fn test_stop_with_key1_armed_switches_speed() {
+        let mut cpu = test_cpu_with_model(Model::Cgb);
+        cpu.bus.poke(0xFF4D, 0x01);
+        cpu.bus.poke(0x0100, 0x10); // STOP
+        cpu.bus.poke(0x0101, 0x00);
+        cpu.step().unwrap();
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.bus.read_data(0xFF4D), 0xFE);
+        assert_eq!(cpu.step(), Ok(2));
+        for _ in 0..2049 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.registers.pc, 0x0102);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0103);
+    }
+
+    #[test]
+    fn test_illegal_opcode_error() {
+        let mut cpu = test_cpu();
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Error);
+        cpu.bus.poke(0x0100, 0xDD);
+        let error = cpu.step().unwrap_err();
+        assert_eq!(
+            error,
+            CpuError::IllegalOpcode {
+                opcode: 0xDD,
+                pc: 0x0100,
+                bank: 0
+            }
+        );
+        assert_eq!(error.to_string(), "illegal opcode 0xDD at 00:0100");
+    }
+
+    #[test]
+    fn test_illegal_opcode_lockup() {
+        let mut cpu = test_cpu();
+        cpu.ime = true;
+        cpu.bus.poke(0xFFFF, 0x04);
+        cpu.bus.poke(0xFF07, 0x05);
+        cpu.bus.poke(0x0100, 0xFC);
+        for _ in 0..1100 {
+            assert_eq!(cpu.step(), Ok(4));
+        }
+        assert!(cpu.locked_up);
+        assert_eq!(cpu.registers.pc, 0x0101);
+        // the timer keeps running and requesting, but nothing is serviced
+        assert_eq!(cpu.bus.interrupt_flag & 0x04, 0x04);
+    }
+
+    #[test]
+    fn test_all_illegal_opcodes_are_rejected() {
+        let illegal = [
+            0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+        ];
+        for opcode in 0..=0xFFu8 {
+            let mut cpu = test_cpu();
+            cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Error);
+            cpu.registers.sp = 0xD000;
+            cpu.registers.set_hl(0xC000);
+            let result = cpu.execute(Instruction::decode_slice(&[opcode, 0x00, 0x00]));
+            assert_eq!(result.is_err(), illegal.contains(&opcode), "{opcode:#04X}");
+        }
+    }
 }
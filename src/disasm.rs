@@ -0,0 +1,196 @@
+pub mod flow;
+
+use std::fmt;
+
+use crate::instruction::Instruction;
+use crate::memorybus::MemoryBus;
+
+pub use crate::cartridge::ROM_BANK_SIZE;
+
+/// One disassembled instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub bank: u16,
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub instruction: Instruction,
+}
+
+impl Line {
+    /// The instruction in RGBDS syntax, with relative jumps resolved to
+    /// their absolute target.
+    pub fn text(&self) -> String {
+        format_instruction(&self.instruction, self.address)
+    }
+}
+
+impl fmt::Display for Line {
+    /// `BB:AAAA  XX XX XX  mnemonic operands`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{b:02X}")).collect();
+        write!(
+            f,
+            "{:02X}:{:04X}  {:<8}  {}",
+            self.bank,
+            self.address,
+            bytes.join(" "),
+            self.text()
+        )
+    }
+}
+
+/// `instruction` in RGBDS syntax as it reads when located at `address`:
+/// the same as its `Display` form, except that JR targets are absolute.
+pub fn format_instruction(instruction: &Instruction, address: u16) -> String {
+    match instruction {
+        Instruction::Jr(condition, offset) => {
+            let target = address.wrapping_add(2).wrapping_add(*offset as u16);
+            match condition {
+                Some(condition) => format!("jr {condition}, ${target:04X}"),
+                None => format!("jr ${target:04X}"),
+            }
+        }
+        _ => instruction.to_string(),
+    }
+}
+
+/// Linear sweep over `code`, where `code[0]` sits at `origin` in `bank`.
+/// Stops after `count` instructions or at the end of the slice; an
+/// instruction cut off by the end of the slice is emitted as `db` bytes.
+pub fn disassemble(code: &[u8], origin: u16, bank: u16, count: usize) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() && lines.len() < count {
+        let address = origin.wrapping_add(offset as u16);
+        let mut instruction = Instruction::decode_slice(&code[offset..]);
+        let mut length = instruction.length() as usize;
+        if offset + length > code.len() {
+            instruction = Instruction::Illegal(code[offset]);
+            length = 1;
+        }
+        lines.push(Line {
+            bank,
+            address,
+            bytes: code[offset..offset + length].to_vec(),
+            instruction,
+        });
+        offset += length;
+    }
+    lines
+}
+
+/// Disassembles a ROM image starting at `from` in `bank`. Bank 0 lives at
+/// 0x0000-0x3FFF, every other bank at 0x4000-0x7FFF; the sweep does not
+/// leave the bank. Returns `None` if the bank or address is not in the ROM.
+pub fn disassemble_rom(rom: &[u8], bank: u16, from: u16, count: usize) -> Option<Vec<Line>> {
+    let window = if bank == 0 {
+        0x0000..0x4000
+    } else {
+        0x4000..0x8000
+    };
+    if !window.contains(&from) {
+        return None;
+    }
+    let bank_start = bank as usize * ROM_BANK_SIZE;
+    let start = bank_start + (from - window.start) as usize;
+    let end = (bank_start + ROM_BANK_SIZE).min(rom.len());
+    if start >= end {
+        return None;
+    }
+    Some(disassemble(&rom[start..end], from, bank, count))
+}
+
+/// Disassembles whatever is currently mapped on `bus`, starting at `from`.
+/// Reads go through `read_data`, so they see the active ROM bank.
+pub fn disassemble_bus(bus: &MemoryBus, from: u16, count: usize) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut address = from;
+    for _ in 0..count {
+        let mut bytes = Vec::new();
+        let instruction = Instruction::decode(&mut || {
+            let byte = bus.read_data(address.wrapping_add(bytes.len() as u16));
+            bytes.push(byte);
+            byte
+        });
+        if instruction == Instruction::Stop {
+            bytes.push(bus.read_data(address.wrapping_add(1)));
+        }
+        let length = bytes.len() as u16;
+        lines.push(Line {
+            bank: bus.rom_bank(address),
+            address,
+            bytes,
+            instruction,
+        });
+        address = address.wrapping_add(length);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linear_sweep() {
+        let code = [0x00, 0xC3, 0x50, 0x01, 0x18, 0xFE, 0x10, 0x00, 0xDD];
+        let lines = disassemble(&code, 0x0100, 0, usize::MAX);
+        let text: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+        assert_eq!(
+            text,
+            vec![
+                "00:0100  00        nop",
+                "00:0101  C3 50 01  jp $0150",
+                "00:0104  18 FE     jr $0104",
+                "00:0106  10 00     stop",
+                "00:0108  DD        db $DD",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_truncated_instruction_becomes_data() {
+        let lines = disassemble(&[0x00, 0xCD, 0x00], 0x3FFD, 0, usize::MAX);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1].text(), "db $CD");
+        assert_eq!(lines[2].address, 0x3FFF);
+    }
+
+    #[test]
+    fn test_count_limits_output() {
+        let lines = disassemble(&[0x00; 16], 0xC000, 0, 4);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[3].address, 0xC003);
+    }
+
+    #[test]
+    fn test_conditional_jr_target() {
+        let jr = Instruction::Jr(Some(crate::instruction::Condition::NC), 0x10);
+        assert_eq!(format_instruction(&jr, 0x4000), "jr nc, $4012");
+    }
+
+    #[test]
+    fn test_disassemble_rom_banks() {
+        let mut rom = vec![0x00; 3 * ROM_BANK_SIZE];
+        rom[2 * ROM_BANK_SIZE + 0x10] = 0xAF; // xor a at 02:4010
+        let lines = disassemble_rom(&rom, 2, 0x4010, 1).unwrap();
+        assert_eq!(lines[0].to_string(), "02:4010  AF        xor a");
+        assert!(disassemble_rom(&rom, 2, 0x0150, 1).is_none());
+        assert!(disassemble_rom(&rom, 3, 0x4000, 1).is_none());
+        let lines = disassemble_rom(&rom, 0, 0x3FFE, usize::MAX).unwrap();
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn test_disassemble_bus() {
+        let mut bus = MemoryBus::new();
+        bus.write_data(0xC000, 0x3E); // ld a, $42
+        bus.write_data(0xC001, 0x42);
+        bus.write_data(0xC002, 0xCB); // swap a
+        bus.write_data(0xC003, 0x37);
+        let lines = disassemble_bus(&bus, 0xC000, 2);
+        assert_eq!(lines[0].text(), "ld a, $42");
+        assert_eq!(lines[1].bytes, vec![0xCB, 0x37]);
+        assert_eq!(lines[1].text(), "swap a");
+    }
+}
@@ -0,0 +1,531 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+
+use crate::disasm::{ROM_BANK_SIZE, format_instruction};
+use crate::instruction::{Instruction, Reg8};
+
+/// Where execution starts on its own: the entry point, the RST vectors and
+/// the interrupt vectors, with the labels they get in the output.
+const ENTRY_POINTS: [(u16, &str); 14] = [
+    (0x0100, "entry_point"),
+    (0x0000, "rst_00"),
+    (0x0008, "rst_08"),
+    (0x0010, "rst_10"),
+    (0x0018, "rst_18"),
+    (0x0020, "rst_20"),
+    (0x0028, "rst_28"),
+    (0x0030, "rst_30"),
+    (0x0038, "rst_38"),
+    (0x0040, "vblank_interrupt"),
+    (0x0048, "stat_interrupt"),
+    (0x0050, "timer_interrupt"),
+    (0x0058, "serial_interrupt"),
+    (0x0060, "joypad_interrupt"),
+];
+
+/// Result of a flow-following (recursive descent) disassembly: every byte
+/// reached by following control flow from the entry points is code, the
+/// rest is treated as data.
+pub struct FlowDisassembly<'a> {
+    rom: &'a [u8],
+    /// Instructions keyed by ROM offset of their first byte
+    instructions: BTreeMap<usize, Instruction>,
+    /// Bytes covered by an instruction
+    code: Vec<bool>,
+    labels: BTreeMap<usize, String>,
+    /// Resolved branch targets, keyed by the branch's ROM offset
+    targets: BTreeMap<usize, usize>,
+}
+
+/// A place to continue decoding: ROM bank and CPU address, plus the bank
+/// mapped at 0x4000-0x7FFF while executing there, if known.
+#[derive(Copy, Clone)]
+struct Path {
+    bank: u16,
+    address: u16,
+    switchable_bank: Option<u16>,
+}
+
+impl Path {
+    /// What decides where a branch from here leads: the switchable bank,
+    /// for code in bank 0.
+    fn context(&self) -> Option<u16> {
+        if self.bank == 0 {
+            self.switchable_bank
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> FlowDisassembly<'a> {
+    pub fn analyze(rom: &'a [u8]) -> FlowDisassembly<'a> {
+        let mut flow = FlowDisassembly {
+            rom,
+            instructions: BTreeMap::new(),
+            code: vec![false; rom.len()],
+            labels: BTreeMap::new(),
+            targets: BTreeMap::new(),
+        };
+        // MBCs power up with bank 1 mapped in the switchable area
+        let initial_bank = (rom.len() > ROM_BANK_SIZE).then_some(1);
+        let mut queue = Vec::new();
+        for (address, name) in ENTRY_POINTS.iter().rev() {
+            flow.labels.insert(*address as usize, name.to_string());
+            queue.push(Path {
+                bank: 0,
+                address: *address,
+                switchable_bank: initial_bank,
+            });
+        }
+        let mut visited = HashSet::new();
+        let mut traced = HashSet::new();
+        while let Some(path) = queue.pop() {
+            if visited.insert((path.bank, path.address, path.context())) {
+                flow.trace(path, &mut queue, &mut traced);
+            }
+        }
+        flow
+    }
+
+    /// Number of ROM banks, counting a partial last bank.
+    pub fn bank_count(&self) -> usize {
+        self.rom.len().div_ceil(ROM_BANK_SIZE)
+    }
+
+    /// Whether the byte at `bank:address` was reached as code.
+    pub fn is_code(&self, bank: u16, address: u16) -> bool {
+        rom_offset(bank, address)
+            .and_then(|offset| self.code.get(offset).copied())
+            .unwrap_or(false)
+    }
+
+    pub fn label(&self, bank: u16, address: u16) -> Option<&str> {
+        let offset = rom_offset(bank, address)?;
+        self.labels.get(&offset).map(String::as_str)
+    }
+
+    /// Decodes straight-line code from `path` until control flow leaves it,
+    /// queueing every branch target it can resolve. `traced` holds the
+    /// instructions already followed, by ROM offset and bank context: bank 0
+    /// code is followed again under each switchable bank it runs with, as
+    /// its branches into 0x4000-0x7FFF lead somewhere else each time.
+    fn trace(
+        &mut self,
+        mut path: Path,
+        queue: &mut Vec<Path>,
+        traced: &mut HashSet<(usize, Option<u16>)>,
+    ) {
+        // value of A when it was last loaded with an immediate
+        let mut a_value: Option<u8> = None;
+        loop {
+            let Some(offset) = rom_offset(path.bank, path.address) else {
+                return;
+            };
+            if offset >= self.rom.len() || !traced.insert((offset, path.context())) {
+                return;
+            }
+            let instruction = match self.instructions.get(&offset) {
+                Some(&instruction) => instruction,
+                None => {
+                    let instruction = Instruction::decode_slice(&self.rom[offset..]);
+                    let end = offset + instruction.length() as usize;
+                    let bank_end = (offset / ROM_BANK_SIZE + 1) * ROM_BANK_SIZE;
+                    if end > self.rom.len().min(bank_end) || self.code[offset..end].contains(&true)
+                    {
+                        // runs off the bank or into the middle of known code
+                        return;
+                    }
+                    self.instructions.insert(offset, instruction);
+                    self.code[offset..end].fill(true);
+                    instruction
+                }
+            };
+            let length = instruction.length() as usize;
+
+            let next = path.address.wrapping_add(length as u16);
+            let mut fallthrough = true;
+            match instruction {
+                Instruction::Jr(condition, displacement) => {
+                    let target = next.wrapping_add(displacement as u16);
+                    self.branch(path, offset, target, "jump", queue);
+                    fallthrough = condition.is_some();
+                }
+                Instruction::Jp(condition, target) => {
+                    self.branch(path, offset, target, "jump", queue);
+                    fallthrough = condition.is_some();
+                }
+                Instruction::Call(_, target) => self.branch(path, offset, target, "call", queue),
+                Instruction::Rst(vector) => self.branch(path, offset, vector as u16, "call", queue),
+                Instruction::Ret(None) | Instruction::Reti | Instruction::JpHl => {
+                    fallthrough = false
+                }
+                Instruction::Illegal(_) => fallthrough = false,
+                Instruction::LdAddrA(address) if (0x2000..0x4000).contains(&address) => {
+                    // ROM bank select: MBC1/3/5 all take the low bits here
+                    path.switchable_bank = a_value.map(|value| {
+                        let bank = (value as usize % self.bank_count().max(1)) as u16;
+                        bank.max(1)
+                    });
+                }
+                _ => {}
+            }
+            a_value = match instruction {
+                Instruction::LdR8Imm(Reg8::A, value) => Some(value),
+                _ if writes_a(&instruction) => None,
+                _ => a_value,
+            };
+            if !fallthrough {
+                return;
+            }
+            if path.bank != 0 && !(0x4000..0x8000).contains(&next) {
+                return;
+            }
+            path.address = next;
+        }
+    }
+
+    /// Resolves the target of the branch at ROM offset `at` to a ROM bank,
+    /// labels it and queues it. Targets outside ROM or in an unknown
+    /// switchable bank are skipped.
+    fn branch(&mut self, from: Path, at: usize, target: u16, kind: &str, queue: &mut Vec<Path>) {
+        let bank = match target {
+            0x0000..=0x3FFF => 0,
+            0x4000..=0x7FFF if from.bank != 0 => from.bank,
+            0x4000..=0x7FFF => match from.switchable_bank {
+                Some(bank) => bank,
+                None => return,
+            },
+            _ => return,
+        };
+        let Some(offset) = rom_offset(bank, target) else {
+            return;
+        };
+        if offset >= self.rom.len() {
+            return;
+        }
+        self.targets.insert(at, offset);
+        let name = format!("{kind}_{bank:02X}_{target:04X}");
+        let label = self.labels.entry(offset).or_insert(name);
+        if kind == "call" && label.starts_with("jump_") {
+            *label = format!("call_{bank:02X}_{target:04X}");
+        }
+        queue.push(Path {
+            bank,
+            address: target,
+            switchable_bank: if bank == 0 {
+                from.switchable_bank
+            } else {
+                Some(bank)
+            },
+        });
+    }
+
+    /// Emits an RGBDS source file with one section per bank. Reached code
+    /// is written as instructions with labels for branch targets; anything
+    /// else becomes `db` lines, so the output assembles back to the ROM.
+    pub fn to_source(&self) -> String {
+        let mut out = String::new();
+        for bank in 0..self.bank_count() {
+            let bank = bank as u16;
+            if bank == 0 {
+                writeln!(out, "SECTION \"ROM Bank $00\", ROM0[$0000]").unwrap();
+            } else {
+                writeln!(out).unwrap();
+                writeln!(
+                    out,
+                    "SECTION \"ROM Bank ${bank:02X}\", ROMX[$4000], BANK[${bank:02X}]"
+                )
+                .unwrap();
+            }
+            let start = bank as usize * ROM_BANK_SIZE;
+            let end = (start + ROM_BANK_SIZE).min(self.rom.len());
+            let mut offset = start;
+            let mut data = Vec::new();
+            while offset < end {
+                if let Some(label) = self
+                    .labels
+                    .get(&offset)
+                    .filter(|_| self.instructions.contains_key(&offset))
+                {
+                    flush_data(&mut out, &mut data);
+                    writeln!(out, "\n{label}:").unwrap();
+                }
+                match self.instructions.get(&offset) {
+                    Some(instruction) if self.assembles_back(instruction, offset) => {
+                        flush_data(&mut out, &mut data);
+                        writeln!(out, "    {}", self.source_text(instruction, offset)).unwrap();
+                        offset += instruction.length() as usize;
+                    }
+                    _ => {
+                        data.push(self.rom[offset]);
+                        if data.len() == 8 {
+                            flush_data(&mut out, &mut data);
+                        }
+                        offset += 1;
+                    }
+                }
+            }
+            flush_data(&mut out, &mut data);
+        }
+        out
+    }
+
+    /// Whether RGBDS would produce the same bytes for the instruction's text.
+    /// Not the case for illegal opcodes and for STOP with a non-zero second
+    /// byte, which are left as data.
+    fn assembles_back(&self, instruction: &Instruction, offset: usize) -> bool {
+        match instruction {
+            Instruction::Illegal(_) => false,
+            Instruction::Stop => self.rom[offset + 1] == 0x00,
+            _ => true,
+        }
+    }
+
+    /// The instruction's source text, using labels for branch targets that
+    /// start an instruction, as `to_source` only defines labels there.
+    fn source_text(&self, instruction: &Instruction, offset: usize) -> String {
+        let address = cpu_address(offset);
+        let condition = match instruction {
+            Instruction::Jr(condition, _)
+            | Instruction::Jp(condition, _)
+            | Instruction::Call(condition, _) => condition,
+            _ => return format_instruction(instruction, address),
+        };
+        let label = self
+            .targets
+            .get(&offset)
+            .filter(|target| self.instructions.contains_key(*target))
+            .and_then(|target| self.labels.get(target));
+        match label {
+            Some(label) => match condition {
+                Some(condition) => format!("{} {condition}, {label}", instruction.mnemonic()),
+                None => format!("{} {label}", instruction.mnemonic()),
+            },
+            _ => format_instruction(instruction, address),
+        }
+    }
+}
+
+/// ROM offset of `address` as seen with `bank` mapped, if it is ROM.
+fn rom_offset(bank: u16, address: u16) -> Option<usize> {
+    match address {
+        0x0000..=0x3FFF if bank == 0 => Some(address as usize),
+        0x4000..=0x7FFF if bank != 0 => {
+            Some(bank as usize * ROM_BANK_SIZE + (address as usize - 0x4000))
+        }
+        _ => None,
+    }
+}
+
+/// CPU address at which the byte at ROM `offset` is visible.
+fn cpu_address(offset: usize) -> u16 {
+    if offset < ROM_BANK_SIZE {
+        offset as u16
+    } else {
+        (0x4000 + offset % ROM_BANK_SIZE) as u16
+    }
+}
+
+/// Conservatively: does `instruction` leave A with a value we can't track?
+fn writes_a(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::LdR8Imm(Reg8::A, _)
+            | Instruction::LdR8R8(Reg8::A, _)
+            | Instruction::LdAIndirect(_)
+            | Instruction::LdhAAddr(_)
+            | Instruction::LdhAC
+            | Instruction::LdAAddr(_)
+            | Instruction::IncR8(Reg8::A)
+            | Instruction::DecR8(Reg8::A)
+            | Instruction::RotateA(_)
+            | Instruction::Daa
+            | Instruction::Cpl
+            | Instruction::Alu(..)
+            | Instruction::AluImm(..)
+            | Instruction::Pop(_)
+            | Instruction::Shift(_, Reg8::A)
+            | Instruction::Res(_, Reg8::A)
+            | Instruction::Set(_, Reg8::A)
+            | Instruction::Call(..)
+            | Instruction::Rst(_)
+    )
+}
+
+fn flush_data(out: &mut String, data: &mut Vec<u8>) {
+    if data.is_empty() {
+        return;
+    }
+    let bytes: Vec<String> = data.iter().map(|b| format!("${b:02X}")).collect();
+    writeln!(out, "    db {}", bytes.join(", ")).unwrap();
+    data.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom_with(banks: usize, code: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut rom = vec![0x00; banks * ROM_BANK_SIZE];
+        // keep the vectors from running into each other as NOP slides
+        for vector in (0x00..=0x60).step_by(8) {
+            rom[vector] = 0xC9; // ret
+        }
+        for (offset, bytes) in code {
+            rom[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+        rom
+    }
+
+    #[test]
+    fn test_data_after_unconditional_jump_is_not_code() {
+        let rom = rom_with(2, &[(0x100, &[0x00, 0xC3, 0x50, 0x01, 0xCE, 0xED])]);
+        let flow = FlowDisassembly::analyze(&rom);
+        assert!(flow.is_code(0, 0x0101));
+        assert!(!flow.is_code(0, 0x0104));
+        assert!(!flow.is_code(0, 0x0105));
+        assert!(flow.is_code(0, 0x0150));
+        assert_eq!(flow.label(0, 0x0150), Some("jump_00_0150"));
+    }
+
+    #[test]
+    fn test_follows_calls_and_conditional_branches() {
+        let rom = rom_with(
+            2,
+            &[
+                (0x100, &[0x18, 0x4E]), // jr $0150
+                (0x150, &[0xCD, 0x00, 0x02, 0x28, 0x02, 0x18, 0xFE, 0xC9]),
+                (0x200, &[0xC9]), // ret
+            ],
+        );
+        let flow = FlowDisassembly::analyze(&rom);
+        assert!(flow.is_code(0, 0x0200));
+        assert_eq!(flow.label(0, 0x0200), Some("call_00_0200"));
+        assert!(flow.is_code(0, 0x0155)); // after the jr z
+        assert!(flow.is_code(0, 0x0157)); // jr z target
+        assert!(!flow.is_code(0, 0x0158));
+    }
+
+    #[test]
+    fn test_tracks_bank_switch() {
+        let rom = rom_with(
+            4,
+            &[
+                // ld a, 3 / ld [$2000], a / call $4000
+                (
+                    0x100,
+                    &[0x3E, 0x03, 0xEA, 0x00, 0x20, 0xCD, 0x00, 0x40, 0x76],
+                ),
+                (3 * ROM_BANK_SIZE, &[0x3C, 0xC9]), // inc a / ret
+            ],
+        );
+        let flow = FlowDisassembly::analyze(&rom);
+        assert!(flow.is_code(3, 0x4000));
+        assert!(flow.is_code(3, 0x4001));
+        assert!(!flow.is_code(1, 0x4000));
+        assert_eq!(flow.label(3, 0x4000), Some("call_03_4000"));
+        assert!(flow.to_source().contains("    call call_03_4000\n"));
+    }
+
+    #[test]
+    fn test_bank_0_routine_followed_under_each_bank() {
+        // two callers switch in banks 2 and 3, then call the same routine
+        // at $0200, which calls $4000 in whatever bank is mapped
+        let rom = rom_with(
+            4,
+            &[
+                (
+                    0x100,
+                    &[
+                        0x3E, 0x02, 0xEA, 0x00, 0x20, 0xCD, 0x00, 0x02, 0xC3, 0x50, 0x01,
+                    ],
+                ),
+                (
+                    0x150,
+                    &[0x3E, 0x03, 0xEA, 0x00, 0x20, 0xCD, 0x00, 0x02, 0x76],
+                ),
+                (0x200, &[0xCD, 0x00, 0x40, 0xC9]),
+                (2 * ROM_BANK_SIZE, &[0x3C, 0xC9]),
+                (3 * ROM_BANK_SIZE, &[0x3D, 0xC9]),
+            ],
+        );
+        let flow = FlowDisassembly::analyze(&rom);
+        assert!(flow.is_code(2, 0x4000));
+        assert!(flow.is_code(3, 0x4000));
+        assert_eq!(flow.label(2, 0x4000), Some("call_02_4000"));
+        assert_eq!(flow.label(3, 0x4000), Some("call_03_4000"));
+    }
+
+    #[test]
+    fn test_unknown_bank_is_not_followed() {
+        let rom = rom_with(
+            4,
+            &[(
+                0x100,
+                &[0xFA, 0x00, 0xC0, 0xEA, 0x00, 0x20, 0xCD, 0x00, 0x40, 0x76],
+            )],
+        );
+        let flow = FlowDisassembly::analyze(&rom);
+        // bank 1 was mapped until the write of an unknown value
+        assert!(!flow.is_code(1, 0x4000));
+    }
+
+    #[test]
+    fn test_source_uses_labels_and_data_blocks() {
+        let rom = rom_with(2, &[(0x100, &[0x00, 0xC3, 0x50, 0x01, 0xDD])]);
+        let source = FlowDisassembly::analyze(&rom).to_source();
+        assert!(source.starts_with("SECTION \"ROM Bank $00\", ROM0[$0000]\n"));
+        assert!(source.contains("\nentry_point:\n    nop\n    jp jump_00_0150\n    db $DD, $00"));
+        assert!(source.contains("\njump_00_0150:\n    nop\n"));
+        assert!(source.contains("SECTION \"ROM Bank $01\", ROMX[$4000], BANK[$01]\n    db $00"));
+    }
+
+    #[test]
+    fn test_branch_into_an_operand_gets_no_label() {
+        // ld a, $18 / jr to its operand byte
+        let rom = rom_with(
+            2,
+            &[
+                (0x100, &[0x00, 0xC3, 0x50, 0x01]),
+                (0x150, &[0x3E, 0x18, 0x18, 0xFD]),
+            ],
+        );
+        let source = FlowDisassembly::analyze(&rom).to_source();
+        let defined: HashSet<&str> = source
+            .lines()
+            .filter_map(|line| line.strip_suffix(':'))
+            .collect();
+        for line in source.lines().map(str::trim) {
+            let Some(("jp" | "jr" | "call", operands)) = line.split_once(' ') else {
+                continue;
+            };
+            let target = operands.rsplit(", ").next().unwrap();
+            assert!(
+                target.starts_with('$') || defined.contains(target),
+                "{line}"
+            );
+        }
+        assert!(source.contains("    jr $0151\n"));
+    }
+
+    #[test]
+    fn test_source_has_every_byte_once() {
+        let rom = rom_with(2, &[(0x100, &[0x00, 0xC3, 0x50, 0x01, 0x10, 0x01])]);
+        let source = FlowDisassembly::analyze(&rom).to_source();
+        let mut total = 0;
+        for line in source.lines().map(str::trim) {
+            if let Some(bytes) = line.strip_prefix("db ") {
+                total += bytes.split(", ").count();
+            } else if !line.is_empty() && !line.ends_with(':') && !line.starts_with("SECTION") {
+                let mnemonic = line.split(' ').next().unwrap();
+                total += match mnemonic {
+                    "jp" | "call" => 3,
+                    "jr" | "stop" => 2,
+                    _ => 1,
+                };
+            }
+        }
+        assert_eq!(total, rom.len());
+    }
+}
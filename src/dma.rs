@@ -0,0 +1,136 @@
+/// Bytes an OAM DMA copies: all of OAM.
+pub const OAM_DMA_LENGTH: u8 = 0xA0;
+
+/// OAM DMA (0xFF46): copies 160 bytes from `XX00` to OAM, one per M-cycle.
+///
+/// A write starts the transfer after one M-cycle of setup; a transfer
+/// already under way keeps going through that cycle, then the new one
+/// takes over from the first byte. The DMA only keeps the state; the bus
+/// does the copying, since it owns the memory.
+pub struct OamDma {
+    /// The last value written
+    register: u8,
+    /// Source page of a transfer starting next M-cycle
+    pending: Option<u8>,
+    /// Source page of the transfer under way
+    page: u8,
+    /// Next byte of the transfer under way
+    progress: Option<u8>,
+    /// The byte last copied, which the source's bus carries
+    in_flight: u8,
+}
+
+impl Default for OamDma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OamDma {
+    pub fn new() -> OamDma {
+        OamDma {
+            register: 0xFF,
+            pending: None,
+            page: 0,
+            progress: None,
+            in_flight: 0xFF,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.register = value;
+        self.pending = Some(value);
+    }
+
+    /// Sets 0xFF46 without starting a transfer.
+    pub fn set_register(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    /// Where the transfer under way reads from, once sources past 0xDFFF
+    /// are folded onto WRAM like echo RAM, or `None` with no transfer.
+    pub fn source(&self) -> Option<u16> {
+        self.progress?;
+        Some(source_address(self.page, 0))
+    }
+
+    /// The byte on the source's bus, which CPU reads there see instead.
+    pub fn in_flight(&self) -> u8 {
+        self.in_flight
+    }
+
+    /// Advances one M-cycle. Returns the source address and OAM offset of
+    /// the byte to copy this cycle; the bus copies it and hands it back
+    /// through `set_in_flight`.
+    pub fn tick(&mut self) -> Option<(u16, u8)> {
+        let starting = self.pending.take();
+        let copy = self.progress.map(|index| {
+            self.progress = (index + 1 < OAM_DMA_LENGTH).then_some(index + 1);
+            (source_address(self.page, index), index)
+        });
+        if let Some(page) = starting {
+            self.page = page;
+            self.progress = Some(0);
+        }
+        copy
+    }
+
+    pub fn set_in_flight(&mut self, value: u8) {
+        self.in_flight = value;
+    }
+}
+
+/// Source of byte `index` from page `page`. Pages 0xE0-0xFF are read
+/// through the echo RAM decoding, so 0xFE00 reads WRAM at 0xDE00 rather
+/// than OAM.
+fn source_address(page: u8, index: u8) -> u16 {
+    let address = u16::from_be_bytes([page, index]);
+    if address >= 0xE000 {
+        address - 0x2000
+    } else {
+        address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_timing() {
+        let mut dma = OamDma::new();
+        assert_eq!(dma.tick(), None);
+        dma.write(0xC1);
+        assert_eq!(dma.read(), 0xC1);
+        // a cycle of setup, then one byte per cycle
+        assert_eq!(dma.tick(), None);
+        assert_eq!(dma.source(), Some(0xC100));
+        assert_eq!(dma.tick(), Some((0xC100, 0)));
+        for index in 1..OAM_DMA_LENGTH {
+            assert_eq!(dma.tick(), Some((0xC100 + index as u16, index)));
+        }
+        assert_eq!(dma.source(), None);
+        assert_eq!(dma.tick(), None);
+    }
+
+    #[test]
+    fn test_restart_and_high_sources() {
+        let mut dma = OamDma::new();
+        dma.write(0x80);
+        dma.tick();
+        dma.tick();
+        dma.tick();
+        dma.write(0xFE);
+        // the old transfer runs through the setup cycle
+        assert_eq!(dma.tick(), Some((0x8002, 2)));
+        assert_eq!(dma.tick(), Some((0xDE00, 0)));
+
+        dma.set_register(0x12);
+        assert_eq!(dma.read(), 0x12);
+        assert_eq!(dma.tick(), Some((0xDE01, 1)));
+    }
+}
@@ -0,0 +1,857 @@
+use std::fmt;
+
+/// 8-bit operand encoded in three opcode bits: B, C, D, E, H, L, (HL), A.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reg8 {
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    HlIndirect,
+    A,
+}
+
+impl Reg8 {
+    fn from_bits(bits: u8) -> Reg8 {
+        [
+            Reg8::B,
+            Reg8::C,
+            Reg8::D,
+            Reg8::E,
+            Reg8::H,
+            Reg8::L,
+            Reg8::HlIndirect,
+            Reg8::A,
+        ][(bits & 0x07) as usize]
+    }
+}
+
+/// 16-bit register pair encoded in bits 4-5 of most 16-bit instructions.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reg16 {
+    BC,
+    DE,
+    HL,
+    SP,
+}
+
+impl Reg16 {
+    fn from_bits(bits: u8) -> Reg16 {
+        [Reg16::BC, Reg16::DE, Reg16::HL, Reg16::SP][(bits & 0x03) as usize]
+    }
+}
+
+/// Register pair operand of PUSH and POP, where AF takes the place of SP.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StackReg {
+    BC,
+    DE,
+    HL,
+    AF,
+}
+
+impl StackReg {
+    fn from_bits(bits: u8) -> StackReg {
+        [StackReg::BC, StackReg::DE, StackReg::HL, StackReg::AF][(bits & 0x03) as usize]
+    }
+}
+
+/// Memory operand of `LD (rr), A` and `LD A, (rr)`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Indirect {
+    BC,
+    DE,
+    /// (HL+): HL is incremented after the access
+    HlIncrement,
+    /// (HL-): HL is decremented after the access
+    HlDecrement,
+}
+
+impl Indirect {
+    fn from_bits(bits: u8) -> Indirect {
+        [
+            Indirect::BC,
+            Indirect::DE,
+            Indirect::HlIncrement,
+            Indirect::HlDecrement,
+        ][(bits & 0x03) as usize]
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+impl Condition {
+    fn from_bits(bits: u8) -> Condition {
+        [Condition::NZ, Condition::Z, Condition::NC, Condition::C][(bits & 0x03) as usize]
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Condition::NZ => "nz",
+            Condition::Z => "z",
+            Condition::NC => "nc",
+            Condition::C => "c",
+        };
+        write!(f, "{text}")
+    }
+}
+
+/// Accumulator operation selected by bits 3-5 of the 0x80-0xBF block.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    Adc,
+    Sub,
+    Sbc,
+    And,
+    Xor,
+    Or,
+    Cp,
+}
+
+impl AluOp {
+    fn from_bits(bits: u8) -> AluOp {
+        [
+            AluOp::Add,
+            AluOp::Adc,
+            AluOp::Sub,
+            AluOp::Sbc,
+            AluOp::And,
+            AluOp::Xor,
+            AluOp::Or,
+            AluOp::Cp,
+        ][(bits & 0x07) as usize]
+    }
+}
+
+/// Rotate/shift selected by bits 3-5 of the first quarter of the CB table.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShiftOp {
+    Rlc,
+    Rrc,
+    Rl,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+}
+
+impl ShiftOp {
+    fn from_bits(bits: u8) -> ShiftOp {
+        [
+            ShiftOp::Rlc,
+            ShiftOp::Rrc,
+            ShiftOp::Rl,
+            ShiftOp::Rr,
+            ShiftOp::Sla,
+            ShiftOp::Sra,
+            ShiftOp::Swap,
+            ShiftOp::Srl,
+        ][(bits & 0x07) as usize]
+    }
+}
+
+/// A decoded SM83 instruction with its immediate operands.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Nop,
+    /// LD rr, d16
+    LdR16Imm(Reg16, u16),
+    /// LD (rr), A
+    LdIndirectA(Indirect),
+    /// LD A, (rr)
+    LdAIndirect(Indirect),
+    IncR16(Reg16),
+    DecR16(Reg16),
+    IncR8(Reg8),
+    DecR8(Reg8),
+    /// LD r, d8
+    LdR8Imm(Reg8, u8),
+    /// RLCA/RRCA/RLA/RRA, only ever with Rlc, Rrc, Rl or Rr
+    RotateA(ShiftOp),
+    /// LD (a16), SP
+    LdAddrSp(u16),
+    /// ADD HL, rr
+    AddHl(Reg16),
+    /// Two bytes long, but the decoder only consumes the opcode: the byte
+    /// after it is skipped by the CPU when STOP executes.
+    Stop,
+    Jr(Option<Condition>, i8),
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    Halt,
+    /// LD r, r'
+    LdR8R8(Reg8, Reg8),
+    /// ADD/ADC/SUB/SBC/AND/XOR/OR/CP A, r
+    Alu(AluOp, Reg8),
+    /// ADD/ADC/SUB/SBC/AND/XOR/OR/CP A, d8
+    AluImm(AluOp, u8),
+    Ret(Option<Condition>),
+    Reti,
+    Pop(StackReg),
+    Push(StackReg),
+    Jp(Option<Condition>, u16),
+    JpHl,
+    Call(Option<Condition>, u16),
+    /// RST with its target vector (0x00, 0x08, ..., 0x38)
+    Rst(u8),
+    /// LDH (a8), A
+    LdhAddrA(u8),
+    /// LDH A, (a8)
+    LdhAAddr(u8),
+    /// LD (C), A
+    LdhCA,
+    /// LD A, (C)
+    LdhAC,
+    /// LD (a16), A
+    LdAddrA(u16),
+    /// LD A, (a16)
+    LdAAddr(u16),
+    AddSp(i8),
+    /// LD HL, SP+e8
+    LdHlSpOffset(i8),
+    LdSpHl,
+    Di,
+    Ei,
+    /// CB-prefixed RLC/RRC/RL/RR/SLA/SRA/SWAP/SRL
+    Shift(ShiftOp, Reg8),
+    Bit(u8, Reg8),
+    Res(u8, Reg8),
+    Set(u8, Reg8),
+    /// One of the 11 opcodes that have no instruction
+    Illegal(u8),
+}
+
+/// How an instruction treats one flag.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FlagEffect {
+    Unaffected,
+    Reset,
+    Set,
+    /// Depends on the result
+    Affected,
+}
+
+/// Effect of an instruction on Z, N, H and C.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FlagEffects {
+    pub z: FlagEffect,
+    pub n: FlagEffect,
+    pub h: FlagEffect,
+    pub c: FlagEffect,
+}
+
+impl FlagEffects {
+    const NONE: FlagEffects = FlagEffects::from_table(*b"----");
+
+    /// Builds the effects from the notation of the usual opcode tables:
+    /// `-` unaffected, `0` reset, `1` set, anything else affected.
+    const fn from_table(table: [u8; 4]) -> FlagEffects {
+        const fn effect(symbol: u8) -> FlagEffect {
+            match symbol {
+                b'-' => FlagEffect::Unaffected,
+                b'0' => FlagEffect::Reset,
+                b'1' => FlagEffect::Set,
+                _ => FlagEffect::Affected,
+            }
+        }
+        FlagEffects {
+            z: effect(table[0]),
+            n: effect(table[1]),
+            h: effect(table[2]),
+            c: effect(table[3]),
+        }
+    }
+}
+
+impl fmt::Display for FlagEffects {
+    /// Prints the effects in opcode table notation, e.g. `Z0HC`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (effect, name) in [(self.z, 'Z'), (self.n, 'N'), (self.h, 'H'), (self.c, 'C')] {
+            let symbol = match effect {
+                FlagEffect::Unaffected => '-',
+                FlagEffect::Reset => '0',
+                FlagEffect::Set => '1',
+                FlagEffect::Affected => name,
+            };
+            write!(f, "{symbol}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Instruction {
+    /// Decodes one instruction, pulling the opcode and then any prefix or
+    /// immediate bytes from `fetch` in program order. The CPU passes its
+    /// timed opcode fetch here; tools can pass a reader over a byte slice.
+    pub fn decode(fetch: &mut impl FnMut() -> u8) -> Instruction {
+        let opcode = fetch();
+        Instruction::decode_opcode(opcode, fetch)
+    }
+
+    /// Same as `decode`, for an opcode that has already been fetched.
+    pub fn decode_opcode(opcode: u8, fetch: &mut impl FnMut() -> u8) -> Instruction {
+        match opcode {
+            0x00 => Instruction::Nop,
+            0x01 | 0x11 | 0x21 | 0x31 => {
+                let low = fetch() as u16;
+                let high = fetch() as u16;
+                Instruction::LdR16Imm(Reg16::from_bits(opcode >> 4), (high << 8) | low)
+            }
+            0x02 | 0x12 | 0x22 | 0x32 => Instruction::LdIndirectA(Indirect::from_bits(opcode >> 4)),
+            0x0A | 0x1A | 0x2A | 0x3A => Instruction::LdAIndirect(Indirect::from_bits(opcode >> 4)),
+            0x03 | 0x13 | 0x23 | 0x33 => Instruction::IncR16(Reg16::from_bits(opcode >> 4)),
+            0x0B | 0x1B | 0x2B | 0x3B => Instruction::DecR16(Reg16::from_bits(opcode >> 4)),
+            0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => {
+                Instruction::IncR8(Reg8::from_bits(opcode >> 3))
+            }
+            0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => {
+                Instruction::DecR8(Reg8::from_bits(opcode >> 3))
+            }
+            0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => {
+                Instruction::LdR8Imm(Reg8::from_bits(opcode >> 3), fetch())
+            }
+            0x07 | 0x0F | 0x17 | 0x1F => Instruction::RotateA(ShiftOp::from_bits(opcode >> 3)),
+            0x08 => {
+                let low = fetch() as u16;
+                let high = fetch() as u16;
+                Instruction::LdAddrSp((high << 8) | low)
+            }
+            0x09 | 0x19 | 0x29 | 0x39 => Instruction::AddHl(Reg16::from_bits(opcode >> 4)),
+            0x10 => Instruction::Stop,
+            0x18 => Instruction::Jr(None, fetch() as i8),
+            0x20 | 0x28 | 0x30 | 0x38 => {
+                Instruction::Jr(Some(Condition::from_bits(opcode >> 3)), fetch() as i8)
+            }
+            0x27 => Instruction::Daa,
+            0x2F => Instruction::Cpl,
+            0x37 => Instruction::Scf,
+            0x3F => Instruction::Ccf,
+            0x76 => Instruction::Halt,
+            0x40..=0x7F => {
+                Instruction::LdR8R8(Reg8::from_bits(opcode >> 3), Reg8::from_bits(opcode))
+            }
+            0x80..=0xBF => Instruction::Alu(AluOp::from_bits(opcode >> 3), Reg8::from_bits(opcode)),
+            0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => {
+                Instruction::AluImm(AluOp::from_bits(opcode >> 3), fetch())
+            }
+            0xC0 | 0xC8 | 0xD0 | 0xD8 => Instruction::Ret(Some(Condition::from_bits(opcode >> 3))),
+            0xC9 => Instruction::Ret(None),
+            0xD9 => Instruction::Reti,
+            0xC1 | 0xD1 | 0xE1 | 0xF1 => Instruction::Pop(StackReg::from_bits(opcode >> 4)),
+            0xC5 | 0xD5 | 0xE5 | 0xF5 => Instruction::Push(StackReg::from_bits(opcode >> 4)),
+            0xC2 | 0xCA | 0xD2 | 0xDA | 0xC3 | 0xC4 | 0xCC | 0xD4 | 0xDC | 0xCD => {
+                let low = fetch() as u16;
+                let address = ((fetch() as u16) << 8) | low;
+                let condition = Some(Condition::from_bits(opcode >> 3));
+                match opcode {
+                    0xC3 => Instruction::Jp(None, address),
+                    0xCD => Instruction::Call(None, address),
+                    _ if opcode & 0x04 == 0 => Instruction::Jp(condition, address),
+                    _ => Instruction::Call(condition, address),
+                }
+            }
+            0xE9 => Instruction::JpHl,
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
+                Instruction::Rst(opcode & 0x38)
+            }
+            0xE0 => Instruction::LdhAddrA(fetch()),
+            0xF0 => Instruction::LdhAAddr(fetch()),
+            0xE2 => Instruction::LdhCA,
+            0xF2 => Instruction::LdhAC,
+            0xEA | 0xFA => {
+                let low = fetch() as u16;
+                let address = ((fetch() as u16) << 8) | low;
+                if opcode == 0xEA {
+                    Instruction::LdAddrA(address)
+                } else {
+                    Instruction::LdAAddr(address)
+                }
+            }
+            0xE8 => Instruction::AddSp(fetch() as i8),
+            0xF8 => Instruction::LdHlSpOffset(fetch() as i8),
+            0xF9 => Instruction::LdSpHl,
+            0xF3 => Instruction::Di,
+            0xFB => Instruction::Ei,
+            0xCB => Instruction::decode_cb(fetch()),
+            _ => Instruction::Illegal(opcode),
+        }
+    }
+
+    fn decode_cb(opcode: u8) -> Instruction {
+        let operand = Reg8::from_bits(opcode);
+        let bit = (opcode >> 3) & 0x07;
+        match opcode >> 6 {
+            0 => Instruction::Shift(ShiftOp::from_bits(bit), operand),
+            1 => Instruction::Bit(bit, operand),
+            2 => Instruction::Res(bit, operand),
+            _ => Instruction::Set(bit, operand),
+        }
+    }
+
+    /// Decodes the instruction starting at `bytes[0]`. Bytes missing at the
+    /// end of the slice read as 0x00.
+    pub fn decode_slice(bytes: &[u8]) -> Instruction {
+        let mut iter = bytes.iter().copied();
+        Instruction::decode(&mut || iter.next().unwrap_or(0x00))
+    }
+
+    /// Size in bytes, opcode and CB prefix included.
+    pub fn length(&self) -> u16 {
+        match self {
+            Instruction::LdR16Imm(..)
+            | Instruction::LdAddrSp(_)
+            | Instruction::Jp(_, _)
+            | Instruction::Call(_, _)
+            | Instruction::LdAddrA(_)
+            | Instruction::LdAAddr(_) => 3,
+            Instruction::LdR8Imm(..)
+            | Instruction::Jr(..)
+            | Instruction::AluImm(..)
+            | Instruction::LdhAddrA(_)
+            | Instruction::LdhAAddr(_)
+            | Instruction::AddSp(_)
+            | Instruction::LdHlSpOffset(_)
+            | Instruction::Shift(..)
+            | Instruction::Bit(..)
+            | Instruction::Res(..)
+            | Instruction::Set(..)
+            | Instruction::Stop => 2,
+            _ => 1,
+        }
+    }
+
+    /// Duration in M-cycles. `branch_taken` only matters for conditional
+    /// JR/JP/CALL/RET; unconditional ones always count as taken.
+    pub fn cycles(&self, branch_taken: bool) -> u8 {
+        let hl = |r: &Reg8| *r == Reg8::HlIndirect;
+        match self {
+            Instruction::Nop
+            | Instruction::RotateA(_)
+            | Instruction::Stop
+            | Instruction::Daa
+            | Instruction::Cpl
+            | Instruction::Scf
+            | Instruction::Ccf
+            | Instruction::Halt
+            | Instruction::JpHl
+            | Instruction::Di
+            | Instruction::Ei
+            | Instruction::Illegal(_) => 1,
+            Instruction::LdIndirectA(_)
+            | Instruction::LdAIndirect(_)
+            | Instruction::IncR16(_)
+            | Instruction::DecR16(_)
+            | Instruction::AddHl(_)
+            | Instruction::AluImm(..)
+            | Instruction::LdhCA
+            | Instruction::LdhAC
+            | Instruction::LdSpHl => 2,
+            Instruction::LdR16Imm(..)
+            | Instruction::Pop(_)
+            | Instruction::LdhAddrA(_)
+            | Instruction::LdhAAddr(_)
+            | Instruction::LdHlSpOffset(_) => 3,
+            Instruction::Reti
+            | Instruction::Push(_)
+            | Instruction::Rst(_)
+            | Instruction::LdAddrA(_)
+            | Instruction::LdAAddr(_)
+            | Instruction::AddSp(_) => 4,
+            Instruction::LdAddrSp(_) => 5,
+            Instruction::IncR8(r) | Instruction::DecR8(r) => {
+                if hl(r) {
+                    3
+                } else {
+                    1
+                }
+            }
+            Instruction::LdR8Imm(r, _) => {
+                if hl(r) {
+                    3
+                } else {
+                    2
+                }
+            }
+            Instruction::LdR8R8(dst, src) => {
+                if hl(dst) || hl(src) {
+                    2
+                } else {
+                    1
+                }
+            }
+            Instruction::Alu(_, r) => {
+                if hl(r) {
+                    2
+                } else {
+                    1
+                }
+            }
+            Instruction::Shift(_, r) | Instruction::Res(_, r) | Instruction::Set(_, r) => {
+                if hl(r) {
+                    4
+                } else {
+                    2
+                }
+            }
+            Instruction::Bit(_, r) => {
+                if hl(r) {
+                    3
+                } else {
+                    2
+                }
+            }
+            Instruction::Jr(condition, _) => {
+                if condition.is_none() || branch_taken {
+                    3
+                } else {
+                    2
+                }
+            }
+            Instruction::Jp(condition, _) => {
+                if condition.is_none() || branch_taken {
+                    4
+                } else {
+                    3
+                }
+            }
+            Instruction::Call(condition, _) => {
+                if condition.is_none() || branch_taken {
+                    6
+                } else {
+                    3
+                }
+            }
+            Instruction::Ret(None) => 4,
+            Instruction::Ret(Some(_)) => {
+                if branch_taken {
+                    5
+                } else {
+                    2
+                }
+            }
+        }
+    }
+
+    /// Lower-case RGBDS mnemonic.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Nop => "nop",
+            Instruction::LdR16Imm(..)
+            | Instruction::LdIndirectA(_)
+            | Instruction::LdAIndirect(_)
+            | Instruction::LdR8Imm(..)
+            | Instruction::LdAddrSp(_)
+            | Instruction::LdR8R8(..)
+            | Instruction::LdAddrA(_)
+            | Instruction::LdAAddr(_)
+            | Instruction::LdHlSpOffset(_)
+            | Instruction::LdSpHl => "ld",
+            Instruction::LdhAddrA(_)
+            | Instruction::LdhAAddr(_)
+            | Instruction::LdhCA
+            | Instruction::LdhAC => "ldh",
+            Instruction::IncR16(_) | Instruction::IncR8(_) => "inc",
+            Instruction::DecR16(_) | Instruction::DecR8(_) => "dec",
+            Instruction::RotateA(ShiftOp::Rlc) => "rlca",
+            Instruction::RotateA(ShiftOp::Rrc) => "rrca",
+            Instruction::RotateA(ShiftOp::Rl) => "rla",
+            Instruction::RotateA(_) => "rra",
+            Instruction::AddHl(_) | Instruction::AddSp(_) => "add",
+            Instruction::Stop => "stop",
+            Instruction::Jr(..) => "jr",
+            Instruction::Daa => "daa",
+            Instruction::Cpl => "cpl",
+            Instruction::Scf => "scf",
+            Instruction::Ccf => "ccf",
+            Instruction::Halt => "halt",
+            Instruction::Alu(op, _) | Instruction::AluImm(op, _) => match op {
+                AluOp::Add => "add",
+                AluOp::Adc => "adc",
+                AluOp::Sub => "sub",
+                AluOp::Sbc => "sbc",
+                AluOp::And => "and",
+                AluOp::Xor => "xor",
+                AluOp::Or => "or",
+                AluOp::Cp => "cp",
+            },
+            Instruction::Ret(_) => "ret",
+            Instruction::Reti => "reti",
+            Instruction::Pop(_) => "pop",
+            Instruction::Push(_) => "push",
+            Instruction::Jp(..) | Instruction::JpHl => "jp",
+            Instruction::Call(..) => "call",
+            Instruction::Rst(_) => "rst",
+            Instruction::Di => "di",
+            Instruction::Ei => "ei",
+            Instruction::Shift(op, _) => match op {
+                ShiftOp::Rlc => "rlc",
+                ShiftOp::Rrc => "rrc",
+                ShiftOp::Rl => "rl",
+                ShiftOp::Rr => "rr",
+                ShiftOp::Sla => "sla",
+                ShiftOp::Sra => "sra",
+                ShiftOp::Swap => "swap",
+                ShiftOp::Srl => "srl",
+            },
+            Instruction::Bit(..) => "bit",
+            Instruction::Res(..) => "res",
+            Instruction::Set(..) => "set",
+            Instruction::Illegal(_) => "db",
+        }
+    }
+
+    /// Operands in RGBDS syntax, e.g. `a, [hl+]`; empty when there are none.
+    /// Relative jumps are written against `@`, the address of the
+    /// instruction itself, so the text assembles back to the same bytes.
+    pub fn operands(&self) -> String {
+        match self {
+            Instruction::LdR16Imm(rr, value) => format!("{}, ${value:04X}", reg16(*rr)),
+            Instruction::LdIndirectA(ind) => format!("{}, a", indirect(*ind)),
+            Instruction::LdAIndirect(ind) => format!("a, {}", indirect(*ind)),
+            Instruction::IncR16(rr) | Instruction::DecR16(rr) => reg16(*rr).to_string(),
+            Instruction::IncR8(r) | Instruction::DecR8(r) => reg8(*r).to_string(),
+            Instruction::LdR8Imm(r, value) => format!("{}, ${value:02X}", reg8(*r)),
+            Instruction::LdAddrSp(address) => format!("[${address:04X}], sp"),
+            Instruction::AddHl(rr) => format!("hl, {}", reg16(*rr)),
+            Instruction::Jr(condition, offset) => {
+                let target = format!("@{:+}", *offset as i16 + 2);
+                with_condition(*condition, target)
+            }
+            Instruction::LdR8R8(dst, src) => format!("{}, {}", reg8(*dst), reg8(*src)),
+            Instruction::Alu(op, r) => alu_operands(*op, reg8(*r)),
+            Instruction::AluImm(op, value) => alu_operands(*op, &format!("${value:02X}")),
+            Instruction::Ret(Some(c)) => c.to_string(),
+            Instruction::Pop(rr) | Instruction::Push(rr) => stack_reg(*rr).to_string(),
+            Instruction::Jp(c, address) | Instruction::Call(c, address) => {
+                with_condition(*c, format!("${address:04X}"))
+            }
+            Instruction::JpHl => "hl".to_string(),
+            Instruction::Rst(vector) => format!("${vector:02X}"),
+            Instruction::LdhAddrA(low) => format!("[$FF{low:02X}], a"),
+            Instruction::LdhAAddr(low) => format!("a, [$FF{low:02X}]"),
+            Instruction::LdhCA => "[c], a".to_string(),
+            Instruction::LdhAC => "a, [c]".to_string(),
+            Instruction::LdAddrA(address) => format!("[${address:04X}], a"),
+            Instruction::LdAAddr(address) => format!("a, [${address:04X}]"),
+            Instruction::AddSp(offset) => format!("sp, {offset}"),
+            Instruction::LdHlSpOffset(offset) => format!("hl, sp{offset:+}"),
+            Instruction::LdSpHl => "sp, hl".to_string(),
+            Instruction::Shift(_, r) => reg8(*r).to_string(),
+            Instruction::Bit(bit, r) | Instruction::Res(bit, r) | Instruction::Set(bit, r) => {
+                format!("{bit}, {}", reg8(*r))
+            }
+            Instruction::Illegal(opcode) => format!("${opcode:02X}"),
+            _ => String::new(),
+        }
+    }
+
+    /// What the instruction does to the flags, as listed in opcode tables.
+    pub fn flags(&self) -> FlagEffects {
+        let table = match self {
+            Instruction::IncR8(_) => b"Z0H-",
+            Instruction::DecR8(_) => b"Z1H-",
+            Instruction::RotateA(_) => b"000C",
+            Instruction::AddHl(_) => b"-0HC",
+            Instruction::Daa => b"Z-0C",
+            Instruction::Cpl => b"-11-",
+            Instruction::Scf => b"-001",
+            Instruction::Ccf => b"-00C",
+            Instruction::Alu(op, _) | Instruction::AluImm(op, _) => match op {
+                AluOp::Add | AluOp::Adc => b"Z0HC",
+                AluOp::Sub | AluOp::Sbc | AluOp::Cp => b"Z1HC",
+                AluOp::And => b"Z010",
+                AluOp::Xor | AluOp::Or => b"Z000",
+            },
+            Instruction::Pop(StackReg::AF) => b"ZNHC",
+            Instruction::AddSp(_) | Instruction::LdHlSpOffset(_) => b"00HC",
+            Instruction::Shift(ShiftOp::Swap, _) => b"Z000",
+            Instruction::Shift(..) => b"Z00C",
+            Instruction::Bit(..) => b"Z01-",
+            _ => return FlagEffects::NONE,
+        };
+        FlagEffects::from_table(*table)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operands = self.operands();
+        if operands.is_empty() {
+            write!(f, "{}", self.mnemonic())
+        } else {
+            write!(f, "{} {}", self.mnemonic(), operands)
+        }
+    }
+}
+
+fn reg8(r: Reg8) -> &'static str {
+    match r {
+        Reg8::B => "b",
+        Reg8::C => "c",
+        Reg8::D => "d",
+        Reg8::E => "e",
+        Reg8::H => "h",
+        Reg8::L => "l",
+        Reg8::HlIndirect => "[hl]",
+        Reg8::A => "a",
+    }
+}
+
+fn reg16(rr: Reg16) -> &'static str {
+    match rr {
+        Reg16::BC => "bc",
+        Reg16::DE => "de",
+        Reg16::HL => "hl",
+        Reg16::SP => "sp",
+    }
+}
+
+fn stack_reg(rr: StackReg) -> &'static str {
+    match rr {
+        StackReg::BC => "bc",
+        StackReg::DE => "de",
+        StackReg::HL => "hl",
+        StackReg::AF => "af",
+    }
+}
+
+fn indirect(ind: Indirect) -> &'static str {
+    match ind {
+        Indirect::BC => "[bc]",
+        Indirect::DE => "[de]",
+        Indirect::HlIncrement => "[hl+]",
+        Indirect::HlDecrement => "[hl-]",
+    }
+}
+
+fn with_condition(c: Option<Condition>, target: String) -> String {
+    match c {
+        Some(c) => format!("{c}, {target}"),
+        None => target,
+    }
+}
+
+/// Written the way common disassemblers do: `add a, b`, `adc a, b` and
+/// `sbc a, b` name the accumulator, SUB, the logical ops and CP leave it
+/// implicit. RGBDS accepts both forms.
+fn alu_operands(op: AluOp, operand: &str) -> String {
+    match op {
+        AluOp::Add | AluOp::Adc | AluOp::Sbc => format!("a, {operand}"),
+        _ => operand.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_immediates() {
+        assert_eq!(
+            Instruction::decode_slice(&[0x21, 0x34, 0x12]),
+            Instruction::LdR16Imm(Reg16::HL, 0x1234)
+        );
+        assert_eq!(
+            Instruction::decode_slice(&[0x20, 0xFE]),
+            Instruction::Jr(Some(Condition::NZ), -2)
+        );
+        assert_eq!(
+            Instruction::decode_slice(&[0xDC, 0x00, 0x40]),
+            Instruction::Call(Some(Condition::C), 0x4000)
+        );
+        assert_eq!(
+            Instruction::decode_slice(&[0xCB, 0x7E]),
+            Instruction::Bit(7, Reg8::HlIndirect)
+        );
+    }
+
+    #[test]
+    fn test_decoded_length_matches_bytes_consumed() {
+        // STOP is the exception: its second byte is skipped at execution
+        for opcode in (0..=0xFFu8).filter(|&op| op != 0x10) {
+            let mut consumed = 0;
+            let instruction = Instruction::decode(&mut || {
+                consumed += 1;
+                if consumed == 1 { opcode } else { 0x00 }
+            });
+            assert_eq!(instruction.length(), consumed, "{opcode:#04X}");
+        }
+    }
+
+    #[test]
+    fn test_illegal_opcodes() {
+        let illegal: Vec<u8> = (0..=0xFFu8)
+            .filter(|&op| matches!(Instruction::decode_slice(&[op]), Instruction::Illegal(_)))
+            .collect();
+        assert_eq!(
+            illegal,
+            vec![
+                0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cycles() {
+        let decode = Instruction::decode_slice;
+        assert_eq!(decode(&[0x96]).cycles(false), 2); // SUB (HL)
+        assert_eq!(decode(&[0x86]).cycles(false), 2); // ADD A, (HL)
+        assert_eq!(decode(&[0x34]).cycles(false), 3); // INC (HL)
+        assert_eq!(decode(&[0x08]).cycles(false), 5); // LD (a16), SP
+        assert_eq!(decode(&[0xC0]).cycles(false), 2); // RET NZ
+        assert_eq!(decode(&[0xC0]).cycles(true), 5);
+        assert_eq!(decode(&[0xC3]).cycles(false), 4); // JP is never "not taken"
+        assert_eq!(decode(&[0xCB, 0x46]).cycles(false), 3); // BIT 0, (HL)
+        assert_eq!(decode(&[0xCB, 0x86]).cycles(false), 4); // RES 0, (HL)
+        assert_eq!(decode(&[0xCB, 0x00]).cycles(false), 2); // RLC B
+    }
+
+    #[test]
+    fn test_rgbds_syntax() {
+        let text = |bytes: &[u8]| Instruction::decode_slice(bytes).to_string();
+        assert_eq!(text(&[0x00]), "nop");
+        assert_eq!(text(&[0x2A]), "ld a, [hl+]");
+        assert_eq!(text(&[0x36, 0x12]), "ld [hl], $12");
+        assert_eq!(text(&[0x80]), "add a, b");
+        assert_eq!(text(&[0x97]), "sub a");
+        assert_eq!(text(&[0xFE, 0x90]), "cp $90");
+        assert_eq!(text(&[0x18, 0xFE]), "jr @+0");
+        assert_eq!(text(&[0x38, 0x05]), "jr c, @+7");
+        assert_eq!(text(&[0xC2, 0x50, 0x01]), "jp nz, $0150");
+        assert_eq!(text(&[0xE0, 0x40]), "ldh [$FF40], a");
+        assert_eq!(text(&[0xF2]), "ldh a, [c]");
+        assert_eq!(text(&[0xF8, 0xFE]), "ld hl, sp-2");
+        assert_eq!(text(&[0xE8, 0x10]), "add sp, 16");
+        assert_eq!(text(&[0xF1]), "pop af");
+        assert_eq!(text(&[0xFF]), "rst $38");
+        assert_eq!(text(&[0xCB, 0x37]), "swap a");
+        assert_eq!(text(&[0xCB, 0xFE]), "set 7, [hl]");
+        assert_eq!(text(&[0xD3]), "db $D3");
+    }
+
+    #[test]
+    fn test_flags() {
+        let flags = |bytes: &[u8]| Instruction::decode_slice(bytes).flags().to_string();
+        assert_eq!(flags(&[0x00]), "----");
+        assert_eq!(flags(&[0x3C]), "Z0H-");
+        assert_eq!(flags(&[0xA0]), "Z010");
+        assert_eq!(flags(&[0x07]), "000C");
+        assert_eq!(flags(&[0xCB, 0x40]), "Z01-");
+        assert_eq!(flags(&[0xF1]), "ZNHC");
+        assert_eq!(Instruction::Scf.flags().c, FlagEffect::Set);
+    }
+}
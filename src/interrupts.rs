@@ -0,0 +1,62 @@
+/// The five interrupt sources, each a bit in IE (0xFFFF) and IF (0xFF0F).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank = 0b00001,
+    Stat = 0b00010,
+    Timer = 0b00100,
+    Serial = 0b01000,
+    Joypad = 0b10000,
+}
+
+impl Interrupt {
+    /// All sources from highest to lowest priority.
+    pub const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::Stat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    pub fn bit(self) -> u8 {
+        self as u8
+    }
+
+    /// Address the CPU jumps to when servicing this interrupt.
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x0040,
+            Interrupt::Stat => 0x0048,
+            Interrupt::Timer => 0x0050,
+            Interrupt::Serial => 0x0058,
+            Interrupt::Joypad => 0x0060,
+        }
+    }
+
+    /// The source that wins when several bits of `pending` are set: the
+    /// lowest bit has the highest priority.
+    pub fn highest_priority(pending: u8) -> Option<Interrupt> {
+        Interrupt::ALL
+            .into_iter()
+            .find(|interrupt| pending & interrupt.bit() != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vectors() {
+        let vectors: Vec<u16> = Interrupt::ALL.iter().map(|i| i.vector()).collect();
+        assert_eq!(vectors, vec![0x40, 0x48, 0x50, 0x58, 0x60]);
+    }
+
+    #[test]
+    fn test_highest_priority() {
+        assert_eq!(Interrupt::highest_priority(0x00), None);
+        assert_eq!(Interrupt::highest_priority(0x1F), Some(Interrupt::VBlank));
+        assert_eq!(Interrupt::highest_priority(0x14), Some(Interrupt::Timer));
+        assert_eq!(Interrupt::highest_priority(0xE0), None);
+    }
+}
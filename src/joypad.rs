@@ -0,0 +1,132 @@
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    /// Bit of the button within its group, as it appears in P1.
+    fn line(self) -> u8 {
+        match self {
+            Button::Right | Button::A => 0b0001,
+            Button::Left | Button::B => 0b0010,
+            Button::Up | Button::Select => 0b0100,
+            Button::Down | Button::Start => 0b1000,
+        }
+    }
+    fn is_direction(self) -> bool {
+        matches!(
+            self,
+            Button::Right | Button::Left | Button::Up | Button::Down
+        )
+    }
+}
+
+/// P1/JOYP (0xFF00). Bits 4 and 5 select the direction and action groups
+/// (active low); bits 0-3 read 0 for pressed buttons of the selected groups.
+pub struct Joypad {
+    select: u8,
+    directions: u8,
+    actions: u8,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            select: 0x30,
+            directions: 0,
+            actions: 0,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | (!self.lines() & 0x0F)
+    }
+    pub fn write(&mut self, value: u8) {
+        self.select = value & 0x30;
+    }
+
+    /// Presses `button` and returns whether one of the P1 input lines went
+    /// from high to low, which is what raises the joypad interrupt.
+    pub fn press(&mut self, button: Button) -> bool {
+        let before = self.lines();
+        if button.is_direction() {
+            self.directions |= button.line();
+        } else {
+            self.actions |= button.line();
+        }
+        self.lines() & !before != 0
+    }
+    pub fn release(&mut self, button: Button) {
+        if button.is_direction() {
+            self.directions &= !button.line();
+        } else {
+            self.actions &= !button.line();
+        }
+    }
+
+    /// Whether any P1 input line is currently pulled low, i.e. a button of a
+    /// selected group is held. This is what wakes the CPU from STOP.
+    pub fn any_line_low(&self) -> bool {
+        self.lines() != 0
+    }
+
+    /// Pressed buttons of the selected groups, active high.
+    fn lines(&self) -> u8 {
+        let mut lines = 0;
+        if self.select & 0x10 == 0 {
+            lines |= self.directions;
+        }
+        if self.select & 0x20 == 0 {
+            lines |= self.actions;
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nothing_selected_reads_high() {
+        let mut joypad = Joypad::new();
+        assert!(!joypad.press(Button::Start));
+        assert_eq!(joypad.read(), 0xFF);
+    }
+
+    #[test]
+    fn test_selected_group_reads_pressed_buttons() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x20); // directions
+        assert!(joypad.press(Button::Left));
+        assert!(!joypad.press(Button::A));
+        assert_eq!(joypad.read(), 0xED);
+        joypad.write(0x10); // actions
+        assert_eq!(joypad.read(), 0xDE);
+        joypad.release(Button::A);
+        assert_eq!(joypad.read(), 0xDF);
+        assert!(!joypad.any_line_low());
+    }
+
+    #[test]
+    fn test_press_of_held_line_does_not_interrupt() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x00); // both groups share the lines
+        assert!(joypad.press(Button::Right));
+        assert!(!joypad.press(Button::A));
+        assert!(joypad.any_line_low());
+    }
+}
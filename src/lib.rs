@@ -1,3 +1,15 @@
-mod cpu;
-mod memorybus;
-mod register;
+pub mod apu;
+pub mod boot;
+pub mod cartridge;
+pub mod cpu;
+pub mod disasm;
+pub mod dma;
+pub mod instruction;
+pub mod interrupts;
+pub mod joypad;
+pub mod mbc;
+pub mod memorybus;
+pub mod model;
+pub mod ppu;
+pub mod register;
+pub mod timer;
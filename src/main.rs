@@ -1,3 +1,83 @@
-fn main() {
-    println!("Hello, world!");
+use std::env;
+use std::fs;
+use std::process::ExitCode;
+
+use rustedboy::disasm;
+use rustedboy::disasm::flow::FlowDisassembly;
+
+const USAGE: &str = "usage: rustedboy disasm <rom> [--bank N] [--from ADDR] [--count N]
+       rustedboy disasm <rom> --source";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("disasm") => run_disasm(&args[1..]),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{message}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// `rustedboy disasm <rom> [--bank N] [--from ADDR] [--count N]`
+///
+/// Prints a linear disassembly of `count` instructions (default 64) from
+/// `from` (default the start of the bank, or 0x0100 for bank 0).
+///
+/// `rustedboy disasm <rom> --source` instead prints the whole ROM as RGBDS
+/// source, separating code from data by following control flow.
+fn run_disasm(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut bank = 0;
+    let mut from = None;
+    let mut count = 64;
+    let mut source = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bank" => bank = parse_number(args.next(), "--bank")?,
+            "--from" => from = Some(parse_number(args.next(), "--from")?),
+            "--count" => count = parse_number(args.next(), "--count")?,
+            "--source" => source = true,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => return Err(format!("unexpected argument '{arg}'\n{USAGE}")),
+        }
+    }
+    let path = path.ok_or(USAGE)?;
+    let rom = fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+    if source {
+        print!("{}", FlowDisassembly::analyze(&rom).to_source());
+        return Ok(());
+    }
+
+    let bank = u16::try_from(bank).map_err(|_| format!("bank {bank} out of range"))?;
+    let default_from = if bank == 0 { 0x0100 } else { 0x4000 };
+    let from = u16::try_from(from.unwrap_or(default_from))
+        .map_err(|_| "--from must be a 16-bit address".to_string())?;
+    let lines = disasm::disassemble_rom(&rom, bank, from, count as usize).ok_or_else(|| {
+        format!("{bank:02X}:{from:04X} is not in this ROM (bank 0 is 0x0000-0x3FFF, others 0x4000-0x7FFF)")
+    })?;
+    for line in lines {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// Parses a decimal or `0x`/`$`-prefixed hexadecimal number.
+fn parse_number(value: Option<&String>, flag: &str) -> Result<u32, String> {
+    let value = value.ok_or_else(|| format!("{flag} needs a value"))?;
+    let parsed = if let Some(hex) = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .or_else(|| value.strip_prefix('$'))
+    {
+        u32::from_str_radix(hex, 16)
+    } else {
+        value.parse()
+    };
+    parsed.map_err(|_| format!("{flag}: '{value}' is not a number"))
 }
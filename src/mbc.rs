@@ -0,0 +1,131 @@
+mod camera;
+mod huc1;
+mod huc3;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+pub mod rtc;
+mod tama5;
+
+pub use camera::{CAMERA_HEIGHT, CAMERA_WIDTH, PocketCamera};
+pub use huc1::HuC1;
+pub use huc3::HuC3;
+pub use mbc1::Mbc1;
+pub use mbc2::{MBC2_RAM_SIZE, Mbc2};
+pub use mbc3::Mbc3;
+pub use mbc5::Mbc5;
+pub use mbc6::{MBC6_FLASH_SIZE, Mbc6};
+pub use mbc7::{MBC7_EEPROM_SIZE, Mbc7};
+pub use mmm01::Mmm01;
+pub use rtc::Rtc;
+pub use tama5::{TAMA5_RAM_SIZE, Tama5};
+
+use crate::cartridge::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+/// Memory bank controller: decides which part of the cartridge's ROM and
+/// RAM the CPU sees, and listens to writes in the ROM area to switch banks.
+/// The cartridge owns the memory and lends it on each access.
+pub trait Mbc {
+    /// CPU read from 0x0000-0x7FFF.
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8;
+    /// CPU write to 0x0000-0x7FFF: a control register write.
+    fn write_rom(&mut self, address: u16, value: u8);
+    /// CPU read from 0xA000-0xBFFF.
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8;
+    /// CPU write to 0xA000-0xBFFF. Returns whether it stored anything the
+    /// battery keeps, as opposed to being dropped or hitting a register.
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool;
+    /// ROM bank currently mapped at `address`, for diagnostics.
+    fn rom_bank(&self, address: u16) -> u16;
+    /// Advances on-cartridge clocks by `cycles` base clock cycles (4 MiHz).
+    fn tick(&mut self, _cycles: u32) {}
+    fn rtc(&self) -> Option<&Rtc> {
+        None
+    }
+    /// The new state of the rumble motor if it changed since the last call.
+    fn take_rumble_change(&mut self) -> Option<bool> {
+        None
+    }
+
+    /// Size of the state saved after the cartridge RAM: clocks, flash.
+    fn extra_save_size(&self) -> usize {
+        0
+    }
+    /// That state, with clocks stamped with `now` (seconds since the UNIX
+    /// epoch).
+    fn save_extra(&self, _now: u64) -> Vec<u8> {
+        Vec::new()
+    }
+    /// Restores what `save_extra` wrote, letting clocks catch up to `now`.
+    /// Returns false if `bytes` is not such a state.
+    fn load_extra(&mut self, _bytes: &[u8], _now: u64) -> bool {
+        false
+    }
+    /// Whether state in `save_extra` changed since the last call, for state
+    /// not written through `write_ram`.
+    fn take_dirty(&mut self) -> bool {
+        false
+    }
+
+    /// Host input: tilt of the cartridge in g along X (right) and Y (down).
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+    /// Host input: the picture in front of the camera, 128x112 8-bit gray
+    /// levels row by row, 0 black.
+    fn set_camera_image(&mut self, _image: &[u8]) {}
+}
+
+/// No controller: 32 KiB of ROM and, optionally, up to 8 KiB of RAM that is
+/// always accessible.
+pub struct NoMbc;
+
+impl Mbc for NoMbc {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        rom.get(address as usize).copied().unwrap_or(0xFF)
+    }
+    fn write_rom(&mut self, _address: u16, _value: u8) {}
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        ram_byte(ram, 0, address).map_or(0xFF, |i| ram[i])
+    }
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if let Some(i) = ram_byte(ram, 0, address) {
+            ram[i] = value;
+            return true;
+        }
+        false
+    }
+    fn rom_bank(&self, address: u16) -> u16 {
+        (address >= 0x4000) as u16
+    }
+}
+
+/// A ROM whose banks start with their own number, for mapper tests.
+#[cfg(test)]
+pub(crate) fn numbered_rom(banks: usize) -> Vec<u8> {
+    let mut rom = vec![0; banks * ROM_BANK_SIZE];
+    for bank in 0..banks {
+        rom[bank * ROM_BANK_SIZE] = bank as u8;
+    }
+    rom
+}
+
+/// Byte of ROM bank `bank` at the offset `address` has within its window.
+/// Banks past the end wrap around, as the unused bank lines are not wired.
+fn rom_byte(rom: &[u8], bank: usize, address: u16) -> u8 {
+    if rom.is_empty() {
+        return 0xFF;
+    }
+    rom[(bank * ROM_BANK_SIZE + (address as usize & 0x3FFF)) % rom.len()]
+}
+
+/// Index into `ram` of `address` in RAM bank `bank`, wrapping like
+/// `rom_byte`, or `None` if the cartridge has no RAM.
+fn ram_byte(ram: &[u8], bank: usize, address: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+    Some((bank * RAM_BANK_SIZE + (address as usize & 0x1FFF)) % ram.len())
+}
@@ -0,0 +1,196 @@
+use crate::mbc::{Mbc, ram_byte, rom_byte};
+
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+/// Where a capture lands in RAM bank 0, as 16x14 2bpp tiles.
+const IMAGE_OFFSET: usize = 0x0100;
+/// Registers 0x06-0x35: three thresholds for each cell of a 4x4 matrix.
+const MATRIX_START: usize = 0x06;
+const REGISTER_COUNT: usize = 0x36;
+
+/// Pocket Camera (Game Boy Camera): a 1 MiB ROM, 128 KiB of RAM and a
+/// Mitsubishi M64282FP sensor.
+///
+/// Setting bit 4 of 0x4000-0x5FFF maps the sensor registers at
+/// 0xA000-0xA07F instead of RAM. Setting bit 0 of register 0 starts a
+/// capture of the host image, which is dithered through the threshold
+/// matrix into RAM right away; the bit reads back set until the capture
+/// time derived from the exposure registers has passed. Exposure and the
+/// sensor's edge filters are not applied to the image.
+pub struct PocketCamera {
+    ram_enabled: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    registers: [u8; REGISTER_COUNT],
+    image: Vec<u8>,
+    /// Base clock cycles until the capture in progress finishes
+    busy_cycles: u32,
+}
+
+impl Default for PocketCamera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PocketCamera {
+    pub fn new() -> PocketCamera {
+        PocketCamera {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            registers: [0; REGISTER_COUNT],
+            image: vec![0x80; CAMERA_WIDTH * CAMERA_HEIGHT],
+            busy_cycles: 0,
+        }
+    }
+
+    fn registers_mapped(&self) -> bool {
+        self.ram_bank & 0x10 != 0
+    }
+
+    /// Capture time, from the sensor's clock of 1 MiHz.
+    fn capture_cycles(&self) -> u32 {
+        let exposure = u16::from_be_bytes([self.registers[2], self.registers[3]]) as u32;
+        let n_bit = if self.registers[1] & 0x80 != 0 {
+            0
+        } else {
+            512
+        };
+        4 * (32446 + n_bit + 16 * exposure)
+    }
+
+    /// Color 0-3 of `gray` at (`x`, `y`) through the threshold matrix.
+    fn dither(&self, x: usize, y: usize, gray: u8) -> u8 {
+        let cell = MATRIX_START + ((y % 4) * 4 + x % 4) * 3;
+        let thresholds = &self.registers[cell..cell + 3];
+        match thresholds.iter().position(|&t| gray < t) {
+            Some(i) => 3 - i as u8,
+            None => 0,
+        }
+    }
+
+    fn capture(&self, ram: &mut [u8]) {
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let color = self.dither(x, y, self.image[y * CAMERA_WIDTH + x]);
+                let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let i = IMAGE_OFFSET + tile * 16 + (y % 8) * 2;
+                let bit = 0x80 >> (x % 8);
+                if let Some(row) = ram.get_mut(i..i + 2) {
+                    for (plane, byte) in row.iter_mut().enumerate() {
+                        if color >> plane & 1 != 0 {
+                            *byte |= bit;
+                        } else {
+                            *byte &= !bit;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Mbc for PocketCamera {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => rom_byte(rom, 0, address),
+            _ => rom_byte(rom, self.rom_bank as usize, address),
+        }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            // bank 0 can be mapped here too
+            0x2000..=0x3FFF => self.rom_bank = value & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x1F,
+            _ => {}
+        }
+    }
+    /// RAM reads work even with RAM disabled; only register 0 is readable.
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if self.registers_mapped() {
+            return match address & 0x7F {
+                0x00 => self.registers[0] & 0x06 | (self.busy_cycles > 0) as u8,
+                _ => 0x00,
+            };
+        }
+        ram_byte(ram, self.ram_bank as usize, address).map_or(0xFF, |i| ram[i])
+    }
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if self.registers_mapped() {
+            let register = (address & 0x7F) as usize;
+            if register >= REGISTER_COUNT {
+                return false;
+            }
+            self.registers[register] = value;
+            if register == 0 && value & 0x01 != 0 && self.busy_cycles == 0 {
+                self.capture(ram);
+                self.busy_cycles = self.capture_cycles();
+                return true;
+            }
+            return false;
+        }
+        // the game cannot write RAM while the sensor writes it
+        if !self.ram_enabled || self.busy_cycles > 0 {
+            return false;
+        }
+        if let Some(i) = ram_byte(ram, self.ram_bank as usize, address) {
+            ram[i] = value;
+            return true;
+        }
+        false
+    }
+    fn rom_bank(&self, address: u16) -> u16 {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as u16,
+        }
+    }
+    fn tick(&mut self, cycles: u32) {
+        self.busy_cycles = self.busy_cycles.saturating_sub(cycles);
+    }
+    fn set_camera_image(&mut self, image: &[u8]) {
+        if image.len() == self.image.len() {
+            self.image.copy_from_slice(image);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::RAM_BANK_SIZE;
+
+    #[test]
+    fn test_capture_into_ram() {
+        let mut ram = vec![0; 16 * RAM_BANK_SIZE];
+        let mut mbc = PocketCamera::new();
+        // left half black, right half white
+        let image: Vec<u8> = (0..CAMERA_WIDTH * CAMERA_HEIGHT)
+            .map(|i| if i % CAMERA_WIDTH < 64 { 0x00 } else { 0xFF })
+            .collect();
+        mbc.set_camera_image(&image);
+
+        mbc.write_rom(0x4000, 0x10);
+        for register in 0..16 {
+            for (i, threshold) in [0x40, 0x80, 0xC0].into_iter().enumerate() {
+                mbc.write_ram(&mut ram, 0xA006 + register * 3 + i as u16, threshold);
+            }
+        }
+        mbc.write_ram(&mut ram, 0xA000, 0x01);
+        assert_eq!(mbc.read_ram(&ram, 0xA000) & 0x01, 0x01);
+
+        // first tile black (color 3), ninth white (color 0)
+        assert_eq!(ram[IMAGE_OFFSET..IMAGE_OFFSET + 2], [0xFF, 0xFF]);
+        assert_eq!(
+            ram[IMAGE_OFFSET + 8 * 16..IMAGE_OFFSET + 8 * 16 + 2],
+            [0, 0]
+        );
+
+        mbc.tick(mbc.capture_cycles());
+        assert_eq!(mbc.read_ram(&ram, 0xA000) & 0x01, 0x00);
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(&ram, 0xA100), 0xFF);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::mbc::{Mbc, ram_byte, rom_byte};
+
+/// HuC1 (Hudson), up to 1 MiB of ROM and 32 KiB of RAM, plus an infrared
+/// LED and receiver.
+///
+/// There is no RAM enable: 0x0000-0x1FFF switches 0xA000-0xBFFF between RAM
+/// and the IR port (0x0E). There is no emulated peer, so the receiver never
+/// sees light.
+pub struct HuC1 {
+    ir_mode: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+}
+
+impl Default for HuC1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HuC1 {
+    pub fn new() -> HuC1 {
+        HuC1 {
+            ir_mode: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+}
+
+impl Mbc for HuC1 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => rom_byte(rom, 0, address),
+            _ => rom_byte(rom, self.rom_bank as usize, address),
+        }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ir_mode = value & 0x0F == 0x0E,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x3F).max(1),
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if self.ir_mode {
+            // bit 0 clear would mean light seen
+            return 0xC0;
+        }
+        ram_byte(ram, self.ram_bank as usize, address).map_or(0xFF, |i| ram[i])
+    }
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if self.ir_mode {
+            return false;
+        }
+        if let Some(i) = ram_byte(ram, self.ram_bank as usize, address) {
+            ram[i] = value;
+            return true;
+        }
+        false
+    }
+    fn rom_bank(&self, address: u16) -> u16 {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as u16,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::RAM_BANK_SIZE;
+
+    #[test]
+    fn test_ram_and_ir_mode() {
+        let mut ram = vec![0; 4 * RAM_BANK_SIZE];
+        let mut mbc = HuC1::new();
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_ram(&mut ram, 0xA000, 0x42);
+        assert_eq!(ram[2 * RAM_BANK_SIZE], 0x42);
+        mbc.write_rom(0x0000, 0x0E);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xC0);
+        mbc.write_ram(&mut ram, 0xA000, 0x01);
+        assert_eq!(ram[2 * RAM_BANK_SIZE], 0x42);
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x42);
+        mbc.write_rom(0x2000, 0x3F);
+        assert_eq!(mbc.rom_bank(0x4000), 0x3F);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::mbc::{Mbc, ram_byte, rom_byte};
+
+/// Base clock cycles (4 MiHz) per clock minute.
+const CYCLES_PER_MINUTE: u32 = 4_194_304 * 60;
+const MINUTES_PER_DAY: u16 = 24 * 60;
+/// Saved after the RAM: UNIX timestamp, minute of the day, day counter.
+const CLOCK_SAVE_SIZE: usize = 12;
+
+/// HuC3 (Hudson), up to 2 MiB of ROM and 32 KiB of RAM, with a clock, a
+/// tone generator and an infrared port.
+///
+/// 0x0000-0x1FFF selects what 0xA000-0xBFFF is: RAM (0x0A, or read-only
+/// with 0x00), the command register (0x0B write, 0x0C read), a ready flag
+/// (0x0D) or the IR port (0x0E). Commands are one byte: a 3-bit opcode and
+/// a nibble operating on a 256-nibble memory whose first six nibbles mirror
+/// the clock's minutes and days.
+pub struct HuC3 {
+    mode: u8,
+    rom_bank: u8,
+    ram_bank: u8,
+    memory: [u8; 0x100],
+    address: u8,
+    last_command: u8,
+    result: u8,
+    /// Minute of the day, 0-1439
+    minutes: u16,
+    /// 12-bit day counter
+    days: u16,
+    /// Base clock cycles into the current minute
+    cycles: u32,
+    dirty: bool,
+}
+
+impl Default for HuC3 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HuC3 {
+    pub fn new() -> HuC3 {
+        HuC3 {
+            mode: 0,
+            rom_bank: 1,
+            ram_bank: 0,
+            memory: [0; 0x100],
+            address: 0,
+            last_command: 0,
+            result: 0,
+            minutes: 0,
+            days: 0,
+            cycles: 0,
+            dirty: false,
+        }
+    }
+
+    fn advance_minutes(&mut self, minutes: u64) {
+        let total = self.minutes as u64 + minutes;
+        self.minutes = (total % MINUTES_PER_DAY as u64) as u16;
+        self.days = ((self.days as u64 + total / MINUTES_PER_DAY as u64) & 0xFFF) as u16;
+    }
+
+    fn command(&mut self, value: u8) {
+        let argument = value & 0x0F;
+        self.last_command = (value >> 4) & 0x07;
+        match self.last_command {
+            0x1 => {
+                self.result = self.memory[self.address as usize];
+                self.address = self.address.wrapping_add(1);
+            }
+            0x3 => {
+                self.memory[self.address as usize] = argument;
+                self.address = self.address.wrapping_add(1);
+            }
+            0x4 => self.address = (self.address & 0xF0) | argument,
+            0x5 => self.address = (self.address & 0x0F) | argument << 4,
+            0x6 => match argument {
+                // copy the clock into memory 0x00-0x05, low nibbles first
+                0x0 => {
+                    for i in 0..3 {
+                        self.memory[i] = (self.minutes >> (4 * i)) as u8 & 0x0F;
+                        self.memory[3 + i] = (self.days >> (4 * i)) as u8 & 0x0F;
+                    }
+                }
+                // set the clock from memory 0x00-0x05
+                0x1 => {
+                    let nibbles = |start: usize| {
+                        (0..3).fold(0u16, |value, i| {
+                            value | (self.memory[start + i] as u16) << (4 * i)
+                        })
+                    };
+                    self.minutes = nibbles(0) % MINUTES_PER_DAY;
+                    self.days = nibbles(3);
+                    self.cycles = 0;
+                    self.dirty = true;
+                }
+                // status: always ready
+                0x2 => self.result = 0x1,
+                // tone generator and the rest: no effect
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+impl Mbc for HuC3 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => rom_byte(rom, 0, address),
+            _ => rom_byte(rom, self.rom_bank as usize, address),
+        }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.mode = value & 0x0F,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x7F).max(1),
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {}
+        }
+    }
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        match self.mode {
+            0x00 | 0x0A => ram_byte(ram, self.ram_bank as usize, address).map_or(0xFF, |i| ram[i]),
+            0x0C => (self.last_command << 4) | self.result,
+            0x0D => 0x01,
+            0x0E => 0xC0,
+            _ => 0xFF,
+        }
+    }
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        match self.mode {
+            0x0A => {
+                if let Some(i) = ram_byte(ram, self.ram_bank as usize, address) {
+                    ram[i] = value;
+                    return true;
+                }
+                false
+            }
+            // clock changes are reported through `take_dirty`
+            0x0B => {
+                self.command(value);
+                false
+            }
+            _ => false,
+        }
+    }
+    fn rom_bank(&self, address: u16) -> u16 {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as u16,
+        }
+    }
+    fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles;
+        if self.cycles >= CYCLES_PER_MINUTE {
+            self.cycles -= CYCLES_PER_MINUTE;
+            self.advance_minutes(1);
+        }
+    }
+    fn extra_save_size(&self) -> usize {
+        CLOCK_SAVE_SIZE
+    }
+    fn save_extra(&self, now: u64) -> Vec<u8> {
+        let mut bytes = now.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&self.minutes.to_le_bytes());
+        bytes.extend_from_slice(&self.days.to_le_bytes());
+        bytes
+    }
+    fn load_extra(&mut self, bytes: &[u8], now: u64) -> bool {
+        if bytes.len() != CLOCK_SAVE_SIZE {
+            return false;
+        }
+        let timestamp = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+        self.minutes = u16::from_le_bytes([bytes[8], bytes[9]]) % MINUTES_PER_DAY;
+        self.days = u16::from_le_bytes([bytes[10], bytes[11]]) & 0xFFF;
+        self.cycles = 0;
+        self.advance_minutes(now.saturating_sub(timestamp) / 60);
+        true
+    }
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(mbc: &mut HuC3, value: u8) -> u8 {
+        mbc.write_rom(0x0000, 0x0B);
+        mbc.write_ram(&mut [], 0xA000, value);
+        mbc.write_rom(0x0000, 0x0C);
+        mbc.read_ram(&[], 0xA000)
+    }
+
+    #[test]
+    fn test_set_and_read_clock() {
+        let mut mbc = HuC3::new();
+        // minutes 0x123 = 291, days 0x005
+        for (address, nibble) in [3u8, 2, 1, 5, 0, 0].into_iter().enumerate() {
+            command(&mut mbc, 0x40 | address as u8);
+            command(&mut mbc, 0x30 | nibble);
+        }
+        command(&mut mbc, 0x61);
+        assert!(mbc.take_dirty());
+        mbc.tick(CYCLES_PER_MINUTE);
+
+        command(&mut mbc, 0x60);
+        command(&mut mbc, 0x40);
+        command(&mut mbc, 0x50);
+        let nibbles: Vec<u8> = (0..6).map(|_| command(&mut mbc, 0x10) & 0x0F).collect();
+        assert_eq!(nibbles, vec![4, 2, 1, 5, 0, 0]);
+        assert_eq!(command(&mut mbc, 0x10), 0x10);
+    }
+
+    #[test]
+    fn test_ram_modes() {
+        let mut ram = vec![0; 0x8000];
+        let mut mbc = HuC3::new();
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_ram(&mut ram, 0xA000, 0x42);
+        assert_eq!(ram[0x2000], 0x42);
+        mbc.write_rom(0x0000, 0x00);
+        mbc.write_ram(&mut ram, 0xA000, 0x43);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x42);
+        mbc.write_rom(0x0000, 0x0D);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x01);
+    }
+
+    #[test]
+    fn test_clock_catches_up_on_load() {
+        let mut mbc = HuC3::new();
+        mbc.minutes = MINUTES_PER_DAY - 1;
+        let saved = mbc.save_extra(0);
+        let mut loaded = HuC3::new();
+        assert!(loaded.load_extra(&saved, 2 * 60));
+        assert_eq!((loaded.minutes, loaded.days), (1, 1));
+    }
+}
@@ -0,0 +1,167 @@
+use crate::cartridge::{NINTENDO_LOGO, ROM_BANK_SIZE};
+use crate::mbc::{Mbc, ram_byte, rom_byte};
+
+/// MBC1, up to 2 MiB of ROM and 32 KiB of RAM.
+///
+/// BANK1 (0x2000-0x3FFF) is 5 bits and reads 0 as 1, BANK2 (0x4000-0x5FFF)
+/// is 2 bits that go either above BANK1 or, in mode 1, also select the bank
+/// at 0x0000 and the RAM bank. On MBC1M multicarts BANK1's bit 4 is not
+/// connected and BANK2 sits right above its bit 3.
+pub struct Mbc1 {
+    ram_enabled: bool,
+    bank1: u8,
+    bank2: u8,
+    /// 0x6000-0x7FFF bit 0
+    mode: bool,
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(multicart: bool) -> Mbc1 {
+        Mbc1 {
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            multicart,
+        }
+    }
+
+    /// MBC1M boards are 1 MiB collections of four 256 KiB games; the second
+    /// game's header, with its logo, shows up at bank 0x10.
+    pub fn is_multicart(rom: &[u8]) -> bool {
+        let logo = 0x10 * ROM_BANK_SIZE + 0x104;
+        rom.len() == 64 * ROM_BANK_SIZE && rom[logo..logo + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+    }
+
+    fn bank2_shifted(&self) -> usize {
+        let shift = if self.multicart { 4 } else { 5 };
+        (self.bank2 as usize) << shift
+    }
+    fn low_bank(&self) -> usize {
+        if self.mode { self.bank2_shifted() } else { 0 }
+    }
+    fn high_bank(&self) -> usize {
+        let mask = if self.multicart { 0x0F } else { 0x1F };
+        self.bank2_shifted() | (self.bank1 & mask) as usize
+    }
+    fn ram_bank(&self) -> usize {
+        if self.mode { self.bank2 as usize } else { 0 }
+    }
+}
+
+impl Mbc for Mbc1 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => rom_byte(rom, self.low_bank(), address),
+            _ => rom_byte(rom, self.high_bank(), address),
+        }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.bank1 = (value & 0x1F).max(1),
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+            _ => self.mode = value & 0x01 != 0,
+        }
+    }
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        match ram_byte(ram, self.ram_bank(), address) {
+            Some(i) if self.ram_enabled => ram[i],
+            _ => 0xFF,
+        }
+    }
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if let Some(i) = ram_byte(ram, self.ram_bank(), address).filter(|_| self.ram_enabled) {
+            ram[i] = value;
+            return true;
+        }
+        false
+    }
+    fn rom_bank(&self, address: u16) -> u16 {
+        match address {
+            0x0000..=0x3FFF => self.low_bank() as u16,
+            _ => self.high_bank() as u16,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::RAM_BANK_SIZE;
+    use crate::mbc::numbered_rom;
+
+    #[test]
+    fn test_bank_0_selects_bank_1() {
+        let rom = numbered_rom(8);
+        let mut mbc = Mbc1::new(false);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+        mbc.write_rom(0x3FFF, 0x05);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 5);
+        // only 8 banks: 0x0D wraps to 5
+        mbc.write_rom(0x2000, 0x0D);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 5);
+        // the zero check sees all 5 bits, so 0x20 is bank 0 of the next 32
+        mbc.write_rom(0x2000, 0xE0);
+        assert_eq!(mbc.rom_bank(0x4000), 1);
+    }
+
+    #[test]
+    fn test_bank2_and_mode() {
+        let rom = numbered_rom(128);
+        let mut mbc = Mbc1::new(false);
+        mbc.write_rom(0x2000, 0x00);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x41);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x40);
+        assert_eq!(mbc.rom_bank(0x0000), 0x40);
+    }
+
+    #[test]
+    fn test_ram_enable_and_banking() {
+        let mut ram = vec![0; 4 * RAM_BANK_SIZE];
+        let mut mbc = Mbc1::new(false);
+        mbc.write_ram(&mut ram, 0xA000, 0x11);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+        assert_eq!(ram[0], 0x00);
+
+        mbc.write_rom(0x0000, 0x1A);
+        mbc.write_ram(&mut ram, 0xA000, 0x11);
+        mbc.write_rom(0x4000, 0x02);
+        // mode 0: BANK2 does not affect RAM
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x11);
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_ram(&mut ram, 0xA000, 0x22);
+        assert_eq!(ram[2 * RAM_BANK_SIZE], 0x22);
+
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_multicart_wiring() {
+        let mut rom = numbered_rom(64);
+        let logo = 0x10 * ROM_BANK_SIZE + 0x104;
+        rom[logo..logo + 48].copy_from_slice(&NINTENDO_LOGO);
+        assert!(Mbc1::is_multicart(&rom));
+        assert!(!Mbc1::is_multicart(&numbered_rom(64)));
+
+        let mut mbc = Mbc1::new(true);
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x03);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x13);
+        // bit 4 of BANK1 is not connected
+        mbc.write_rom(0x2000, 0x13);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x13);
+        // 0x10 passes the zero check but maps bank 0 of the game
+        mbc.write_rom(0x2000, 0x10);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x10);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x10);
+    }
+}
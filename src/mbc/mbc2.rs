@@ -0,0 +1,105 @@
+use crate::mbc::{Mbc, rom_byte};
+
+/// Size of MBC2's built-in RAM, in 4-bit cells.
+pub const MBC2_RAM_SIZE: usize = 512;
+
+/// MBC2, up to 256 KiB of ROM and 512x4 bits of RAM inside the controller.
+///
+/// Both registers live at 0x0000-0x3FFF and address bit 8 picks one: clear
+/// for RAM enable, set for the 4-bit ROM bank. The RAM only has 9 address
+/// lines, so it repeats across 0xA000-0xBFFF, and its upper nibbles read 1.
+pub struct Mbc2 {
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Default for Mbc2 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mbc2 {
+    pub fn new() -> Mbc2 {
+        Mbc2 {
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => rom_byte(rom, 0, address),
+            _ => rom_byte(rom, self.rom_bank as usize, address),
+        }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x3FFF if address & 0x0100 == 0 => self.ram_enabled = value & 0x0F == 0x0A,
+            0x0000..=0x3FFF => self.rom_bank = (value & 0x0F).max(1),
+            _ => {}
+        }
+    }
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        match ram.get(address as usize % MBC2_RAM_SIZE) {
+            Some(value) if self.ram_enabled => 0xF0 | value,
+            _ => 0xFF,
+        }
+    }
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if let Some(cell) = ram.get_mut(address as usize % MBC2_RAM_SIZE)
+            && self.ram_enabled
+        {
+            *cell = value & 0x0F;
+            return true;
+        }
+        false
+    }
+    fn rom_bank(&self, address: u16) -> u16 {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as u16,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::ROM_BANK_SIZE;
+
+    #[test]
+    fn test_address_bit_8_selects_register() {
+        let mut rom = vec![0; 16 * ROM_BANK_SIZE];
+        rom[5 * ROM_BANK_SIZE] = 0x55;
+        let mut mbc = Mbc2::new();
+        // bit 8 clear: RAM enable, not a bank switch
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.rom_bank(0x4000), 1);
+        mbc.write_rom(0x2100, 0x05);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x55);
+        mbc.write_rom(0x0100, 0x00);
+        assert_eq!(mbc.rom_bank(0x4000), 1);
+        // 0x4000-0x7FFF has no registers
+        mbc.write_rom(0x4100, 0x03);
+        assert_eq!(mbc.rom_bank(0x4000), 1);
+    }
+
+    #[test]
+    fn test_half_byte_ram_echoes() {
+        let mut ram = vec![0; MBC2_RAM_SIZE];
+        let mut mbc = Mbc2::new();
+        mbc.write_ram(&mut ram, 0xA000, 0x12);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(&mut ram, 0xA000, 0x12);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xF2);
+        assert_eq!(mbc.read_ram(&ram, 0xA200), 0xF2);
+        assert_eq!(mbc.read_ram(&ram, 0xBE00), 0xF2);
+        mbc.write_ram(&mut ram, 0xBFFF, 0x0C);
+        assert_eq!(mbc.read_ram(&ram, 0xA1FF), 0xFC);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::mbc::rtc::{RTC_SAVE_SIZE, Rtc};
+use crate::mbc::{Mbc, ram_byte, rom_byte};
+
+/// MBC3, up to 2 MiB of ROM and 32 KiB of RAM, optionally with an RTC.
+/// MBC30 (Pokémon Crystal in Japan) is the same chip with one more ROM and
+/// RAM bank bit, for 4 MiB and 64 KiB.
+///
+/// 0x4000-0x5FFF selects a RAM bank (0x00-0x07) or an RTC register
+/// (0x08-0x0C) at 0xA000-0xBFFF. Writing 0x00 then 0x01 to 0x6000-0x7FFF
+/// latches the clock.
+pub struct Mbc3 {
+    ram_enabled: bool,
+    rom_bank: u8,
+    ram_select: u8,
+    last_latch_write: u8,
+    mbc30: bool,
+    rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    pub fn new(mbc30: bool, has_rtc: bool) -> Mbc3 {
+        Mbc3 {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            last_latch_write: 0xFF,
+            mbc30,
+            rtc: has_rtc.then(Rtc::new),
+        }
+    }
+
+    fn ram_bank(&self) -> Option<usize> {
+        let banks = if self.mbc30 { 0x08 } else { 0x04 };
+        (self.ram_select < banks).then_some(self.ram_select as usize)
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => rom_byte(rom, 0, address),
+            _ => rom_byte(rom, self.rom_bank as usize, address),
+        }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                let mask = if self.mbc30 { 0xFF } else { 0x7F };
+                self.rom_bank = (value & mask).max(1);
+            }
+            0x4000..=0x5FFF => self.ram_select = value & 0x0F,
+            _ => {
+                if self.last_latch_write == 0x00
+                    && value == 0x01
+                    && let Some(rtc) = &mut self.rtc
+                {
+                    rtc.latch();
+                }
+                self.last_latch_write = value;
+            }
+        }
+    }
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match (self.ram_bank(), &self.rtc) {
+            (Some(bank), _) => ram_byte(ram, bank, address).map_or(0xFF, |i| ram[i]),
+            (None, Some(rtc)) if (0x08..=0x0C).contains(&self.ram_select) => {
+                rtc.read(self.ram_select)
+            }
+            _ => 0xFF,
+        }
+    }
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+        match (self.ram_bank(), &mut self.rtc) {
+            (Some(bank), _) => {
+                if let Some(i) = ram_byte(ram, bank, address) {
+                    ram[i] = value;
+                    return true;
+                }
+                false
+            }
+            (None, Some(rtc)) if (0x08..=0x0C).contains(&self.ram_select) => {
+                rtc.write(self.ram_select, value);
+                true
+            }
+            _ => false,
+        }
+    }
+    fn rom_bank(&self, address: u16) -> u16 {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as u16,
+        }
+    }
+    fn tick(&mut self, cycles: u32) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(cycles);
+        }
+    }
+    fn rtc(&self) -> Option<&Rtc> {
+        self.rtc.as_ref()
+    }
+    fn extra_save_size(&self) -> usize {
+        self.rtc.as_ref().map_or(0, |_| RTC_SAVE_SIZE)
+    }
+    fn save_extra(&self, now: u64) -> Vec<u8> {
+        self.rtc
+            .as_ref()
+            .map_or(Vec::new(), |rtc| rtc.save(now).to_vec())
+    }
+    fn load_extra(&mut self, bytes: &[u8], now: u64) -> bool {
+        match (&mut self.rtc, Rtc::load(bytes, now)) {
+            (Some(rtc), Some(loaded)) => {
+                *rtc = loaded;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+    #[test]
+    fn test_rom_and_ram_banking() {
+        let mut rom = vec![0; 128 * ROM_BANK_SIZE];
+        rom[0x45 * ROM_BANK_SIZE] = 0x45;
+        let mut ram = vec![0; 4 * RAM_BANK_SIZE];
+        let mut mbc = Mbc3::new(false, false);
+        mbc.write_rom(0x2000, 0x45);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x45);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.rom_bank(0x4000), 1);
+        // 7 bits: 0x80 is bank 0, read as 1
+        mbc.write_rom(0x2000, 0x80);
+        assert_eq!(mbc.rom_bank(0x4000), 1);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x03);
+        mbc.write_ram(&mut ram, 0xA010, 0x33);
+        assert_eq!(ram[3 * RAM_BANK_SIZE + 0x10], 0x33);
+        // no RTC: its registers are open bus
+        mbc.write_rom(0x4000, 0x08);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_rtc_registers_and_latch() {
+        let ram = vec![];
+        let mut mbc = Mbc3::new(false, true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x09);
+        mbc.write_ram(&mut [], 0xA000, 42);
+        // not latched yet
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 42);
+
+        mbc.tick(60 * 4_194_304);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 43);
+
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+    }
+}
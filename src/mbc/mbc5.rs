@@ -0,0 +1,126 @@
+use crate::mbc::{Mbc, ram_byte, rom_byte};
+
+/// MBC5, up to 8 MiB of ROM and 128 KiB of RAM.
+///
+/// The 9-bit ROM bank is split between 0x2000-0x2FFF (low 8 bits) and
+/// 0x3000-0x3FFF (bit 8), and unlike older MBCs bank 0 can be mapped at
+/// 0x4000. On rumble cartridges, bit 3 of the RAM bank register drives the
+/// motor instead of a RAM address line.
+pub struct Mbc5 {
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    motor: bool,
+    /// The motor turned on or off since the host last asked
+    motor_changed: bool,
+}
+
+impl Mbc5 {
+    pub fn new(has_rumble: bool) -> Mbc5 {
+        Mbc5 {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            motor: false,
+            motor_changed: false,
+        }
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => rom_byte(rom, 0, address),
+            _ => rom_byte(rom, self.rom_bank as usize, address),
+        }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | (value as u16 & 1) << 8,
+            0x4000..=0x5FFF if self.has_rumble => {
+                self.ram_bank = value & 0x07;
+                let motor = value & 0x08 != 0;
+                self.motor_changed |= motor != self.motor;
+                self.motor = motor;
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {}
+        }
+    }
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        match ram_byte(ram, self.ram_bank as usize, address) {
+            Some(i) if self.ram_enabled => ram[i],
+            _ => 0xFF,
+        }
+    }
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if let Some(i) = ram_byte(ram, self.ram_bank as usize, address).filter(|_| self.ram_enabled)
+        {
+            ram[i] = value;
+            return true;
+        }
+        false
+    }
+    fn rom_bank(&self, address: u16) -> u16 {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank,
+        }
+    }
+    fn take_rumble_change(&mut self) -> Option<bool> {
+        std::mem::take(&mut self.motor_changed).then_some(self.motor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+    #[test]
+    fn test_nine_bit_rom_bank() {
+        let mut rom = vec![0; 512 * ROM_BANK_SIZE];
+        rom[0x1A5 * ROM_BANK_SIZE] = 0xA5;
+        let mut mbc = Mbc5::new(false);
+        mbc.write_rom(0x2000, 0xA5);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0xA5);
+        assert_eq!(mbc.rom_bank(0x4000), 0x1A5);
+        // bank 0 is not turned into 1
+        mbc.write_rom(0x2000, 0x00);
+        mbc.write_rom(0x3FFF, 0x00);
+        assert_eq!(mbc.rom_bank(0x4000), 0);
+    }
+
+    #[test]
+    fn test_sixteen_ram_banks() {
+        let mut ram = vec![0; 16 * RAM_BANK_SIZE];
+        let mut mbc = Mbc5::new(false);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x0F);
+        mbc.write_ram(&mut ram, 0xA000, 0x0F);
+        assert_eq!(ram[15 * RAM_BANK_SIZE], 0x0F);
+        assert_eq!(mbc.take_rumble_change(), None);
+    }
+
+    #[test]
+    fn test_rumble_motor() {
+        let mut ram = vec![0; 8 * RAM_BANK_SIZE];
+        let mut mbc = Mbc5::new(true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x0B);
+        assert_eq!(mbc.take_rumble_change(), Some(true));
+        assert_eq!(mbc.take_rumble_change(), None);
+        // bit 3 is not a RAM address line
+        mbc.write_ram(&mut ram, 0xA000, 0x03);
+        assert_eq!(ram[3 * RAM_BANK_SIZE], 0x03);
+        mbc.write_rom(0x4000, 0x0B);
+        assert_eq!(mbc.take_rumble_change(), None);
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mbc.take_rumble_change(), Some(false));
+    }
+}
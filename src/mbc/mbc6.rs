@@ -0,0 +1,301 @@
+use crate::mbc::Mbc;
+
+const HALF_BANK: usize = 0x2000;
+const RAM_HALF_BANK: usize = 0x1000;
+pub const MBC6_FLASH_SIZE: usize = 0x100000;
+/// Erase granularity. The chip's smaller boot-block sectors are not modelled.
+const FLASH_SECTOR_SIZE: usize = 0x10000;
+/// What the flash answers in ID mode at offsets 0 and 1 (Macronix, MX29F008).
+const FLASH_ID: [u8; 2] = [0xC2, 0x81];
+
+/// Where the flash is in its command sequence. Commands start with 0xAA at
+/// 0x5555 and 0x55 at 0x2AAA, flash-relative.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum FlashState {
+    Idle,
+    Unlock1,
+    Unlock2,
+    Program,
+    EraseUnlock,
+    Erase1,
+    Erase2,
+}
+
+/// MBC6 (Net de Get), with 1 MiB of flash next to the ROM.
+///
+/// 0x4000-0x5FFF and 0x6000-0x7FFF are two independent 8 KiB windows, each
+/// showing a ROM or flash bank; likewise 0xA000-0xAFFF and 0xB000-0xBFFF
+/// show two 4 KiB RAM banks. The flash is saved with the RAM.
+pub struct Mbc6 {
+    ram_enabled: bool,
+    ram_banks: [u8; 2],
+    rom_banks: [u8; 2],
+    /// Whether each ROM window shows flash
+    window_flash: [bool; 2],
+    flash_enabled: bool,
+    flash_write_enabled: bool,
+    flash: Vec<u8>,
+    flash_state: FlashState,
+    flash_id_mode: bool,
+    dirty: bool,
+}
+
+impl Default for Mbc6 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mbc6 {
+    pub fn new() -> Mbc6 {
+        Mbc6 {
+            ram_enabled: false,
+            ram_banks: [0; 2],
+            rom_banks: [0; 2],
+            window_flash: [false; 2],
+            flash_enabled: false,
+            flash_write_enabled: false,
+            flash: vec![0xFF; MBC6_FLASH_SIZE],
+            flash_state: FlashState::Idle,
+            flash_id_mode: false,
+            dirty: false,
+        }
+    }
+
+    fn window(address: u16) -> usize {
+        ((address - 0x4000) as usize) / HALF_BANK
+    }
+    fn flash_address(&self, window: usize, address: u16) -> usize {
+        (self.rom_banks[window] as usize * HALF_BANK + (address as usize % HALF_BANK))
+            % MBC6_FLASH_SIZE
+    }
+
+    fn write_flash(&mut self, address: usize, value: u8) {
+        let command_address = address & 0x7FFF;
+        self.flash_state = match (self.flash_state, command_address, value) {
+            // the data byte of a program command, even 0xF0
+            (FlashState::Program, _, _) => {
+                if self.flash_write_enabled {
+                    // programming can only clear bits
+                    self.flash[address] &= value;
+                    self.dirty = true;
+                }
+                FlashState::Idle
+            }
+            (_, _, 0xF0) => {
+                self.flash_id_mode = false;
+                FlashState::Idle
+            }
+            (FlashState::Idle, 0x5555, 0xAA) => FlashState::Unlock1,
+            (FlashState::Unlock1, 0x2AAA, 0x55) => FlashState::Unlock2,
+            (FlashState::Unlock2, 0x5555, 0xA0) => FlashState::Program,
+            (FlashState::Unlock2, 0x5555, 0x80) => FlashState::EraseUnlock,
+            (FlashState::Unlock2, 0x5555, 0x90) => {
+                self.flash_id_mode = true;
+                FlashState::Idle
+            }
+            (FlashState::EraseUnlock, 0x5555, 0xAA) => FlashState::Erase1,
+            (FlashState::Erase1, 0x2AAA, 0x55) => FlashState::Erase2,
+            (FlashState::Erase2, _, 0x30) => {
+                if self.flash_write_enabled {
+                    let start = address / FLASH_SECTOR_SIZE * FLASH_SECTOR_SIZE;
+                    self.flash[start..start + FLASH_SECTOR_SIZE].fill(0xFF);
+                    self.dirty = true;
+                }
+                FlashState::Idle
+            }
+            (FlashState::Erase2, 0x5555, 0x10) => {
+                if self.flash_write_enabled {
+                    self.flash.fill(0xFF);
+                    self.dirty = true;
+                }
+                FlashState::Idle
+            }
+            _ => FlashState::Idle,
+        };
+    }
+
+    /// Index into `ram` of `address` in the 4 KiB bank of its window.
+    fn ram_index(&self, ram: &[u8], address: u16) -> Option<usize> {
+        if ram.is_empty() || !self.ram_enabled {
+            return None;
+        }
+        let window = ((address - 0xA000) as usize) / RAM_HALF_BANK;
+        let bank = self.ram_banks[window] as usize;
+        Some((bank * RAM_HALF_BANK + address as usize % RAM_HALF_BANK) % ram.len())
+    }
+}
+
+impl Mbc for Mbc6 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        if address < 0x4000 {
+            return rom.get(address as usize).copied().unwrap_or(0xFF);
+        }
+        let window = Mbc6::window(address);
+        if self.window_flash[window] {
+            if !self.flash_enabled {
+                return 0xFF;
+            }
+            let flash_address = self.flash_address(window, address);
+            if self.flash_id_mode {
+                return FLASH_ID.get(flash_address & 0x01).copied().unwrap_or(0xFF);
+            }
+            return self.flash[flash_address];
+        }
+        if rom.is_empty() {
+            return 0xFF;
+        }
+        rom[(self.rom_banks[window] as usize * HALF_BANK + address as usize % HALF_BANK)
+            % rom.len()]
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x03FF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x0400..=0x07FF => self.ram_banks[0] = value & 0x07,
+            0x0800..=0x0BFF => self.ram_banks[1] = value & 0x07,
+            0x0C00..=0x0FFF => self.flash_enabled = value & 0x01 != 0,
+            0x1000 => self.flash_write_enabled = value & 0x01 != 0,
+            0x2000..=0x27FF => self.rom_banks[0] = value & 0x7F,
+            0x2800..=0x2FFF => self.window_flash[0] = value == 0x08,
+            0x3000..=0x37FF => self.rom_banks[1] = value & 0x7F,
+            0x3800..=0x3FFF => self.window_flash[1] = value == 0x08,
+            0x4000..=0x7FFF => {
+                let window = Mbc6::window(address);
+                if self.window_flash[window] && self.flash_enabled {
+                    let flash_address = self.flash_address(window, address);
+                    self.write_flash(flash_address, value);
+                }
+            }
+            _ => {}
+        }
+    }
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        self.ram_index(ram, address).map_or(0xFF, |i| ram[i])
+    }
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if let Some(i) = self.ram_index(ram, address) {
+            ram[i] = value;
+            return true;
+        }
+        false
+    }
+    /// In 8 KiB units, as MBC6 counts them.
+    fn rom_bank(&self, address: u16) -> u16 {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_banks[Mbc6::window(address)] as u16,
+        }
+    }
+    fn extra_save_size(&self) -> usize {
+        MBC6_FLASH_SIZE
+    }
+    fn save_extra(&self, _now: u64) -> Vec<u8> {
+        self.flash.clone()
+    }
+    fn load_extra(&mut self, bytes: &[u8], _now: u64) -> bool {
+        if bytes.len() != MBC6_FLASH_SIZE {
+            return false;
+        }
+        self.flash.copy_from_slice(bytes);
+        true
+    }
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Maps flash bank `bank` at 0x4000 and sends the unlock sequence,
+    /// which lives in bank 2 (0x5555) and bank 1 (0x2AAA).
+    fn unlock(mbc: &mut Mbc6) {
+        mbc.write_rom(0x2000, 0x02);
+        mbc.write_rom(0x5555, 0xAA);
+        mbc.write_rom(0x2000, 0x01);
+        mbc.write_rom(0x4AAA, 0x55);
+        mbc.write_rom(0x2000, 0x02);
+    }
+
+    fn flash_mbc() -> Mbc6 {
+        let mut mbc = Mbc6::new();
+        mbc.write_rom(0x0C00, 0x01);
+        mbc.write_rom(0x1000, 0x01);
+        mbc.write_rom(0x2800, 0x08);
+        mbc
+    }
+
+    #[test]
+    fn test_independent_rom_windows() {
+        let mut rom = vec![0; 16 * HALF_BANK];
+        rom[5 * HALF_BANK] = 5;
+        rom[9 * HALF_BANK] = 9;
+        let mut mbc = Mbc6::new();
+        mbc.write_rom(0x2000, 0x05);
+        mbc.write_rom(0x3000, 0x09);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 5);
+        assert_eq!(mbc.read_rom(&rom, 0x6000), 9);
+        // flash selected but disabled
+        mbc.write_rom(0x3800, 0x08);
+        assert_eq!(mbc.read_rom(&rom, 0x6000), 0xFF);
+    }
+
+    #[test]
+    fn test_flash_program_and_erase() {
+        let mut mbc = flash_mbc();
+        unlock(&mut mbc);
+        mbc.write_rom(0x5555, 0xA0);
+        mbc.write_rom(0x2000, 0x03);
+        mbc.write_rom(0x4010, 0x5A);
+        assert!(mbc.take_dirty());
+        assert_eq!(mbc.read_rom(&[], 0x4010), 0x5A);
+        assert_eq!(mbc.flash[3 * HALF_BANK + 0x10], 0x5A);
+
+        // without the command sequence, writes are ignored
+        mbc.write_rom(0x4010, 0x00);
+        assert_eq!(mbc.read_rom(&[], 0x4010), 0x5A);
+
+        unlock(&mut mbc);
+        mbc.write_rom(0x5555, 0x80);
+        unlock(&mut mbc);
+        mbc.write_rom(0x2000, 0x03);
+        mbc.write_rom(0x4000, 0x30);
+        assert_eq!(mbc.flash[3 * HALF_BANK + 0x10], 0xFF);
+    }
+
+    #[test]
+    fn test_flash_programs_0xf0() {
+        let mut mbc = flash_mbc();
+        unlock(&mut mbc);
+        mbc.write_rom(0x5555, 0xA0);
+        mbc.write_rom(0x2000, 0x03);
+        mbc.write_rom(0x4010, 0xF0);
+        assert_eq!(mbc.flash[3 * HALF_BANK + 0x10], 0xF0);
+    }
+
+    #[test]
+    fn test_flash_id_mode() {
+        let mut mbc = flash_mbc();
+        unlock(&mut mbc);
+        mbc.write_rom(0x5555, 0x90);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(&[], 0x4000), 0xC2);
+        assert_eq!(mbc.read_rom(&[], 0x4001), 0x81);
+        mbc.write_rom(0x4000, 0xF0);
+        assert_eq!(mbc.read_rom(&[], 0x4000), 0xFF);
+    }
+
+    #[test]
+    fn test_split_ram_windows() {
+        let mut ram = vec![0; 0x8000];
+        let mut mbc = Mbc6::new();
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x0400, 0x02);
+        mbc.write_rom(0x0800, 0x07);
+        mbc.write_ram(&mut ram, 0xA000, 0x22);
+        mbc.write_ram(&mut ram, 0xB000, 0x77);
+        assert_eq!(ram[2 * RAM_HALF_BANK], 0x22);
+        assert_eq!(ram[7 * RAM_HALF_BANK], 0x77);
+    }
+}
@@ -0,0 +1,361 @@
+use crate::mbc::{Mbc, rom_byte};
+
+/// The EEPROM, 128 16-bit words kept in the cartridge RAM, low byte first.
+pub const MBC7_EEPROM_SIZE: usize = 256;
+/// Accelerometer reading when level.
+const ACCEL_CENTER: f32 = 0x81D0 as f32;
+/// Accelerometer counts per g.
+const ACCEL_PER_G: f32 = 0x70 as f32;
+
+/// Where the 93LC56 EEPROM is in a transfer. Commands are a start bit, a
+/// 2-bit opcode and 8 address bits, shifted in MSB first on CLK rising edges.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum EepromState {
+    /// Waiting for the start bit
+    Idle,
+    Command {
+        value: u16,
+        bits: u8,
+    },
+    /// Shifting out words from `address`; `remaining` bits of it are left
+    Read {
+        address: u8,
+        remaining: u8,
+    },
+    /// Shifting in a word; `address` is `None` for WRAL
+    Write {
+        address: Option<u8>,
+        value: u16,
+        bits: u8,
+    },
+    /// Command done, waiting for CS to drop
+    Done,
+}
+
+struct Eeprom {
+    cs: bool,
+    clk: bool,
+    di: bool,
+    do_: bool,
+    state: EepromState,
+    write_enabled: bool,
+    /// A word was stored since `Mbc7::write_ram` last checked
+    written: bool,
+}
+
+impl Eeprom {
+    fn new() -> Eeprom {
+        Eeprom {
+            cs: false,
+            clk: false,
+            di: false,
+            do_: true,
+            state: EepromState::Idle,
+            write_enabled: false,
+            written: false,
+        }
+    }
+
+    fn word(ram: &[u8], address: u8) -> u16 {
+        let i = address as usize * 2;
+        match ram.get(i..i + 2) {
+            Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]),
+            None => 0xFFFF,
+        }
+    }
+    fn set_word(&mut self, ram: &mut [u8], address: u8, value: u16) {
+        let i = address as usize * 2;
+        if self.write_enabled
+            && let Some(bytes) = ram.get_mut(i..i + 2)
+        {
+            bytes.copy_from_slice(&value.to_le_bytes());
+            self.written = true;
+        }
+    }
+
+    fn read(&self) -> u8 {
+        (self.cs as u8) << 7 | (self.clk as u8) << 6 | (self.di as u8) << 1 | self.do_ as u8
+    }
+
+    fn write(&mut self, ram: &mut [u8], value: u8) {
+        let cs = value & 0x80 != 0;
+        let clk = value & 0x40 != 0;
+        self.di = value & 0x02 != 0;
+        if !cs {
+            self.state = EepromState::Idle;
+            self.do_ = true;
+        } else if clk && !self.clk && self.cs {
+            self.rising_edge(ram);
+        }
+        self.cs = cs;
+        self.clk = clk;
+    }
+
+    fn rising_edge(&mut self, ram: &mut [u8]) {
+        let di = self.di as u16;
+        self.state = match self.state {
+            EepromState::Idle if self.di => EepromState::Command { value: 0, bits: 0 },
+            EepromState::Idle => EepromState::Idle,
+            EepromState::Command { value, bits } => {
+                let value = value << 1 | di;
+                if bits + 1 < 10 {
+                    EepromState::Command {
+                        value,
+                        bits: bits + 1,
+                    }
+                } else {
+                    self.command(ram, value)
+                }
+            }
+            EepromState::Read { address, remaining } => {
+                // sequential read: carry on with the next word
+                let (address, remaining) = match remaining {
+                    0 => (address.wrapping_add(1) & 0x7F, 16),
+                    _ => (address, remaining),
+                };
+                self.do_ = Eeprom::word(ram, address) >> (remaining - 1) & 1 != 0;
+                EepromState::Read {
+                    address,
+                    remaining: remaining - 1,
+                }
+            }
+            EepromState::Write {
+                address,
+                value,
+                bits,
+            } => {
+                let value = value << 1 | di;
+                if bits + 1 < 16 {
+                    EepromState::Write {
+                        address,
+                        value,
+                        bits: bits + 1,
+                    }
+                } else {
+                    match address {
+                        Some(address) => self.set_word(ram, address, value),
+                        None => (0..0x80).for_each(|a| self.set_word(ram, a, value)),
+                    }
+                    self.do_ = true;
+                    EepromState::Done
+                }
+            }
+            EepromState::Done => EepromState::Done,
+        };
+    }
+
+    fn command(&mut self, ram: &mut [u8], command: u16) -> EepromState {
+        let address = (command & 0x7F) as u8;
+        match (command >> 8, (command >> 6) & 0x03) {
+            (0b10, _) => {
+                // a dummy zero comes before the data
+                self.do_ = false;
+                EepromState::Read {
+                    address,
+                    remaining: 16,
+                }
+            }
+            (0b01, _) => EepromState::Write {
+                address: Some(address),
+                value: 0,
+                bits: 0,
+            },
+            (0b11, _) => {
+                self.set_word(ram, address, 0xFFFF);
+                EepromState::Done
+            }
+            (_, 0b11) => {
+                self.write_enabled = true;
+                EepromState::Done
+            }
+            (_, 0b00) => {
+                self.write_enabled = false;
+                EepromState::Done
+            }
+            (_, 0b10) => {
+                (0..0x80).for_each(|a| self.set_word(ram, a, 0xFFFF));
+                EepromState::Done
+            }
+            _ => EepromState::Write {
+                address: None,
+                value: 0,
+                bits: 0,
+            },
+        }
+    }
+}
+
+/// MBC7 (Kirby Tilt 'n' Tumble, Command Master), with a two-axis
+/// accelerometer and a 93LC56 serial EEPROM instead of RAM.
+///
+/// 0xA000-0xAFFF is enabled by 0x0A at 0x0000-0x1FFF and 0x40 at
+/// 0x4000-0x5FFF, and address bits 4-7 pick a register: writing 0x55 then
+/// 0xAA to Ax0x/Ax1x latches the tilt into Ax2x-Ax5x; Ax8x drives the
+/// EEPROM pins (CS bit 7, CLK bit 6, DI bit 1, DO bit 0).
+pub struct Mbc7 {
+    ram_enabled: [bool; 2],
+    rom_bank: u8,
+    tilt: (f32, f32),
+    latched: (u16, u16),
+    latch_ready: bool,
+    eeprom: Eeprom,
+}
+
+impl Default for Mbc7 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mbc7 {
+    pub fn new() -> Mbc7 {
+        Mbc7 {
+            ram_enabled: [false; 2],
+            rom_bank: 1,
+            tilt: (0.0, 0.0),
+            latched: (0x8000, 0x8000),
+            latch_ready: false,
+            eeprom: Eeprom::new(),
+        }
+    }
+
+    fn enabled(&self, address: u16) -> bool {
+        self.ram_enabled == [true; 2] && address < 0xB000
+    }
+}
+
+impl Mbc for Mbc7 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => rom_byte(rom, 0, address),
+            _ => rom_byte(rom, self.rom_bank as usize, address),
+        }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled[0] = value == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.ram_enabled[1] = value == 0x40,
+            _ => {}
+        }
+    }
+    fn read_ram(&self, _ram: &[u8], address: u16) -> u8 {
+        if !self.enabled(address) {
+            return 0xFF;
+        }
+        let (x, y) = self.latched;
+        match (address >> 4) & 0x0F {
+            0x2 => x as u8,
+            0x3 => (x >> 8) as u8,
+            0x4 => y as u8,
+            0x5 => (y >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if !self.enabled(address) {
+            return false;
+        }
+        match (address >> 4) & 0x0F {
+            0x0 if value == 0x55 => {
+                self.latched = (0x8000, 0x8000);
+                self.latch_ready = true;
+            }
+            0x1 if value == 0xAA && self.latch_ready => {
+                let axis = |g: f32| (ACCEL_CENTER + g * ACCEL_PER_G).clamp(0.0, 65535.0) as u16;
+                self.latched = (axis(self.tilt.0), axis(self.tilt.1));
+                self.latch_ready = false;
+            }
+            0x8 => self.eeprom.write(ram, value),
+            _ => {}
+        }
+        std::mem::take(&mut self.eeprom.written)
+    }
+    fn rom_bank(&self, address: u16) -> u16 {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as u16,
+        }
+    }
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled_mbc() -> Mbc7 {
+        let mut mbc = Mbc7::new();
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x40);
+        mbc
+    }
+
+    /// Clocks `bits` (MSB first) into the EEPROM, returning DO after each.
+    fn clock_bits(mbc: &mut Mbc7, ram: &mut [u8], bits: u32, count: u8) -> u32 {
+        let mut out = 0;
+        for i in (0..count).rev() {
+            let di = ((bits >> i) & 1) as u8 * 0x02;
+            mbc.write_ram(ram, 0xA080, 0x80 | di);
+            mbc.write_ram(ram, 0xA080, 0xC0 | di);
+            out = out << 1 | (mbc.read_ram(ram, 0xA080) & 0x01) as u32;
+        }
+        out
+    }
+
+    /// Sends the start bit, `opcode` and `address`, returning the last DO.
+    fn send_command(mbc: &mut Mbc7, ram: &mut [u8], opcode: u32, address: u32) -> u32 {
+        clock_bits(mbc, ram, 1 << 10 | opcode << 8 | address, 11) & 1
+    }
+
+    fn deselect(mbc: &mut Mbc7, ram: &mut [u8]) {
+        mbc.write_ram(ram, 0xA080, 0x00);
+    }
+
+    #[test]
+    fn test_accelerometer_latch() {
+        let mut mbc = enabled_mbc();
+        mbc.set_tilt(1.0, -0.5);
+        // no latch yet
+        assert_eq!(mbc.read_ram(&[], 0xA020), 0x00);
+        mbc.write_ram(&mut [], 0xA000, 0x55);
+        mbc.write_ram(&mut [], 0xA010, 0xAA);
+        let x = mbc.read_ram(&[], 0xA020) as u16 | (mbc.read_ram(&[], 0xA030) as u16) << 8;
+        let y = mbc.read_ram(&[], 0xA040) as u16 | (mbc.read_ram(&[], 0xA050) as u16) << 8;
+        assert_eq!((x, y), (0x81D0 + 0x70, 0x81D0 - 0x38));
+
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(&[], 0xA020), 0xFF);
+    }
+
+    #[test]
+    fn test_eeprom_write_and_read() {
+        let mut ram = vec![0xFF; MBC7_EEPROM_SIZE];
+        let mut mbc = enabled_mbc();
+
+        // EWEN, then WRITE 0x1234 to word 5
+        send_command(&mut mbc, &mut ram, 0b00, 0xC0);
+        deselect(&mut mbc, &mut ram);
+        send_command(&mut mbc, &mut ram, 0b01, 0x05);
+        clock_bits(&mut mbc, &mut ram, 0x1234, 16);
+        deselect(&mut mbc, &mut ram);
+        assert_eq!(ram[10..12], [0x34, 0x12]);
+
+        // READ word 5: a dummy zero, then the word
+        let dummy = send_command(&mut mbc, &mut ram, 0b10, 0x05);
+        assert_eq!(dummy, 0);
+        assert_eq!(clock_bits(&mut mbc, &mut ram, 0, 16), 0x1234);
+        deselect(&mut mbc, &mut ram);
+
+        // EWDS: writes are ignored
+        send_command(&mut mbc, &mut ram, 0b00, 0x00);
+        deselect(&mut mbc, &mut ram);
+        send_command(&mut mbc, &mut ram, 0b11, 0x05);
+        deselect(&mut mbc, &mut ram);
+        assert_eq!(ram[10..12], [0x34, 0x12]);
+    }
+}
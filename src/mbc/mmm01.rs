@@ -0,0 +1,164 @@
+use crate::mbc::{Mbc, ram_byte, rom_byte};
+
+/// MMM01, a multicart mapper: an MBC1 behind a configurable outer bank.
+///
+/// At power on it is unmapped and shows the last 32 KiB of ROM, where the
+/// menu lives. The menu programs the outer ROM/RAM bank bits and a mask of
+/// which inner ROM bank bits the game may change, then sets bit 6 of
+/// 0x0000-0x1FFF to map the game, after which only the MBC1 part of the
+/// registers responds until reset.
+pub struct Mmm01 {
+    mapped: bool,
+    ram_enabled: bool,
+    /// ROM bank bits 0-4, as MBC1's BANK1
+    rom_bank_low: u8,
+    /// ROM bank bits 5-8
+    rom_bank_high: u8,
+    /// Which of ROM bank bits 1-4 keep the value the menu set once mapped
+    rom_mask: u8,
+    /// RAM bank bits 0-1, as MBC1's BANK2
+    ram_bank_low: u8,
+    ram_bank_high: u8,
+    mode: bool,
+    /// Set with 0x4000-0x5FFF bit 6: the game cannot change `mode`
+    mode_locked: bool,
+    rom_banks: usize,
+}
+
+impl Mmm01 {
+    pub fn new(rom_banks: usize) -> Mmm01 {
+        Mmm01 {
+            mapped: false,
+            ram_enabled: false,
+            rom_bank_low: 0,
+            rom_bank_high: 0,
+            rom_mask: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            mode: false,
+            mode_locked: false,
+            rom_banks,
+        }
+    }
+
+    /// Bank at 0x4000-0x7FFF. The masked bits of `rom_bank_low` were frozen
+    /// when the game was mapped, so it already holds the right value.
+    fn high_bank(&self) -> usize {
+        if !self.mapped {
+            return self.rom_banks.saturating_sub(1);
+        }
+        ((self.rom_bank_high as usize) << 5) | self.rom_bank_low.max(1) as usize
+    }
+    /// Bank at 0x0000-0x3FFF: the outer bank and the frozen inner bits.
+    fn low_bank(&self) -> usize {
+        if !self.mapped {
+            return self.rom_banks.saturating_sub(2);
+        }
+        let masked = (self.rom_mask << 1) & 0x1E;
+        ((self.rom_bank_high as usize) << 5) | (self.rom_bank_low & masked) as usize
+    }
+    fn ram_bank(&self) -> usize {
+        let low = if self.mode || !self.mapped {
+            self.ram_bank_low
+        } else {
+            0
+        };
+        ((self.ram_bank_high as usize) << 2) | low as usize
+    }
+}
+
+impl Mbc for Mmm01 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => rom_byte(rom, self.low_bank(), address),
+            _ => rom_byte(rom, self.high_bank(), address),
+        }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+                if !self.mapped {
+                    self.mapped = value & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3FFF => {
+                if self.mapped {
+                    // bits frozen by the mask keep their menu value
+                    let masked = (self.rom_mask << 1) & 0x1E;
+                    self.rom_bank_low = (self.rom_bank_low & masked) | (value & 0x1F & !masked);
+                } else {
+                    self.rom_bank_low = value & 0x1F;
+                    self.rom_bank_high = (self.rom_bank_high & 0x0C) | (value >> 5) & 0x03;
+                }
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank_low = value & 0x03;
+                if !self.mapped {
+                    self.ram_bank_high = (value >> 2) & 0x03;
+                    self.rom_bank_high = (self.rom_bank_high & 0x03) | ((value >> 4) & 0x03) << 2;
+                    self.mode_locked = value & 0x40 != 0;
+                }
+            }
+            _ => {
+                if !self.mapped || !self.mode_locked {
+                    self.mode = value & 0x01 != 0;
+                }
+                if !self.mapped {
+                    self.rom_mask = (value >> 2) & 0x0F;
+                }
+            }
+        }
+    }
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        match ram_byte(ram, self.ram_bank(), address) {
+            Some(i) if self.ram_enabled => ram[i],
+            _ => 0xFF,
+        }
+    }
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if let Some(i) = ram_byte(ram, self.ram_bank(), address).filter(|_| self.ram_enabled) {
+            ram[i] = value;
+            return true;
+        }
+        false
+    }
+    fn rom_bank(&self, address: u16) -> u16 {
+        let bank = match address {
+            0x0000..=0x3FFF => self.low_bank(),
+            _ => self.high_bank(),
+        };
+        (bank % self.rom_banks.max(1)) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::numbered_rom;
+
+    #[test]
+    fn test_menu_then_game() {
+        let rom = numbered_rom(64);
+        let mut mbc = Mmm01::new(64);
+        // the menu sits in the last 32 KiB
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 62);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 63);
+
+        // game at banks 0x20-0x27: outer bit 5, inner bits 3-4 frozen at 0
+        mbc.write_rom(0x2000, 0x20);
+        mbc.write_rom(0x6000, 0x0C << 2);
+        mbc.write_rom(0x0000, 0x40);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x20);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x21);
+        mbc.write_rom(0x2000, 0x03);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x23);
+        // the game cannot leave its 8 banks
+        mbc.write_rom(0x2000, 0x1F);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x27);
+        // nor unmap itself or move the outer bank
+        mbc.write_rom(0x0000, 0x00);
+        mbc.write_rom(0x4000, 0x30);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x20);
+    }
+}
@@ -0,0 +1,275 @@
+/// Base clock cycles (4 MiHz) per RTC second. The RTC has its own 32 KiHz
+/// crystal, so it does not speed up in CGB double speed.
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+/// Size of the RTC state saved after the cartridge RAM: five current and
+/// five latched registers as 32-bit values, then a 64-bit UNIX timestamp.
+/// This is the layout BGB, VBA-M, SameBoy and mGBA use.
+pub const RTC_SAVE_SIZE: usize = 48;
+/// Older VBA saves use a 32-bit timestamp.
+const RTC_SAVE_SIZE_SHORT: usize = 44;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RtcRegisters {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    /// Lower 8 bits of the 9-bit day counter
+    pub days_low: u8,
+    /// Bit 0: day counter bit 8, bit 6: halt, bit 7: day counter carry
+    pub days_high: u8,
+}
+
+impl RtcRegisters {
+    fn halted(&self) -> bool {
+        self.days_high & 0x40 != 0
+    }
+    fn days(&self) -> u16 {
+        ((self.days_high as u16 & 0x01) << 8) | self.days_low as u16
+    }
+    fn set_days(&mut self, days: u16) {
+        self.days_low = days as u8;
+        self.days_high = (self.days_high & 0xFE) | ((days >> 8) as u8 & 0x01);
+    }
+
+    /// Advances by one second. Counters that were written out of range count
+    /// up to their bit width and wrap to 0 without carrying.
+    fn tick_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.add_days(1);
+    }
+
+    fn add_days(&mut self, days: u64) {
+        let total = self.days() as u64 + days;
+        if total > 0x1FF {
+            self.days_high |= 0x80;
+        }
+        self.set_days((total & 0x1FF) as u16);
+    }
+
+    /// Advances by `seconds`, as if `tick_second` ran that many times.
+    fn advance(&mut self, mut seconds: u64) {
+        // step until every counter is in range, then do it arithmetically
+        while seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.tick_second();
+            seconds -= 1;
+        }
+        let total =
+            seconds + self.seconds as u64 + self.minutes as u64 * 60 + self.hours as u64 * 3600;
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        let days = total / 86400;
+        if days > 0 {
+            self.add_days(days);
+        }
+    }
+
+    fn to_words(self) -> [u32; 5] {
+        [
+            self.seconds as u32,
+            self.minutes as u32,
+            self.hours as u32,
+            self.days_low as u32,
+            self.days_high as u32,
+        ]
+    }
+    fn from_words(words: &[u32]) -> RtcRegisters {
+        RtcRegisters {
+            seconds: words[0] as u8 & 0x3F,
+            minutes: words[1] as u8 & 0x3F,
+            hours: words[2] as u8 & 0x1F,
+            days_low: words[3] as u8,
+            days_high: words[4] as u8 & 0xC1,
+        }
+    }
+}
+
+/// MBC3 real-time clock. Counts emulated time while the game runs; when a
+/// save is loaded, it catches up on the wall-clock time since it was written.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rtc {
+    current: RtcRegisters,
+    latched: RtcRegisters,
+    /// Base clock cycles into the current second
+    cycles: u32,
+}
+
+impl Rtc {
+    pub fn new() -> Rtc {
+        Rtc::default()
+    }
+
+    pub fn current(&self) -> RtcRegisters {
+        self.current
+    }
+    pub fn latched(&self) -> RtcRegisters {
+        self.latched
+    }
+
+    /// Advances by `cycles` base clock cycles (4 MiHz).
+    pub fn tick(&mut self, cycles: u32) {
+        if self.current.halted() {
+            return;
+        }
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.current.tick_second();
+        }
+    }
+
+    /// Copies the running registers into the ones the CPU reads.
+    pub fn latch(&mut self) {
+        self.latched = self.current;
+    }
+
+    /// Reads RTC register `select` (0x08-0x0C) from the latched copy.
+    pub fn read(&self, select: u8) -> u8 {
+        match select {
+            0x08 => self.latched.seconds,
+            0x09 => self.latched.minutes,
+            0x0A => self.latched.hours,
+            0x0B => self.latched.days_low,
+            _ => self.latched.days_high,
+        }
+    }
+    /// Writes RTC register `select` (0x08-0x0C) of the running clock.
+    pub fn write(&mut self, select: u8, value: u8) {
+        match select {
+            0x08 => {
+                // writing the seconds restarts the current second
+                self.current.seconds = value & 0x3F;
+                self.cycles = 0;
+            }
+            0x09 => self.current.minutes = value & 0x3F,
+            0x0A => self.current.hours = value & 0x1F,
+            0x0B => self.current.days_low = value,
+            _ => self.current.days_high = value & 0xC1,
+        }
+    }
+
+    /// The clock's state stamped with `now` (seconds since the UNIX epoch).
+    pub fn save(&self, now: u64) -> [u8; RTC_SAVE_SIZE] {
+        let mut bytes = [0; RTC_SAVE_SIZE];
+        let words = self
+            .current
+            .to_words()
+            .into_iter()
+            .chain(self.latched.to_words());
+        for (chunk, word) in bytes.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes[40..].copy_from_slice(&now.to_le_bytes());
+        bytes
+    }
+
+    /// Restores a clock saved by `save` (or the 44-byte VBA variant) and
+    /// runs it forward by the time between its timestamp and `now`. Returns
+    /// `None` if `bytes` has neither size.
+    pub fn load(bytes: &[u8], now: u64) -> Option<Rtc> {
+        let timestamp = match bytes.len() {
+            RTC_SAVE_SIZE => u64::from_le_bytes(bytes[40..48].try_into().unwrap()),
+            RTC_SAVE_SIZE_SHORT => u32::from_le_bytes(bytes[40..44].try_into().unwrap()) as u64,
+            _ => return None,
+        };
+        let words: Vec<u32> = bytes[..40]
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        let mut rtc = Rtc {
+            current: RtcRegisters::from_words(&words[..5]),
+            latched: RtcRegisters::from_words(&words[5..]),
+            cycles: 0,
+        };
+        if !rtc.current.halted() {
+            rtc.current.advance(now.saturating_sub(timestamp));
+        }
+        Some(rtc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_emulated_seconds() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x08, 59);
+        rtc.write(0x09, 59);
+        rtc.write(0x0A, 23);
+        rtc.write(0x0B, 0xFF);
+        rtc.write(0x0C, 0x01);
+        rtc.tick(CYCLES_PER_SECOND - 1);
+        assert_eq!(rtc.current().seconds, 59);
+        rtc.tick(1);
+        // day 511 rolls over to 0 and sets the carry
+        assert_eq!(
+            rtc.current(),
+            RtcRegisters {
+                days_high: 0x80,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_latch_and_halt() {
+        let mut rtc = Rtc::new();
+        rtc.tick(3 * CYCLES_PER_SECOND);
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 3);
+        rtc.write(0x0C, 0x40);
+        rtc.tick(10 * CYCLES_PER_SECOND);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 3);
+        assert_eq!(rtc.read(0x0C), 0x40);
+    }
+
+    #[test]
+    fn test_out_of_range_values_wrap_without_carry() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x08, 0x3F);
+        rtc.tick(CYCLES_PER_SECOND);
+        assert_eq!(rtc.current().seconds, 0);
+        assert_eq!(rtc.current().minutes, 0);
+    }
+
+    #[test]
+    fn test_save_and_fast_forward() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x0A, 22);
+        rtc.latch();
+        let saved = rtc.save(1_000_000);
+        // two hours and five seconds later
+        let loaded = Rtc::load(&saved, 1_000_000 + 7205).unwrap();
+        assert_eq!(loaded.latched(), rtc.latched());
+        let current = loaded.current();
+        assert_eq!((current.hours, current.minutes, current.seconds), (0, 0, 5));
+        assert_eq!(current.days_low, 1);
+
+        // a halted clock does not catch up
+        rtc.write(0x0C, 0x40);
+        let loaded = Rtc::load(&rtc.save(0), 86400).unwrap();
+        assert_eq!(loaded.current(), rtc.current());
+
+        assert!(Rtc::load(&saved[..44], 1_000_000).is_some());
+        assert!(Rtc::load(&saved[..40], 1_000_000).is_none());
+    }
+}
@@ -0,0 +1,123 @@
+use crate::mbc::{Mbc, rom_byte};
+
+/// TAMA5's RAM: 32 bytes in the chip, not mapped at 0xA000.
+pub const TAMA5_RAM_SIZE: usize = 32;
+
+/// TAMA5 (Bandai, Game de Hakken!! Tamagotchi Osucchi to Mesucchi).
+///
+/// Everything goes through two ports: a write to 0xA001 selects a 4-bit
+/// register, and 0xA000 reads or writes it. Registers 0 and 1 are the ROM
+/// bank; 4/5 hold a byte to write and 6/7 a command and RAM address, the
+/// write to 7 running it. Results are read from registers 0xC/0xD. The
+/// TC8521 clock behind the RAM commands is not emulated.
+pub struct Tama5 {
+    select: u8,
+    registers: [u8; 0x10],
+    output: u8,
+}
+
+impl Default for Tama5 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tama5 {
+    pub fn new() -> Tama5 {
+        Tama5 {
+            select: 0,
+            registers: [0; 0x10],
+            output: 0,
+        }
+    }
+
+    fn bank(&self) -> usize {
+        (self.registers[0] | (self.registers[1] & 0x01) << 4) as usize
+    }
+
+    /// Runs the command in registers 6/7. Returns whether it wrote RAM.
+    fn command(&mut self, ram: &mut [u8]) -> bool {
+        let address = ((self.registers[6] & 0x01) << 4 | self.registers[7]) as usize;
+        match self.registers[6] >> 1 {
+            0x0 => {
+                if let Some(byte) = ram.get_mut(address) {
+                    *byte = self.registers[5] << 4 | self.registers[4];
+                    return true;
+                }
+                false
+            }
+            0x1 => {
+                self.output = ram.get(address).copied().unwrap_or(0xFF);
+                false
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Mbc for Tama5 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => rom_byte(rom, 0, address),
+            _ => rom_byte(rom, self.bank(), address),
+        }
+    }
+    fn write_rom(&mut self, _address: u16, _value: u8) {}
+    fn read_ram(&self, _ram: &[u8], address: u16) -> u8 {
+        match (address & 0x01, self.select) {
+            (0, 0x0C) => 0xF0 | self.output & 0x0F,
+            (0, 0x0D) => 0xF0 | self.output >> 4,
+            // always ready
+            (1, _) => 0xF1,
+            _ => 0xFF,
+        }
+    }
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if address & 0x01 != 0 {
+            self.select = value & 0x0F;
+            return false;
+        }
+        self.registers[self.select as usize] = value & 0x0F;
+        self.select == 0x07 && self.command(ram)
+    }
+    fn rom_bank(&self, address: u16) -> u16 {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.bank() as u16,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_register(mbc: &mut Tama5, ram: &mut [u8], register: u8, value: u8) {
+        mbc.write_ram(ram, 0xA001, register);
+        mbc.write_ram(ram, 0xA000, value);
+    }
+
+    #[test]
+    fn test_rom_bank_and_ram_commands() {
+        let mut ram = vec![0; TAMA5_RAM_SIZE];
+        let mut mbc = Tama5::new();
+        write_register(&mut mbc, &mut ram, 0x00, 0x03);
+        write_register(&mut mbc, &mut ram, 0x01, 0x01);
+        assert_eq!(mbc.rom_bank(0x4000), 0x13);
+
+        // write 0xA5 to RAM 0x12
+        write_register(&mut mbc, &mut ram, 0x04, 0x05);
+        write_register(&mut mbc, &mut ram, 0x05, 0x0A);
+        write_register(&mut mbc, &mut ram, 0x06, 0x01);
+        write_register(&mut mbc, &mut ram, 0x07, 0x02);
+        assert_eq!(ram[0x12], 0xA5);
+
+        // and read it back
+        write_register(&mut mbc, &mut ram, 0x06, 0x03);
+        write_register(&mut mbc, &mut ram, 0x07, 0x02);
+        mbc.write_ram(&mut ram, 0xA001, 0x0C);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xF5);
+        mbc.write_ram(&mut ram, 0xA001, 0x0D);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFA);
+    }
+}
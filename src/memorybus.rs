@@ -1,60 +1,665 @@
-use std::fs::File;
-use std::io::Read;
+use crate::apu::Apu;
+use crate::boot::{self, BootRom};
+use crate::cartridge::Cartridge;
+use crate::dma::OamDma;
+use crate::interrupts::Interrupt;
+use crate::joypad::{Button, Joypad};
+use crate::model::Model;
+use crate::ppu::{Mode, Ppu};
+use crate::timer::Timer;
+
+const VRAM_BANK_SIZE: usize = 0x2000;
+const WRAM_BANK_SIZE: usize = 0x1000;
+
+/// The CPU's view of memory. Each region of the address space is routed
+/// to the component behind it:
+///
+/// | range       | region                                      |
+/// |-------------|---------------------------------------------|
+/// | 0000-7FFF   | cartridge ROM, under the boot ROM until FF50 |
+/// | 8000-9FFF   | VRAM, bank selected by VBK on CGB           |
+/// | A000-BFFF   | cartridge RAM                               |
+/// | C000-CFFF   | WRAM bank 0                                 |
+/// | D000-DFFF   | WRAM bank 1, or 1-7 selected by SVBK on CGB |
+/// | E000-FDFF   | echo of C000-DDFF                           |
+/// | FE00-FE9F   | OAM                                         |
+/// | FEA0-FEFF   | unusable, see `Model::unusable_area_read`   |
+/// | FF00-FF7F   | I/O registers                               |
+/// | FF80-FFFE   | HRAM                                        |
+/// | FFFF        | IE                                          |
 pub struct MemoryBus {
-    pub data: [u8; 0x10000],
+    model: Model,
+    pub cartridge: Option<Cartridge>,
+    /// Mapped over the cartridge until the first write to 0xFF50
+    pub boot_rom: Option<BootRom>,
+    /// One 8 KiB bank, two on CGB
+    pub vram: Vec<u8>,
+    /// Two 4 KiB banks, eight on CGB
+    pub wram: Vec<u8>,
+    /// VBK (0xFF4F), CGB only
+    vram_bank: u8,
+    /// SVBK (0xFF70), CGB only; 0 selects bank 1 like 1 does
+    wram_bank: u8,
+    pub oam: [u8; 0xA0],
+    /// I/O registers not owned by a component yet
+    io: [u8; 0x80],
+    pub hram: [u8; 0x7F],
+    pub timer: Timer,
+    pub apu: Apu,
+    pub joypad: Joypad,
+    pub ppu: Ppu,
+    pub dma: OamDma,
+    /// KEY1 (0xFF4D) bit 7: CPU running at double speed (CGB)
+    pub double_speed: bool,
+    /// KEY1 bit 0: the next STOP switches speed instead of stopping
+    pub speed_switch_armed: bool,
+    /// IE (0xFFFF)
+    pub interrupt_enable: u8,
+    /// IF (0xFF0F), only the low five bits exist
+    pub interrupt_flag: u8,
+    /// Keep the CPU out of VRAM and OAM while the PPU uses them
+    access_locking: bool,
+}
+
+impl Default for MemoryBus {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MemoryBus {
+    /// A DMG bus.
     pub fn new() -> MemoryBus {
-        MemoryBus { data: [0; 0x10000] }
+        MemoryBus::with_model(Model::Dmg)
+    }
+    pub fn with_model(model: Model) -> MemoryBus {
+        let (vram_banks, wram_banks) = if model.is_cgb() { (2, 8) } else { (1, 2) };
+        MemoryBus {
+            model,
+            cartridge: None,
+            boot_rom: None,
+            vram: vec![0; vram_banks * VRAM_BANK_SIZE],
+            wram: vec![0; wram_banks * WRAM_BANK_SIZE],
+            vram_bank: 0,
+            wram_bank: 0,
+            oam: [0; 0xA0],
+            io: [0; 0x80],
+            hram: [0; 0x7F],
+            timer: Timer::new(),
+            apu: Apu::new(),
+            joypad: Joypad::new(),
+            ppu: Ppu::with_model(model),
+            dma: OamDma::new(),
+            double_speed: false,
+            speed_switch_armed: false,
+            interrupt_enable: 0x00,
+            interrupt_flag: 0x00,
+            access_locking: true,
+        }
     }
     pub fn read_data(&self, address: u16) -> u8 {
-        self.data[address as usize]
+        if let Some(value) = self.dma_conflict(address) {
+            return value;
+        }
+        self.read_memory(address)
+    }
+    /// `read_data` as the OAM DMA sees it, without its own bus conflicts.
+    fn read_memory(&self, address: u16) -> u8 {
+        if let Some(value) = self.boot_rom.as_ref().and_then(|b| b.read(address)) {
+            return value;
+        }
+        match address {
+            0x0000..=0x7FFF => self
+                .cartridge
+                .as_ref()
+                .map_or(0xFF, |c| c.read_rom(address)),
+            0x8000..=0x9FFF if self.vram_locked() => 0xFF,
+            0x8000..=0x9FFF => self.vram[self.vram_index(address)],
+            0xA000..=0xBFFF => self
+                .cartridge
+                .as_ref()
+                .map_or(0xFF, |c| c.read_ram(address)),
+            0xC000..=0xFDFF => self.wram[self.wram_index(address)],
+            0xFE00..=0xFEFF if self.oam_locked() => 0xFF,
+            0xFE00..=0xFE9F => self.oam[address as usize - 0xFE00],
+            0xFEA0..=0xFEFF => self.model.unusable_area_read(address),
+            0xFF00..=0xFF7F => self.read_io(address),
+            0xFF80..=0xFFFE => self.hram[address as usize - 0xFF80],
+            0xFFFF => self.interrupt_enable,
+        }
     }
     pub fn write_data(&mut self, address: u16, value: u8) {
-        self.data[address as usize] = value;
+        if self.dma_conflict(address).is_some() {
+            return;
+        }
+        match address {
+            0x0000..=0x7FFF => {
+                if let Some(cartridge) = &mut self.cartridge {
+                    cartridge.write_rom(address, value);
+                }
+            }
+            0x8000..=0x9FFF if self.vram_locked() => {}
+            0x8000..=0x9FFF => {
+                let i = self.vram_index(address);
+                self.vram[i] = value;
+            }
+            0xA000..=0xBFFF => {
+                if let Some(cartridge) = &mut self.cartridge {
+                    cartridge.write_ram(address, value);
+                }
+            }
+            0xC000..=0xFDFF => {
+                let i = self.wram_index(address);
+                self.wram[i] = value;
+            }
+            0xFE00..=0xFE9F if self.oam_locked() => {}
+            0xFE00..=0xFE9F => self.oam[address as usize - 0xFE00] = value,
+            0xFEA0..=0xFEFF => {}
+            0xFF00..=0xFF7F => self.write_io(address, value),
+            0xFF80..=0xFFFE => self.hram[address as usize - 0xFF80] = value,
+            0xFFFF => self.interrupt_enable = value,
+        }
     }
 
-    pub fn extract_rom(&mut self, path: String) -> std::io::Result<Vec<u8>> {
-        let mut file = File::open(path)?;
+    /// Index into `vram` of 0x8000-0x9FFF in the selected bank.
+    fn vram_index(&self, address: u16) -> usize {
+        self.vram_bank as usize * VRAM_BANK_SIZE + (address as usize - 0x8000)
+    }
+    /// Index into `wram` of 0xC000-0xDFFF, or its echo, in the selected bank.
+    fn wram_index(&self, address: u16) -> usize {
+        let offset = (address as usize - 0xC000) & 0x1FFF;
+        match offset {
+            0x0000..=0x0FFF => offset,
+            _ => self.wram_bank.max(1) as usize * WRAM_BANK_SIZE + (offset - WRAM_BANK_SIZE),
+        }
+    }
 
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)?;
-        let len = buffer.len();
+    fn read_io(&self, address: u16) -> u8 {
+        match address {
+            _ if io_unmapped(address) => 0xFF,
+            _ if cgb_only(address) && !self.model.is_cgb() => 0xFF,
+            0xFF00 => self.joypad.read(),
+            0xFF04..=0xFF07 => self.timer.read(address),
+            0xFF0F => self.interrupt_flag | 0xE0,
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.read(address),
+            0xFF46 => self.dma.read(),
+            0xFF4D => 0x7E | ((self.double_speed as u8) << 7) | self.speed_switch_armed as u8,
+            0xFF4F => 0xFE | self.vram_bank,
+            0xFF50 => 0xFF,
+            0xFF70 => 0xF8 | self.wram_bank,
+            _ => self.io[address as usize - 0xFF00],
+        }
+    }
+    fn write_io(&mut self, address: u16, value: u8) {
+        match address {
+            _ if io_unmapped(address) => {}
+            _ if cgb_only(address) && !self.model.is_cgb() => {}
+            0xFF00 => self.joypad.write(value),
+            0xFF04..=0xFF07 => self.timer.write(address, value),
+            0xFF0F => self.interrupt_flag = value & 0x1F,
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => {
+                self.ppu.write(address, value);
+                if self.ppu.take_stat_interrupt() {
+                    self.request_interrupt(Interrupt::Stat);
+                }
+            }
+            0xFF46 => self.dma.write(value),
+            0xFF4D => self.speed_switch_armed = value & 0x01 != 0,
+            0xFF4F => self.vram_bank = value & 0x01,
+            // unmapping is one-way: only a reset maps the boot ROM again
+            0xFF50 => {
+                if value & 0x01 != 0 {
+                    self.boot_rom = None;
+                }
+            }
+            0xFF70 => self.wram_bank = value & 0x07,
+            _ => self.io[address as usize - 0xFF00] = value,
+        }
+    }
 
-        self.data[0..len].copy_from_slice(&buffer[0..len]);
+    pub fn model(&self) -> Model {
+        self.model
+    }
 
-        Ok(buffer)
+    /// Whether CPU accesses to VRAM during mode 3 and to OAM during modes
+    /// 2 and 3 are blocked (reads 0xFF, writes dropped), as on hardware.
+    /// On by default; debuggers turn it off to see memory at any time.
+    pub fn set_access_locking(&mut self, enabled: bool) {
+        self.access_locking = enabled;
+    }
+    pub fn access_locking(&self) -> bool {
+        self.access_locking
+    }
+    /// What a CPU read of `address` gets instead while OAM DMA runs: 0xFF
+    /// from OAM, which the DMA owns, and the DMA's in-flight byte from the
+    /// bus it reads from. I/O, HRAM and the other buses stay open, and
+    /// writes to blocked addresses are dropped.
+    fn dma_conflict(&self, address: u16) -> Option<u8> {
+        let source = self.dma.source()?;
+        match address {
+            0xFE00..=0xFEFF => Some(0xFF),
+            0xFF00..=0xFFFF => None,
+            _ if self.data_bus(address) == self.data_bus(source) => Some(self.dma.in_flight()),
+            _ => None,
+        }
+    }
+    /// The CPU bus `address` (below 0xFE00) is on.
+    fn data_bus(&self, address: u16) -> DataBus {
+        match address {
+            0x8000..=0x9FFF => DataBus::Video,
+            0xC000..=0xFDFF if self.model.is_cgb() => DataBus::Wram,
+            _ => DataBus::External,
+        }
+    }
+    fn vram_locked(&self) -> bool {
+        self.access_locking && self.ppu.mode() == Mode::Drawing
+    }
+    fn oam_locked(&self) -> bool {
+        self.access_locking && matches!(self.ppu.mode(), Mode::OamScan | Mode::Drawing)
+    }
+
+    /// Advances every clocked component on the bus by `m_cycles` M-cycles.
+    /// This is the machine clock: the CPU calls it with the cost of each
+    /// instruction it executes.
+    pub fn tick(&mut self, m_cycles: u8) {
+        // the PPU and cartridge run off the base clock, unaffected by the
+        // CPU's double speed
+        let cycles = m_cycles as u32 * if self.double_speed { 2 } else { 4 };
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.tick(cycles);
+        }
+        self.ppu
+            .tick(cycles, &self.vram[..VRAM_BANK_SIZE], &self.oam);
+        if self.ppu.take_vblank_interrupt() {
+            self.request_interrupt(Interrupt::VBlank);
+        }
+        if self.ppu.take_stat_interrupt() {
+            self.request_interrupt(Interrupt::Stat);
+        }
+        for _ in 0..m_cycles {
+            if let Some((source, index)) = self.dma.tick() {
+                let value = self.read_memory(source);
+                self.oam[index as usize] = value;
+                self.dma.set_in_flight(value);
+            }
+            self.timer.tick();
+            if self.timer.take_interrupt() {
+                self.request_interrupt(Interrupt::Timer);
+            }
+            self.apu.tick(self.timer.counter(), self.double_speed);
+        }
+    }
+
+    /// Raises `interrupt` in IF. Peripherals call this; the CPU decides when
+    /// (and whether) to service it.
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag |= interrupt.bit();
+    }
+    /// Clears `interrupt` in IF once the CPU has started servicing it.
+    pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag &= !interrupt.bit();
+    }
+    /// Interrupts that are both requested and enabled, regardless of IME.
+    pub fn pending_interrupts(&self) -> u8 {
+        self.interrupt_enable & self.interrupt_flag & 0x1F
+    }
+
+    /// ROM bank mapped at `address`, for diagnostics. 0 outside ROM, and
+    /// 0/1 for the two ROM windows when no cartridge is inserted.
+    pub fn rom_bank(&self, address: u16) -> u16 {
+        match (address, &self.cartridge) {
+            (0x0000..=0x7FFF, Some(cartridge)) => cartridge.rom_bank(address),
+            (0x4000..=0x7FFF, None) => 1,
+            _ => 0,
+        }
+    }
+
+    pub fn press_button(&mut self, button: Button) {
+        if self.joypad.press(button) {
+            self.request_interrupt(Interrupt::Joypad);
+        }
+    }
+    pub fn release_button(&mut self, button: Button) {
+        self.joypad.release(button);
+    }
+
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.cartridge = Some(cartridge);
+    }
+
+    /// Puts the I/O registers and the DIV counter in the state the model's
+    /// boot ROM leaves them in, for starting at 0x0100 without running it.
+    pub fn skip_boot(&mut self) {
+        self.boot_rom = None;
+        let io = boot::post_boot_io(self.model);
+        for &(address, value) in &io {
+            match address {
+                // the boot ROM leaves the register, not a transfer
+                0xFF46 => self.dma.set_register(value),
+                _ => self.write_io(address, value),
+            }
+        }
+        // the STAT write may have raised an interrupt the boot ROM never saw
+        if let Some(&(address, value)) = io.iter().find(|(address, _)| *address == 0xFF0F) {
+            self.write_io(address, value);
+        }
+        self.timer
+            .set_counter(boot::post_boot_div_counter(self.model));
+    }
+
+    /// Writes `value` at `address` like `write_data`, except that ROM
+    /// addresses patch the cartridge image, so tests can place code there.
+    #[cfg(test)]
+    pub(crate) fn poke(&mut self, address: u16, value: u8) {
+        match (address, &mut self.cartridge) {
+            (0x0000..=0x7FFF, Some(cartridge)) => cartridge.rom_mut()[address as usize] = value,
+            _ => self.write_data(address, value),
+        }
     }
 }
 
-#[cfg(test)]
+/// The buses memory hangs off, which OAM DMA and the CPU contend for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum DataBus {
+    /// Cartridge ROM and RAM, and WRAM on the monochrome models
+    External,
+    /// VRAM
+    Video,
+    /// WRAM, on its own bus on CGB
+    Wram,
+}
+
+/// I/O registers that only exist on CGB hardware: KEY1, VBK, HDMA, RP,
+/// the color palettes, OPRI, SVBK and the undocumented 0xFF72-0xFF77. Other
+/// models read them as 0xFF and ignore writes.
+fn cgb_only(address: u16) -> bool {
+    matches!(
+        address,
+        0xFF4D | 0xFF4F | 0xFF51..=0xFF56 | 0xFF68..=0xFF6C | 0xFF70 | 0xFF72..=0xFF77
+    )
+}
+
+/// I/O addresses with no register behind them on any model: reads return
+/// 0xFF and writes are ignored.
+fn io_unmapped(address: u16) -> bool {
+    matches!(
+        address,
+        0xFF03 | 0xFF08..=0xFF0E | 0xFF15 | 0xFF1F | 0xFF27..=0xFF2F | 0xFF4E | 0xFF57..=0xFF67
+            | 0xFF6D..=0xFF6F | 0xFF71 | 0xFF78..=0xFF7F
+    )
+}
 
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cartridge::test_rom;
 
     #[test]
     fn test_read_and_write_data() {
         let mut bus = MemoryBus::new();
-        bus.write_data(0x1234, 0xAB);
-        let value = bus.read_data(0x1234);
+        bus.write_data(0xC234, 0xAB);
+        let value = bus.read_data(0xC234);
 
         assert_eq!(value, 0xAB)
     }
     #[test]
-    fn test_read_rom() {
+    fn test_rom_is_read_only() {
         let mut bus = MemoryBus::new();
+        assert_eq!(bus.read_data(0x0150), 0xFF); // no cartridge
+        bus.load_cartridge(Cartridge::from_bytes(test_rom(0x00, 0x00, 0x00)).unwrap());
+        bus.write_data(0x0150, 0xAB);
+        assert_eq!(bus.read_data(0x0150), 0x18);
+        assert_eq!(bus.read_data(0xA000), 0xFF); // no cartridge RAM
+    }
+    #[test]
+    fn test_echo_ram_mirrors_wram() {
+        let mut bus = MemoryBus::new();
+        bus.write_data(0xC123, 0x11);
+        assert_eq!(bus.read_data(0xE123), 0x11);
+        bus.write_data(0xFDFF, 0x22);
+        assert_eq!(bus.read_data(0xDDFF), 0x22);
+    }
+    #[test]
+    fn test_unusable_and_unmapped_areas() {
+        let mut bus = MemoryBus::new();
+        bus.write_data(0xFEA0, 0x12);
+        bus.write_data(0xFF03, 0x00);
+        assert_eq!(bus.read_data(0xFEA0), 0x00);
+        assert_eq!(bus.read_data(0xFEFF), 0x00);
+        assert_eq!(bus.read_data(0xFF03), 0xFF);
+        bus.write_data(0xFE9F, 0x33);
+        bus.write_data(0xFF80, 0x44);
+        bus.write_data(0x9FFF, 0x55);
+        assert_eq!(bus.oam[0x9F], 0x33);
+        assert_eq!(bus.hram[0], 0x44);
+        assert_eq!(bus.vram[0x1FFF], 0x55);
+        let bus = MemoryBus::with_model(Model::Cgb);
+        assert_eq!(bus.read_data(0xFEA0), 0xAA);
+        assert_eq!(bus.read_data(0xFEFF), 0xFF);
+    }
+    #[test]
+    fn test_timer_registers_routed_to_timer() {
+        let mut bus = MemoryBus::new();
+        bus.write_data(0xFF07, 0x05);
+        bus.tick(4);
+        assert_eq!(bus.read_data(0xFF05), 1);
+        assert_eq!(bus.io[0x05], 0);
+    }
+    #[test]
+    fn test_ppu_raises_vblank_interrupt() {
+        let mut bus = MemoryBus::new();
+        bus.write_data(0xFF40, 0x80);
+        for _ in 0..144 {
+            bus.tick(114);
+        }
+        assert_eq!(bus.read_data(0xFF44), 144);
+        assert_eq!(bus.interrupt_flag, Interrupt::VBlank.bit());
+    }
+    #[test]
+    fn test_ppu_raises_stat_interrupt() {
+        let mut bus = MemoryBus::with_model(Model::Cgb);
+        bus.write_data(0xFF45, 0x90);
+        bus.write_data(0xFF40, 0x80);
+        bus.write_data(0xFF41, 0x08); // HBlank
+        bus.tick(62);
+        assert_eq!(bus.interrupt_flag, 0);
+        bus.tick(1);
+        assert_eq!(bus.interrupt_flag, Interrupt::Stat.bit());
+    }
+    #[test]
+    fn test_vram_and_oam_locked_by_ppu_mode() {
+        let mut bus = MemoryBus::new();
+        bus.write_data(0x8000, 0x12);
+        bus.write_data(0xFE00, 0x34);
+        bus.write_data(0xFF40, 0x80);
+        // mode 2: OAM locked, the unusable area with it
+        assert_eq!(bus.read_data(0x8000), 0x12);
+        assert_eq!(bus.read_data(0xFE00), 0xFF);
+        assert_eq!(bus.read_data(0xFEA0), 0xFF);
+        bus.write_data(0xFE00, 0x56);
+        // mode 3: both locked
+        bus.tick(20);
+        assert_eq!(bus.read_data(0x8000), 0xFF);
+        bus.write_data(0x8000, 0x78);
+        bus.set_access_locking(false);
+        assert_eq!(bus.read_data(0x8000), 0x12);
+        assert_eq!(bus.read_data(0xFE00), 0x34);
+        bus.set_access_locking(true);
+        // HBlank: both open again
+        bus.tick(43);
+        assert_eq!(bus.read_data(0x8000), 0x12);
+        assert_eq!(bus.read_data(0xFE00), 0x34);
+        assert_eq!(bus.read_data(0xFEA0), 0x00);
+    }
+    #[test]
+    fn test_oam_dma() {
+        let mut bus = MemoryBus::new();
+        for i in 0..0xA0 {
+            bus.write_data(0xC000 + i, i as u8);
+            bus.write_data(0xDE00 + i, 0xA0 - i as u8);
+        }
+        bus.write_data(0x8000, 0x55);
+        bus.write_data(0xFF80, 0x66);
+        bus.write_data(0xFF46, 0xC0);
+        assert_eq!(bus.read_data(0xFF46), 0xC0);
+        bus.tick(1);
+        // OAM is the DMA's; WRAM shares its bus with the cartridge on DMG
+        bus.tick(4);
+        assert_eq!(bus.read_data(0xFE00), 0xFF);
+        assert_eq!(bus.read_data(0xD000), 0x03);
+        assert_eq!(bus.read_data(0x0000), 0x03);
+        bus.write_data(0xC000, 0xEE);
+        assert_eq!(bus.read_data(0x8000), 0x55);
+        assert_eq!(bus.read_data(0xFF80), 0x66);
+        assert_eq!(bus.read_data(0xFF46), 0xC0);
+
+        // restarting from 0xFE00 reads WRAM at 0xDE00
+        bus.write_data(0xFF46, 0xFE);
+        bus.tick(1 + 0xA0);
+        assert_eq!(bus.read_data(0xFE00), 0xA0);
+        assert_eq!(bus.read_data(0xFE9F), 0x01);
+        assert_eq!(bus.read_data(0xC000), 0x00);
 
-        bus.extract_rom(String::from("/home/elodie/Downloads/Tetris (World).gb"))
-            .expect("file not found");
+        // on CGB, WRAM has its own bus
+        let mut bus = MemoryBus::with_model(Model::Cgb);
+        bus.write_data(0xC000, 0x77);
+        bus.write_data(0xFF46, 0x80);
+        bus.tick(2);
+        assert_eq!(bus.read_data(0xC000), 0x77);
+        assert_eq!(bus.read_data(0x9000), 0x00);
+    }
+    #[test]
+    fn test_tick_raises_timer_interrupt() {
+        let mut bus = MemoryBus::new();
+        bus.write_data(0xFF05, 0xFF);
+        bus.write_data(0xFF07, 0x05);
+        bus.tick(5);
+        assert_eq!(bus.read_data(0xFF0F), 0xE0 | Interrupt::Timer.bit());
+    }
+    #[test]
+    fn test_button_press_raises_joypad_interrupt() {
+        let mut bus = MemoryBus::new();
+        bus.press_button(Button::Start);
+        assert_eq!(bus.interrupt_flag, 0);
+        bus.release_button(Button::Start);
+        bus.write_data(0xFF00, 0x10);
+        bus.press_button(Button::Start);
+        assert_eq!(bus.interrupt_flag, Interrupt::Joypad.bit());
+        assert_eq!(bus.read_data(0xFF00), 0xD7);
+    }
+    #[test]
+    fn test_key1() {
+        let mut bus = MemoryBus::with_model(Model::Cgb);
+        assert_eq!(bus.read_data(0xFF4D), 0x7E);
+        bus.write_data(0xFF4D, 0xFF);
+        assert_eq!(bus.read_data(0xFF4D), 0x7F);
+        bus.double_speed = true;
+        bus.speed_switch_armed = false;
+        assert_eq!(bus.read_data(0xFF4D), 0xFE);
+    }
+    #[test]
+    fn test_cgb_registers_absent_on_dmg() {
+        let mut bus = MemoryBus::new();
+        for address in [0xFF4D, 0xFF4F, 0xFF70] {
+            bus.write_data(address, 0x01);
+            assert_eq!(bus.read_data(address), 0xFF);
+        }
+        assert!(!bus.speed_switch_armed);
+    }
+    #[test]
+    fn test_cgb_vram_and_wram_banks() {
+        let mut bus = MemoryBus::with_model(Model::Cgb);
+        bus.write_data(0x8000, 0x10);
+        bus.write_data(0xFF4F, 0x01);
+        assert_eq!(bus.read_data(0xFF4F), 0xFF);
+        assert_eq!(bus.read_data(0x8000), 0x00);
+        bus.write_data(0x8000, 0x11);
+        assert_eq!(bus.vram[0x2000], 0x11);
 
-        let mut file = File::open(String::from("/home/elodie/Downloads/Tetris (World).gb"))
-            .expect("file not found");
+        bus.write_data(0xC000, 0x20);
+        bus.write_data(0xD000, 0x21);
+        bus.write_data(0xFF70, 0x07);
+        assert_eq!(bus.read_data(0xFF70), 0xFF);
+        bus.write_data(0xD000, 0x27);
+        assert_eq!(bus.read_data(0xC000), 0x20);
+        assert_eq!(bus.wram[7 * 0x1000], 0x27);
+        // bank 0 selects bank 1
+        bus.write_data(0xFF70, 0x00);
+        assert_eq!(bus.read_data(0xD000), 0x21);
+        assert_eq!(bus.read_data(0xF000), 0x21);
 
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)
-            .expect("Failed to read ROM file");
+        assert_eq!((bus.vram.len(), bus.wram.len()), (0x4000, 0x8000));
+        let bus = MemoryBus::new();
+        assert_eq!((bus.vram.len(), bus.wram.len()), (0x2000, 0x2000));
+    }
+    #[test]
+    fn test_ppu_draws_from_vram_bank_0_only() {
+        let mut bus = MemoryBus::with_model(Model::Cgb);
+        // bank 1: tile 0 solid color 3, and map attributes picking bank 1
+        bus.write_data(0xFF4F, 0x01);
+        for address in 0x8000..0x8010 {
+            bus.write_data(address, 0xFF);
+        }
+        bus.write_data(0x9800, 0x08);
+        bus.write_data(0xFF47, 0xE4);
+        bus.write_data(0xFF40, 0x91);
+        for _ in 0..154 {
+            bus.tick(114);
+        }
+        // CGB attributes are not supported: bank 0's blank tile is drawn
+        assert_eq!(bus.ppu.frame()[0], 0);
+    }
+    #[test]
+    fn test_pending_interrupts_needs_enable_and_flag() {
+        let mut bus = MemoryBus::new();
+        bus.request_interrupt(Interrupt::Joypad);
+        bus.request_interrupt(Interrupt::Stat);
+        assert_eq!(bus.pending_interrupts(), 0);
+        bus.write_data(0xFFFF, 0xFF);
+        assert_eq!(bus.pending_interrupts(), 0x12);
+        bus.acknowledge_interrupt(Interrupt::Stat);
+        assert_eq!(bus.pending_interrupts(), 0x10);
+        bus.write_data(0xFF0F, 0xFF);
+        assert_eq!(bus.read_data(0xFF0F), 0xFF);
+        assert_eq!(bus.pending_interrupts(), 0x1F);
+    }
+    #[test]
+    fn test_boot_rom_unmaps_on_ff50_write() {
+        let mut bus = MemoryBus::new();
+        bus.load_cartridge(Cartridge::from_bytes(test_rom(0x00, 0x00, 0x00)).unwrap());
+        bus.boot_rom = Some(BootRom::new(vec![0x31; boot::DMG_BOOT_ROM_SIZE]).unwrap());
+        assert_eq!(bus.read_data(0x0000), 0x31);
+        assert_eq!(bus.read_data(0x0104), 0xCE); // header logo shows through
+        bus.write_data(0xFF50, 0x00);
+        assert_eq!(bus.read_data(0x0000), 0x31);
+        bus.write_data(0xFF50, 0x01);
+        assert_eq!(bus.read_data(0x0000), 0x00);
+        assert_eq!(bus.read_data(0xFF50), 0xFF);
+    }
+    #[test]
+    fn test_skip_boot_io_state() {
+        let mut bus = MemoryBus::new();
+        bus.skip_boot();
+        assert_eq!(bus.read_data(0xFF04), 0xAB);
+        assert_eq!(bus.read_data(0xFF0F), 0xE1);
+        assert_eq!(bus.read_data(0xFF40), 0x91);
+        assert_eq!(bus.read_data(0xFF26), 0xF1);
+        let mut bus = MemoryBus::with_model(Model::Sgb);
+        bus.skip_boot();
+        assert_eq!(bus.read_data(0xFF26), 0xF0);
+        let mut bus = MemoryBus::with_model(Model::Cgb);
+        bus.skip_boot();
+        assert_eq!(bus.read_data(0xFF02), 0x7F);
+    }
+    #[test]
+    fn test_load_cartridge() {
+        let mut bus = MemoryBus::new();
+        let rom = test_rom(0x01, 0x02, 0x00);
+        bus.load_cartridge(Cartridge::from_bytes(rom.clone()).unwrap());
 
-        assert_eq!(bus.data[0..buffer.len()], buffer[..])
+        for address in [0x0000, 0x0134, 0x3FFF, 0x4000, 0x7FFF] {
+            assert_eq!(bus.read_data(address), rom[address as usize]);
+        }
+        assert_eq!(bus.cartridge.as_ref().unwrap().header.title, "TEST");
     }
 }
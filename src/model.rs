@@ -0,0 +1,73 @@
+use crate::cartridge::{CgbSupport, Header};
+
+/// The console being emulated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Model {
+    /// Original Game Boy
+    Dmg,
+    /// Game Boy Pocket and Light
+    Mgb,
+    /// Super Game Boy
+    Sgb,
+    /// Super Game Boy 2
+    Sgb2,
+    /// Game Boy Color
+    Cgb,
+    /// Game Boy Advance, in its Game Boy Color mode
+    Agb,
+}
+
+impl Model {
+    /// The model a cartridge is meant for: CGB if its header says it uses
+    /// CGB features, DMG otherwise.
+    pub fn for_header(header: &Header) -> Model {
+        match header.cgb {
+            CgbSupport::None => Model::Dmg,
+            CgbSupport::Compatible | CgbSupport::Only => Model::Cgb,
+        }
+    }
+
+    /// Whether this model has the CGB hardware (CGB and AGB).
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+    pub fn is_sgb(self) -> bool {
+        matches!(self, Model::Sgb | Model::Sgb2)
+    }
+
+    /// Whether writes to STAT spuriously raise the STAT interrupt, as on
+    /// the monochrome models.
+    pub fn has_stat_write_bug(self) -> bool {
+        !self.is_cgb()
+    }
+
+    /// What a read from the unusable 0xFEA0-0xFEFF area returns: 0x00 on
+    /// the monochrome models, the high nibble of the address's low byte
+    /// twice on CGB (revision E) and AGB.
+    pub fn unusable_area_read(self, address: u16) -> u8 {
+        if self.is_cgb() {
+            let nibble = (address as u8) >> 4;
+            nibble << 4 | nibble
+        } else {
+            0x00
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{fix_checksums, test_rom};
+
+    #[test]
+    fn test_model_for_header() {
+        let mut rom = test_rom(0x00, 0x00, 0x00);
+        assert_eq!(Model::for_header(&Header::parse(&rom).unwrap()), Model::Dmg);
+        rom[0x143] = 0x80;
+        fix_checksums(&mut rom);
+        assert_eq!(Model::for_header(&Header::parse(&rom).unwrap()), Model::Cgb);
+        rom[0x143] = 0xC0;
+        fix_checksums(&mut rom);
+        assert_eq!(Model::for_header(&Header::parse(&rom).unwrap()), Model::Cgb);
+    }
+}
@@ -0,0 +1,869 @@
+mod fifo;
+
+use crate::model::Model;
+use fifo::FifoLine;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+/// Dots (base clock cycles) per scanline.
+const LINE_DOTS: u16 = 456;
+/// Scanlines per frame, including the ten of VBlank.
+const LINES: u8 = 154;
+const OAM_SCAN_DOTS: u16 = 80;
+/// Mode 3 length with no scrolling, window or sprites.
+const DRAWING_DOTS: u16 = 172;
+const MAX_SPRITES_PER_LINE: usize = 10;
+/// Dots into line 153 after which LY already reads 0.
+const LINE_153_LY_DOTS: u16 = 4;
+
+/// LCDC (0xFF40) bits.
+const LCDC_BG_ENABLE: u8 = 0x01;
+const LCDC_OBJ_ENABLE: u8 = 0x02;
+const LCDC_OBJ_TALL: u8 = 0x04;
+const LCDC_BG_MAP: u8 = 0x08;
+const LCDC_TILE_DATA: u8 = 0x10;
+const LCDC_WINDOW_ENABLE: u8 = 0x20;
+const LCDC_WINDOW_MAP: u8 = 0x40;
+const LCDC_ENABLE: u8 = 0x80;
+
+/// STAT (0xFF41) interrupt source enables.
+const STAT_HBLANK: u8 = 0x08;
+const STAT_VBLANK: u8 = 0x10;
+const STAT_OAM_SCAN: u8 = 0x20;
+const STAT_LYC: u8 = 0x40;
+
+/// What the PPU is doing, as reported in STAT bits 0-1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+/// How the PPU turns VRAM into pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Renderer {
+    /// Each line is drawn at once at the start of mode 3, which always
+    /// lasts 172 dots. Register writes made during mode 3 are not seen
+    /// until the next line, so mid-line raster effects are lost.
+    Scanline,
+    /// A pixel fetcher and FIFOs run through mode 3 a dot at a time, so
+    /// its length varies with SCX, the window and sprites, and register
+    /// writes in the middle of a line take effect where they land.
+    Fifo,
+}
+
+/// A sprite's OAM entry.
+#[derive(Copy, Clone, Debug)]
+struct Sprite {
+    /// Screen Y + 16
+    y: u8,
+    /// Screen X + 8
+    x: u8,
+    tile: u8,
+    attributes: u8,
+}
+
+/// The picture processing unit: walks the 154 scanlines of a frame through
+/// modes 2 (OAM scan), 3 (drawing) and 0 (HBlank), then VBlank, and renders
+/// each visible line into a 160x144 framebuffer of DMG shades.
+///
+/// The default scanline renderer draws a whole line at the start of mode 3
+/// from the registers at that moment, so changes in the middle of mode 3
+/// only take effect on the next line; see `Renderer::Fifo` for those. Like
+/// the cartridge, the PPU does not own the memory it reads: the bus lends
+/// it VRAM and OAM on each tick.
+///
+/// Only VRAM bank 0 is read, and only DMG shades are drawn: on CGB, tile
+/// attributes, bank 1 tiles and color palettes are not supported yet.
+pub struct Ppu {
+    lcdc: u8,
+    /// The interrupt source enables; the other STAT bits are live
+    stat: u8,
+    /// LY = LYC, as of the last compare
+    coincidence: bool,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    /// Dots into the current line
+    dot: u16,
+    /// Dot at which mode 3 of the current line ends
+    drawing_end: u16,
+    /// Line of the window to draw next; only advances on lines showing it
+    window_line: u8,
+    /// LY has matched WY on some line of this frame, letting the window
+    /// show on the lines after it
+    wy_triggered: bool,
+    renderer: Renderer,
+    /// Mode 3 in progress, with the FIFO renderer
+    fifo: Option<FifoLine>,
+    /// Shades 0 (white) to 3 (black), row by row
+    frame: Vec<u8>,
+    frame_ready: bool,
+    vblank_interrupt: bool,
+    /// The OR of the enabled STAT sources; the interrupt fires as it rises
+    stat_line: bool,
+    stat_interrupt: bool,
+    /// Writes to STAT briefly act as if every source were enabled (DMG)
+    stat_write_bug: bool,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ppu {
+    pub fn new() -> Ppu {
+        Ppu::with_model(Model::Dmg)
+    }
+
+    pub fn with_model(model: Model) -> Ppu {
+        Ppu {
+            lcdc: 0,
+            stat: 0,
+            coincidence: false,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            dot: 0,
+            drawing_end: OAM_SCAN_DOTS + DRAWING_DOTS,
+            window_line: 0,
+            wy_triggered: false,
+            renderer: Renderer::Scanline,
+            fifo: None,
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_ready: false,
+            vblank_interrupt: false,
+            stat_line: false,
+            stat_interrupt: false,
+            stat_write_bug: model.has_stat_write_bug(),
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0xFF40 => self.lcdc,
+            0xFF41 => 0x80 | self.stat | (self.coincidence as u8) << 2 | self.mode() as u8,
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly_register(),
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF40 => {
+                if value & LCDC_ENABLE == 0 && self.lcd_enabled() {
+                    // off, the LCD rests at the start of line 0
+                    self.ly = 0;
+                    self.dot = 0;
+                    self.window_line = 0;
+                    self.wy_triggered = false;
+                    self.fifo = None;
+                }
+                self.lcdc = value;
+                self.update_stat();
+            }
+            0xFF41 => {
+                if self.stat_write_bug && self.lcd_enabled() && !self.stat_line {
+                    // for a cycle, all of HBlank, VBlank and LY = LYC fire
+                    if self.stat_sources(STAT_HBLANK | STAT_VBLANK | STAT_LYC) {
+                        self.stat_interrupt = true;
+                        self.stat_line = true;
+                    }
+                }
+                self.stat = value & 0x78;
+                self.update_stat();
+            }
+            0xFF42 => self.scy = value,
+            0xFF43 => self.scx = value,
+            // LY is read-only
+            0xFF44 => {}
+            0xFF45 => {
+                self.lyc = value;
+                self.update_stat();
+            }
+            0xFF47 => self.bgp = value,
+            0xFF48 => self.obp0 = value,
+            0xFF49 => self.obp1 = value,
+            0xFF4A => self.wy = value,
+            0xFF4B => self.wx = value,
+            _ => {}
+        }
+    }
+
+    /// Switches renderers; takes effect from the next line.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & LCDC_ENABLE != 0
+    }
+
+    pub fn mode(&self) -> Mode {
+        if !self.lcd_enabled() {
+            Mode::HBlank
+        } else if self.ly >= SCREEN_HEIGHT as u8 {
+            Mode::VBlank
+        } else if self.dot < OAM_SCAN_DOTS {
+            Mode::OamScan
+        } else if self.dot < self.drawing_end {
+            Mode::Drawing
+        } else {
+            Mode::HBlank
+        }
+    }
+
+    /// The last completed frame: 160x144 shades, 0 white to 3 black.
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+    /// Whether a frame was completed since the last call.
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+    /// Whether VBlank started since the last call.
+    pub fn take_vblank_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.vblank_interrupt)
+    }
+    /// Whether the STAT interrupt fired since the last call.
+    pub fn take_stat_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.stat_interrupt)
+    }
+
+    /// LY as the CPU and the LYC compare see it: line 153 reads as 0 for
+    /// all but its first few dots.
+    fn ly_register(&self) -> u8 {
+        if self.ly == LINES - 1 && self.dot >= LINE_153_LY_DOTS {
+            0
+        } else {
+            self.ly
+        }
+    }
+
+    /// Whether any of the STAT sources in `enables` currently holds.
+    fn stat_sources(&self, enables: u8) -> bool {
+        let mode = match self.mode() {
+            Mode::HBlank => STAT_HBLANK,
+            Mode::VBlank => STAT_VBLANK,
+            Mode::OamScan => STAT_OAM_SCAN,
+            Mode::Drawing => 0,
+        };
+        let lyc = if self.coincidence { STAT_LYC } else { 0 };
+        enables & (mode | lyc) != 0
+    }
+
+    /// Redoes the LY = LYC compare and the STAT interrupt line. The sources
+    /// are OR'ed into one line and only its rising edge fires, so a source
+    /// cannot fire while another one holds the line high.
+    fn update_stat(&mut self) {
+        if !self.lcd_enabled() {
+            self.stat_line = false;
+            return;
+        }
+        self.coincidence = self.ly_register() == self.lyc;
+        let line = self.stat_sources(self.stat);
+        if line && !self.stat_line {
+            self.stat_interrupt = true;
+        }
+        self.stat_line = line;
+    }
+
+    /// Advances by `dots` base clock cycles. `vram` is VRAM bank 0 (8 KiB)
+    /// and `oam` the 160 bytes of OAM.
+    pub fn tick(&mut self, dots: u32, vram: &[u8], oam: &[u8]) {
+        if !self.lcd_enabled() {
+            return;
+        }
+        for _ in 0..dots {
+            self.dot += 1;
+            if self.dot == 1 && self.ly == self.wy {
+                self.wy_triggered = true;
+            }
+            if self.dot == OAM_SCAN_DOTS && (self.ly as usize) < SCREEN_HEIGHT {
+                match self.renderer {
+                    Renderer::Scanline => {
+                        self.render_line(vram, oam);
+                        self.drawing_end = OAM_SCAN_DOTS + DRAWING_DOTS;
+                    }
+                    Renderer::Fifo => {
+                        self.fifo = Some(FifoLine::new(self, oam));
+                        // until the last pixel is out
+                        self.drawing_end = LINE_DOTS;
+                    }
+                }
+            } else if let Some(mut line) = self.fifo.take() {
+                if line.step(self, vram) {
+                    self.drawing_end = self.dot + 1;
+                    if line.drew_window() {
+                        self.window_line += 1;
+                    }
+                } else {
+                    self.fifo = Some(line);
+                }
+            }
+            if self.dot == LINE_DOTS {
+                self.dot = 0;
+                self.fifo = None;
+                self.next_line();
+            }
+            self.update_stat();
+        }
+    }
+
+    fn next_line(&mut self) {
+        self.ly += 1;
+        if self.ly as usize == SCREEN_HEIGHT {
+            self.vblank_interrupt = true;
+            self.frame_ready = true;
+        } else if self.ly == LINES {
+            self.ly = 0;
+            self.window_line = 0;
+            self.wy_triggered = false;
+        }
+    }
+
+    fn render_line(&mut self, vram: &[u8], oam: &[u8]) {
+        let y = self.ly as usize;
+        // color indices before the palette, for sprite priority
+        let mut bg = [0u8; SCREEN_WIDTH];
+        if self.lcdc & LCDC_BG_ENABLE != 0 {
+            let map = if self.lcdc & LCDC_BG_MAP != 0 {
+                0x1C00
+            } else {
+                0x1800
+            };
+            let bg_y = self.ly.wrapping_add(self.scy);
+            for (x, color) in bg.iter_mut().enumerate() {
+                let bg_x = (x as u8).wrapping_add(self.scx);
+                *color = self.tile_map_pixel(vram, map, bg_x, bg_y);
+            }
+            self.render_window(vram, &mut bg);
+        }
+        for (x, &color) in bg.iter().enumerate() {
+            self.frame[y * SCREEN_WIDTH + x] = shade(self.bgp, color);
+        }
+        if self.lcdc & LCDC_OBJ_ENABLE != 0 {
+            self.render_sprites(vram, oam, &bg);
+        }
+    }
+
+    /// Draws the window over `bg` if it is visible on this line. On DMG,
+    /// LCDC bit 0 hides the window along with the background.
+    fn render_window(&mut self, vram: &[u8], bg: &mut [u8; SCREEN_WIDTH]) {
+        if self.lcdc & LCDC_WINDOW_ENABLE == 0 || !self.wy_triggered || self.wx > 166 {
+            return;
+        }
+        let map = if self.lcdc & LCDC_WINDOW_MAP != 0 {
+            0x1C00
+        } else {
+            0x1800
+        };
+        let start = self.wx as i16 - 7;
+        for (x, color) in bg.iter_mut().enumerate().skip(start.max(0) as usize) {
+            let window_x = (x as i16 - start) as u8;
+            *color = self.tile_map_pixel(vram, map, window_x, self.window_line);
+        }
+        self.window_line += 1;
+    }
+
+    /// Color index at (`x`, `y`) of the 256x256 tile map at `map` (an
+    /// offset into VRAM), with LCDC bit 4's tile data addressing.
+    fn tile_map_pixel(&self, vram: &[u8], map: usize, x: u8, y: u8) -> u8 {
+        let tile = vram[map + (y as usize / 8) * 32 + x as usize / 8];
+        tile_pixel(vram, self.tile_address(tile), x % 8, y % 8)
+    }
+
+    /// VRAM offset of background or window tile `tile` under LCDC bit 4.
+    fn tile_address(&self, tile: u8) -> usize {
+        if self.lcdc & LCDC_TILE_DATA != 0 {
+            tile as usize * 16
+        } else {
+            (0x1000 + (tile as i8 as isize) * 16) as usize
+        }
+    }
+
+    fn sprite_height(&self) -> u8 {
+        if self.lcdc & LCDC_OBJ_TALL != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// The sprites the OAM scan picks for this line: the first ten in OAM
+    /// order that cover it.
+    fn scan_oam(&self, oam: &[u8]) -> Vec<Sprite> {
+        let height = self.sprite_height() as i16;
+        let line = self.ly as i16;
+        oam.chunks_exact(4)
+            .map(|entry| Sprite {
+                y: entry[0],
+                x: entry[1],
+                tile: entry[2],
+                attributes: entry[3],
+            })
+            .filter(|s| (0..height).contains(&(line - (s.y as i16 - 16))))
+            .take(MAX_SPRITES_PER_LINE)
+            .collect()
+    }
+
+    /// Color indices of `sprite`'s eight pixels on this line, left to
+    /// right, with its flips applied.
+    fn sprite_row(&self, vram: &[u8], sprite: &Sprite) -> [u8; 8] {
+        let height = self.sprite_height();
+        let mut row = (self.ly as i16 - (sprite.y as i16 - 16)) as u8;
+        if sprite.attributes & 0x40 != 0 {
+            row = height - 1 - row;
+        }
+        let tile = if height == 16 {
+            sprite.tile & 0xFE
+        } else {
+            sprite.tile
+        };
+        std::array::from_fn(|column| {
+            let column = column as u8;
+            let pixel_column = if sprite.attributes & 0x20 != 0 {
+                7 - column
+            } else {
+                column
+            };
+            tile_pixel(vram, tile as usize * 16, pixel_column, row)
+        })
+    }
+
+    fn sprite_palette(&self, sprite: &Sprite) -> u8 {
+        if sprite.attributes & 0x10 != 0 {
+            self.obp1
+        } else {
+            self.obp0
+        }
+    }
+
+    fn render_sprites(&mut self, vram: &[u8], oam: &[u8], bg: &[u8; SCREEN_WIDTH]) {
+        let mut sprites = self.scan_oam(oam);
+        // DMG priority: lower X first, then OAM order (the sort is stable).
+        // Drawing in reverse puts the highest priority on top.
+        sprites.sort_by_key(|s| s.x);
+        let y = self.ly as usize;
+        for sprite in sprites.iter().rev() {
+            let palette = self.sprite_palette(sprite);
+            for (column, color) in self.sprite_row(vram, sprite).into_iter().enumerate() {
+                let x = sprite.x as i16 - 8 + column as i16;
+                if !(0..SCREEN_WIDTH as i16).contains(&x) {
+                    continue;
+                }
+                let behind_bg = sprite.attributes & 0x80 != 0 && bg[x as usize] != 0;
+                if color != 0 && !behind_bg {
+                    self.frame[y * SCREEN_WIDTH + x as usize] = shade(palette, color);
+                }
+            }
+        }
+    }
+}
+
+/// Color index of pixel (`x`, `y`) of the 2bpp tile at `tile_address`. Rows
+/// past the first tile continue into the next one, as 8x16 sprites need.
+fn tile_pixel(vram: &[u8], tile_address: usize, x: u8, y: u8) -> u8 {
+    let row = tile_address + y as usize * 2;
+    let bit = 7 - x;
+    let low = (vram[row] >> bit) & 1;
+    let high = (vram[row + 1] >> bit) & 1;
+    high << 1 | low
+}
+
+/// Shade of color index `color` through the DMG palette `palette`.
+fn shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0x03
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_DOTS: u32 = LINE_DOTS as u32 * LINES as u32;
+
+    /// VRAM with tile 1 solid color 3 and tile 2 solid color 1.
+    fn test_vram() -> Vec<u8> {
+        let mut vram = vec![0; 0x2000];
+        vram[0x10..0x20].fill(0xFF);
+        for row in 0..8 {
+            vram[0x20 + row * 2] = 0xFF;
+        }
+        vram
+    }
+
+    fn enabled_ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.write(0xFF47, 0xE4);
+        ppu.write(0xFF48, 0xE4);
+        ppu.write(0xFF49, 0x1B);
+        ppu.write(0xFF40, 0x91);
+        ppu
+    }
+
+    fn pixel(ppu: &Ppu, x: usize, y: usize) -> u8 {
+        ppu.frame()[y * SCREEN_WIDTH + x]
+    }
+
+    #[test]
+    fn test_mode_sequence_and_vblank() {
+        let mut ppu = enabled_ppu();
+        let vram = test_vram();
+        let oam = [0; 0xA0];
+        assert_eq!(ppu.mode(), Mode::OamScan);
+        ppu.tick(80, &vram, &oam);
+        assert_eq!(ppu.mode(), Mode::Drawing);
+        ppu.tick(172, &vram, &oam);
+        assert_eq!(ppu.mode(), Mode::HBlank);
+        ppu.tick(204, &vram, &oam);
+        assert_eq!((ppu.read(0xFF44), ppu.mode()), (1, Mode::OamScan));
+
+        ppu.tick(143 * LINE_DOTS as u32 - 1, &vram, &oam);
+        assert!(!ppu.take_vblank_interrupt());
+        ppu.tick(1, &vram, &oam);
+        assert_eq!((ppu.read(0xFF44), ppu.mode()), (144, Mode::VBlank));
+        assert!(ppu.take_vblank_interrupt());
+        assert!(ppu.take_frame_ready());
+        ppu.tick(10 * LINE_DOTS as u32, &vram, &oam);
+        assert_eq!(ppu.read(0xFF44), 0);
+
+        ppu.write(0xFF40, 0x00);
+        ppu.tick(FRAME_DOTS, &vram, &oam);
+        assert_eq!((ppu.read(0xFF44), ppu.mode()), (0, Mode::HBlank));
+    }
+
+    #[test]
+    fn test_background_scroll_and_addressing() {
+        let mut ppu = enabled_ppu();
+        let mut vram = test_vram();
+        vram[0x1800 + 1] = 1; // map 0x9800, tile (1, 0)
+        ppu.write(0xFF43, 4);
+        ppu.tick(FRAME_DOTS, &vram, &[0; 0xA0]);
+        assert_eq!(pixel(&ppu, 3, 0), 0);
+        assert_eq!(pixel(&ppu, 4, 0), 3);
+        assert_eq!(pixel(&ppu, 11, 7), 3);
+        assert_eq!(pixel(&ppu, 12, 0), 0);
+
+        // signed addressing: tile 1 is at 0x9010
+        ppu.write(0xFF40, 0x81);
+        vram[0x1010..0x1020].fill(0xFF);
+        vram[0x10..0x20].fill(0x00);
+        ppu.tick(FRAME_DOTS, &vram, &[0; 0xA0]);
+        assert_eq!(pixel(&ppu, 4, 0), 3);
+    }
+
+    #[test]
+    fn test_window() {
+        let mut ppu = enabled_ppu();
+        let mut vram = test_vram();
+        vram[0x1C00..0x1C00 + 32].fill(2); // window map at 0x9C00
+        ppu.write(0xFF40, 0xF1);
+        ppu.write(0xFF4A, 10);
+        ppu.write(0xFF4B, 7 + 80);
+        ppu.tick(FRAME_DOTS, &vram, &[0; 0xA0]);
+        assert_eq!(pixel(&ppu, 80, 9), 0);
+        assert_eq!(pixel(&ppu, 79, 10), 0);
+        assert_eq!(pixel(&ppu, 80, 10), 1);
+        assert_eq!(pixel(&ppu, 159, 17), 1);
+    }
+
+    #[test]
+    fn test_window_waits_for_wy_match() {
+        let mut vram = test_vram();
+        vram[0x1C00..0x2000].fill(2);
+        let oam = [0; 0xA0];
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut ppu = enabled_ppu();
+            ppu.set_renderer(renderer);
+            ppu.write(0xFF40, 0xF1);
+            ppu.write(0xFF4B, 7);
+            // WY lowered past LY: no match this frame, so no window
+            ppu.write(0xFF4A, 100);
+            ppu.tick(20 * LINE_DOTS as u32, &vram, &oam);
+            ppu.write(0xFF4A, 10);
+            ppu.tick(40 * LINE_DOTS as u32, &vram, &oam);
+            assert_eq!(pixel(&ppu, 0, 25), 0);
+            // once matched, moving WY away does not hide it again
+            ppu.tick((LINES as u32 - 60) * LINE_DOTS as u32, &vram, &oam);
+            ppu.tick(20 * LINE_DOTS as u32, &vram, &oam);
+            ppu.write(0xFF4A, 100);
+            ppu.tick(20 * LINE_DOTS as u32, &vram, &oam);
+            assert_eq!(pixel(&ppu, 0, 9), 0);
+            assert_eq!(pixel(&ppu, 0, 25), 1);
+        }
+    }
+
+    #[test]
+    fn test_sprites() {
+        let mut ppu = enabled_ppu();
+        let mut vram = test_vram();
+        // a tile with only its leftmost column set, color 3
+        for row in 0..8 {
+            vram[0x30 + row * 2] = 0x80;
+            vram[0x31 + row * 2] = 0x80;
+        }
+        let mut oam = [0; 0xA0];
+        oam[0..4].copy_from_slice(&[16, 8, 3, 0x00]); // at (0, 0)
+        oam[4..8].copy_from_slice(&[16, 20, 3, 0x20]); // X flipped: column 19
+        oam[8..12].copy_from_slice(&[16, 30, 3, 0x10]); // OBP1: color 3 is shade 0
+        ppu.write(0xFF40, 0x93);
+        ppu.tick(FRAME_DOTS, &vram, &oam);
+        assert_eq!(pixel(&ppu, 0, 0), 3);
+        assert_eq!(pixel(&ppu, 1, 0), 0);
+        assert_eq!(pixel(&ppu, 19, 7), 3);
+        assert_eq!(pixel(&ppu, 12, 0), 0);
+        assert_eq!(pixel(&ppu, 22, 0), 0);
+        assert_eq!(pixel(&ppu, 0, 8), 0);
+
+        // 8x16 uses tile 2/3 for tile 3, so the lower half has the column
+        ppu.write(0xFF40, 0x97);
+        ppu.tick(FRAME_DOTS, &vram, &oam);
+        assert_eq!(pixel(&ppu, 0, 0), 1);
+        assert_eq!(pixel(&ppu, 0, 8), 3);
+    }
+
+    #[test]
+    fn test_sprite_priority_and_limit() {
+        let mut ppu = enabled_ppu();
+        let mut vram = test_vram();
+        vram[0x1800] = 2; // BG color 1 under x 0-7
+        let mut oam = [0; 0xA0];
+        // behind BG colors 1-3
+        oam[0..4].copy_from_slice(&[16, 8, 1, 0x80]);
+        // eleven sprites on line 16: the last is dropped
+        for i in 1..12 {
+            oam[i * 4..i * 4 + 4].copy_from_slice(&[32, 8 + 8 * i as u8, 1, 0x00]);
+        }
+        ppu.write(0xFF40, 0x93);
+        ppu.tick(FRAME_DOTS, &vram, &oam);
+        assert_eq!(pixel(&ppu, 0, 0), 1);
+        assert_eq!(pixel(&ppu, 8 * 10, 16), 3);
+        assert_eq!(pixel(&ppu, 8 * 11, 16), 0);
+
+        // overlapping: the lower X wins even if later in OAM
+        let mut oam = [0; 0xA0];
+        oam[0..4].copy_from_slice(&[16, 8, 1, 0x10]); // OBP1: shade 0
+        oam[4..8].copy_from_slice(&[16, 6, 1, 0x00]);
+        ppu.tick(FRAME_DOTS, &vram, &oam);
+        assert_eq!(pixel(&ppu, 5, 0), 3);
+        assert_eq!(pixel(&ppu, 6, 0), 0);
+    }
+
+    /// Dots spent in mode 3 on the next visible line.
+    fn drawing_dots(ppu: &mut Ppu, vram: &[u8], oam: &[u8]) -> u32 {
+        while ppu.mode() != Mode::OamScan {
+            ppu.tick(1, vram, oam);
+        }
+        let mut dots = 0;
+        while ppu.mode() != Mode::HBlank {
+            ppu.tick(1, vram, oam);
+            if ppu.mode() == Mode::Drawing {
+                dots += 1;
+            }
+        }
+        dots
+    }
+
+    #[test]
+    fn test_fifo_matches_scanline() {
+        let mut vram = test_vram();
+        for (i, tile) in vram[0x1800..0x1C00].iter_mut().enumerate() {
+            *tile = (i % 3) as u8;
+        }
+        vram[0x1C00..0x1C00 + 32].fill(2);
+        for row in 0..8 {
+            vram[0x30 + row * 2] = 0x80;
+            vram[0x31 + row * 2] = 0xC0;
+        }
+        let mut oam = [0; 0xA0];
+        oam[0..4].copy_from_slice(&[16, 4, 3, 0x00]); // off the left edge
+        oam[4..8].copy_from_slice(&[20, 40, 3, 0x20]);
+        oam[8..12].copy_from_slice(&[20, 42, 3, 0x10]);
+        oam[12..16].copy_from_slice(&[30, 90, 1, 0x80]);
+        oam[16..20].copy_from_slice(&[40, 161, 3, 0x40]);
+
+        let mut frames = Vec::new();
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut ppu = enabled_ppu();
+            ppu.set_renderer(renderer);
+            ppu.write(0xFF40, 0xF3);
+            ppu.write(0xFF42, 5);
+            ppu.write(0xFF43, 13);
+            ppu.write(0xFF4A, 50);
+            ppu.write(0xFF4B, 7 + 100);
+            ppu.tick(FRAME_DOTS, &vram, &oam);
+            frames.push(ppu.frame().to_vec());
+        }
+        assert_eq!(frames[0], frames[1]);
+    }
+
+    #[test]
+    fn test_fifo_mode_3_length() {
+        let vram = test_vram();
+        let mut oam = [0; 0xA0];
+        let mut ppu = enabled_ppu();
+        assert_eq!(drawing_dots(&mut ppu, &vram, &oam), 172);
+        ppu.set_renderer(Renderer::Fifo);
+        assert_eq!(drawing_dots(&mut ppu, &vram, &oam), 172);
+
+        // SCX fine scroll drops pixels from the first tile
+        ppu.write(0xFF43, 3);
+        assert_eq!(drawing_dots(&mut ppu, &vram, &oam), 175);
+        ppu.write(0xFF43, 0);
+
+        // starting the window restarts the fetcher
+        ppu.write(0xFF40, 0xB1);
+        ppu.write(0xFF4B, 7 + 80);
+        assert_eq!(drawing_dots(&mut ppu, &vram, &oam), 178);
+
+        // each sprite stalls the output while it is fetched
+        ppu.write(0xFF40, 0x93);
+        oam[0..4].copy_from_slice(&[16, 8 + 80, 1, 0x00]);
+        // at the start of a tile: 6 dots, plus 5 waiting on the tile fetch
+        assert_eq!(drawing_dots(&mut ppu, &vram, &oam), 172 + 11);
+        // further into the tile, less of the wait is left
+        oam[0..4].copy_from_slice(&[16, 8 + 83, 1, 0x00]);
+        assert_eq!(drawing_dots(&mut ppu, &vram, &oam), 172 + 8);
+        // a second sprite over the same tile does not wait again
+        oam[4..8].copy_from_slice(&[16, 8 + 84, 1, 0x00]);
+        assert_eq!(drawing_dots(&mut ppu, &vram, &oam), 172 + 8 + 6);
+        // the scanline renderer ignores all of that
+        ppu.set_renderer(Renderer::Scanline);
+        assert_eq!(drawing_dots(&mut ppu, &vram, &oam), 172);
+    }
+
+    #[test]
+    fn test_fifo_mid_line_register_writes() {
+        let mut vram = test_vram();
+        vram[0x1800..0x1C00].fill(1);
+        let oam = [0; 0xA0];
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut ppu = enabled_ppu();
+            ppu.set_renderer(renderer);
+            // past the OAM scan, the 12-dot startup and 80 pixels
+            ppu.tick(80 + 12 + 80, &vram, &oam);
+            ppu.write(0xFF47, 0x00);
+            ppu.tick(LINE_DOTS as u32, &vram, &oam);
+            assert_eq!(pixel(&ppu, 0, 0), 3);
+            let right = if renderer == Renderer::Fifo { 0 } else { 3 };
+            assert_eq!(pixel(&ppu, 159, 0), right);
+            assert_eq!(pixel(&ppu, 0, 1), 0);
+        }
+    }
+
+    #[test]
+    fn test_stat_mode_and_coincidence_bits() {
+        let mut ppu = enabled_ppu();
+        let vram = test_vram();
+        let oam = [0; 0xA0];
+        ppu.write(0xFF45, 1);
+        assert_eq!(ppu.read(0xFF41), 0x82);
+        ppu.tick(80, &vram, &oam);
+        assert_eq!(ppu.read(0xFF41), 0x83);
+        ppu.tick(172, &vram, &oam);
+        assert_eq!(ppu.read(0xFF41), 0x80);
+        ppu.tick(204, &vram, &oam);
+        assert_eq!(ppu.read(0xFF41), 0x86);
+        ppu.write(0xFF41, 0xFF);
+        assert_eq!(ppu.read(0xFF41), 0xFE);
+        ppu.tick(143 * LINE_DOTS as u32, &vram, &oam);
+        assert_eq!(ppu.read(0xFF41), 0xF9);
+
+        // off, the mode reads 0
+        ppu.write(0xFF40, 0x00);
+        assert_eq!(ppu.read(0xFF41) & 0x03, 0);
+    }
+
+    #[test]
+    fn test_stat_interrupt_blocking() {
+        let mut ppu = enabled_ppu();
+        let vram = test_vram();
+        let oam = [0; 0xA0];
+        // HBlank and LY = LYC for line 1
+        ppu.write(0xFF45, 1);
+        ppu.write(0xFF41, STAT_HBLANK | STAT_LYC);
+        assert!(!ppu.take_stat_interrupt());
+        let mut fired = Vec::new();
+        for _ in 0..3 * LINE_DOTS {
+            ppu.tick(1, &vram, &oam);
+            if ppu.take_stat_interrupt() {
+                fired.push((ppu.read(0xFF44), ppu.mode()));
+            }
+        }
+        // LY = LYC holds the line high from line 0's HBlank through line
+        // 1's, so neither of those two fire again
+        assert_eq!(fired, [(0, Mode::HBlank), (2, Mode::HBlank)]);
+
+        // enabling a source that already holds raises the line
+        ppu.write(0xFF41, STAT_OAM_SCAN);
+        assert!(ppu.take_stat_interrupt());
+    }
+
+    #[test]
+    fn test_line_153_reads_as_line_0() {
+        let mut ppu = enabled_ppu();
+        let vram = test_vram();
+        let oam = [0; 0xA0];
+        // LY = LYC = 0 already holds
+        ppu.write(0xFF41, STAT_LYC);
+        assert!(ppu.take_stat_interrupt());
+        ppu.tick(153 * LINE_DOTS as u32, &vram, &oam);
+        assert_eq!(ppu.read(0xFF44), 153);
+        assert!(!ppu.take_stat_interrupt());
+        ppu.tick(LINE_153_LY_DOTS as u32, &vram, &oam);
+        assert_eq!(ppu.read(0xFF44), 0);
+        assert_eq!(ppu.mode(), Mode::VBlank);
+        assert!(ppu.take_stat_interrupt());
+        // still LY = LYC into line 0: no second interrupt
+        ppu.tick(LINE_DOTS as u32, &vram, &oam);
+        assert!(!ppu.take_stat_interrupt());
+
+        ppu.write(0xFF45, 153);
+        ppu.tick(153 * LINE_DOTS as u32 - 1, &vram, &oam);
+        ppu.tick(1, &vram, &oam);
+        assert!(ppu.take_stat_interrupt());
+    }
+
+    #[test]
+    fn test_stat_write_bug() {
+        let vram = test_vram();
+        let oam = [0; 0xA0];
+        for (model, fires) in [(Model::Dmg, true), (Model::Cgb, false)] {
+            let mut ppu = Ppu::with_model(model);
+            ppu.write(0xFF40, 0x91);
+            ppu.write(0xFF45, 0x90);
+            ppu.tick(80 + 172, &vram, &oam);
+            ppu.write(0xFF41, 0x00);
+            assert_eq!(ppu.take_stat_interrupt(), fires);
+            // not while drawing
+            ppu.tick(LINE_DOTS as u32 - 172, &vram, &oam);
+            ppu.write(0xFF41, 0x00);
+            assert!(!ppu.take_stat_interrupt());
+        }
+    }
+}
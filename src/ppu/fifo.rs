@@ -0,0 +1,253 @@
+use std::collections::VecDeque;
+
+use super::{
+    LCDC_BG_ENABLE, LCDC_BG_MAP, LCDC_OBJ_ENABLE, LCDC_WINDOW_ENABLE, LCDC_WINDOW_MAP, Ppu,
+    SCREEN_WIDTH, Sprite, shade,
+};
+
+/// Dots the fetcher takes to read a tile's number and its two data bytes.
+const FETCH_DOTS: u8 = 6;
+/// Dots a sprite fetch stalls the pixel output for, on top of the wait for
+/// the background fetch it interrupts.
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+/// A sprite pixel waiting in the sprite FIFO.
+#[derive(Copy, Clone, Debug, Default)]
+struct SpritePixel {
+    color: u8,
+    palette: u8,
+    behind_bg: bool,
+}
+
+/// The background fetcher: reads one tile row every six dots, then waits
+/// for the background FIFO to empty before pushing its eight pixels.
+#[derive(Debug, Default)]
+struct Fetcher {
+    /// Dots into the current fetch, up to `FETCH_DOTS`
+    step: u8,
+    /// Tiles fetched so far on this line (or since the window started)
+    tile_x: u8,
+    tile: u8,
+    low: u8,
+    high: u8,
+}
+
+/// Mode 3 of one line, a dot at a time: the fetcher fills the background
+/// FIFO, sprites met along the way stall it to mix their pixels into the
+/// sprite FIFO, and one pixel is shifted out per dot. Registers are read
+/// when the hardware reads them, so mid-line writes land where they would
+/// on the LCD, and mode 3 ends when the 160th pixel is out.
+pub(super) struct FifoLine {
+    /// Sprites from the OAM scan not reached yet, by X then OAM order
+    sprites: VecDeque<Sprite>,
+    /// Color indices before the palette
+    bg: VecDeque<u8>,
+    obj: VecDeque<SpritePixel>,
+    fetcher: Fetcher,
+    /// The first fetch of a line is thrown away
+    first_fetch: bool,
+    /// Pixels still to drop: SCX's fine scroll, or the part of the window
+    /// left of the screen when WX < 7
+    discard: u8,
+    /// Screen column of the next pixel
+    x: u8,
+    in_window: bool,
+    /// Sprite being fetched, and the dots it still stalls for
+    sprite_fetch: Option<(Sprite, u8)>,
+    /// Background or window tile whose fetch the last sprite waited on
+    waited_tile: Option<u8>,
+}
+
+impl FifoLine {
+    pub(super) fn new(ppu: &Ppu, oam: &[u8]) -> FifoLine {
+        let mut sprites = ppu.scan_oam(oam);
+        sprites.sort_by_key(|s| s.x);
+        FifoLine {
+            sprites: sprites.into(),
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(8),
+            fetcher: Fetcher::default(),
+            first_fetch: true,
+            discard: ppu.scx % 8,
+            x: 0,
+            in_window: false,
+            sprite_fetch: None,
+            waited_tile: None,
+        }
+    }
+
+    /// Whether the window was reached on this line.
+    pub(super) fn drew_window(&self) -> bool {
+        self.in_window
+    }
+
+    /// Runs one dot of mode 3. Returns true once the line is complete.
+    pub(super) fn step(&mut self, ppu: &mut Ppu, vram: &[u8]) -> bool {
+        if self.check_window(ppu) {
+            // the fetcher spends this dot restarting
+            return false;
+        }
+        if self.sprite_fetch.is_none() && self.discard == 0 {
+            self.check_sprite(ppu);
+        }
+        if let Some((sprite, dots)) = &mut self.sprite_fetch {
+            *dots -= 1;
+            if *dots == 0 {
+                let sprite = *sprite;
+                self.sprite_fetch = None;
+                self.mix_sprite(ppu, vram, &sprite);
+            }
+            return false;
+        }
+
+        self.fetch_step(ppu, vram);
+        if self.fetcher.step == FETCH_DOTS && self.bg.is_empty() {
+            self.push();
+        }
+        let Some(color) = self.bg.pop_front() else {
+            return false;
+        };
+        let sprite = self.obj.pop_front().unwrap_or_default();
+        if self.discard > 0 {
+            self.discard -= 1;
+            return false;
+        }
+        let color = if ppu.lcdc & LCDC_BG_ENABLE != 0 {
+            color
+        } else {
+            0
+        };
+        let sprite_shown = ppu.lcdc & LCDC_OBJ_ENABLE != 0
+            && sprite.color != 0
+            && !(sprite.behind_bg && color != 0);
+        let pixel = if sprite_shown {
+            shade(sprite.palette, sprite.color)
+        } else {
+            shade(ppu.bgp, color)
+        };
+        ppu.frame[ppu.ly as usize * SCREEN_WIDTH + self.x as usize] = pixel;
+        self.x += 1;
+        self.x as usize == SCREEN_WIDTH
+    }
+
+    /// Switches to fetching the window once the output reaches WX - 7. On
+    /// DMG, LCDC bit 0 hides the window along with the background.
+    /// Returns true on the dot it switches.
+    fn check_window(&mut self, ppu: &Ppu) -> bool {
+        let enabled = ppu.lcdc & LCDC_WINDOW_ENABLE != 0 && ppu.lcdc & LCDC_BG_ENABLE != 0;
+        if self.in_window
+            || !enabled
+            || !ppu.wy_triggered
+            || ppu.wx > 166
+            || (self.x as u16 + 7) < ppu.wx as u16
+        {
+            return false;
+        }
+        self.in_window = true;
+        self.bg.clear();
+        self.fetcher = Fetcher::default();
+        self.discard = 7u8.saturating_sub(ppu.wx);
+        true
+    }
+
+    /// Starts fetching the next sprite if the output has reached it. Those
+    /// hanging off the left edge are all fetched at column 0.
+    ///
+    /// The first sprite over a background tile also waits for that tile's
+    /// fetch to finish: up to 5 dots, fewer the further into the tile it is.
+    fn check_sprite(&mut self, ppu: &Ppu) {
+        if ppu.lcdc & LCDC_OBJ_ENABLE == 0 {
+            return;
+        }
+        let Some(sprite) = self.sprites.front() else {
+            return;
+        };
+        if sprite.x as i16 - 8 > self.x as i16 {
+            return;
+        }
+        let sprite = self.sprites.pop_front().unwrap();
+        let offset = if self.in_window {
+            (self.x + 7).wrapping_sub(ppu.wx)
+        } else {
+            self.x.wrapping_add(ppu.scx)
+        };
+        let wait = if self.waited_tile == Some(offset / 8) {
+            0
+        } else {
+            self.waited_tile = Some(offset / 8);
+            5 - (offset % 8).min(5)
+        };
+        self.sprite_fetch = Some((sprite, SPRITE_FETCH_DOTS + wait));
+    }
+
+    /// Advances the background fetcher by a dot, reading VRAM on the
+    /// second dot of each of its three reads.
+    fn fetch_step(&mut self, ppu: &Ppu, vram: &[u8]) {
+        if self.fetcher.step == FETCH_DOTS {
+            return;
+        }
+        self.fetcher.step += 1;
+        let (map, column, y) = if self.in_window {
+            let map = if ppu.lcdc & LCDC_WINDOW_MAP != 0 {
+                0x1C00
+            } else {
+                0x1800
+            };
+            (map, self.fetcher.tile_x % 32, ppu.window_line)
+        } else {
+            let map = if ppu.lcdc & LCDC_BG_MAP != 0 {
+                0x1C00
+            } else {
+                0x1800
+            };
+            let column = (ppu.scx / 8).wrapping_add(self.fetcher.tile_x) % 32;
+            (map, column, ppu.ly.wrapping_add(ppu.scy))
+        };
+        match self.fetcher.step {
+            2 => self.fetcher.tile = vram[map + (y as usize / 8) * 32 + column as usize],
+            4 | 6 => {
+                let row = ppu.tile_address(self.fetcher.tile) + (y as usize % 8) * 2;
+                if self.fetcher.step == 4 {
+                    self.fetcher.low = vram[row];
+                } else {
+                    self.fetcher.high = vram[row + 1];
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn push(&mut self) {
+        self.fetcher.step = 0;
+        if self.first_fetch {
+            // fetched again from the start
+            self.first_fetch = false;
+            return;
+        }
+        let (low, high) = (self.fetcher.low, self.fetcher.high);
+        self.bg.extend(
+            (0..8)
+                .rev()
+                .map(|bit| ((high >> bit) & 1) << 1 | (low >> bit) & 1),
+        );
+        self.fetcher.tile_x = self.fetcher.tile_x.wrapping_add(1);
+    }
+
+    /// Mixes `sprite` into the sprite FIFO. Pixels already there came from
+    /// sprites with priority, so only transparent ones are replaced.
+    fn mix_sprite(&mut self, ppu: &Ppu, vram: &[u8], sprite: &Sprite) {
+        self.obj.resize(8, SpritePixel::default());
+        let hidden = 8u8.saturating_sub(sprite.x) as usize;
+        let palette = ppu.sprite_palette(sprite);
+        let row = ppu.sprite_row(vram, sprite);
+        for (slot, &color) in self.obj.iter_mut().zip(&row[hidden..]) {
+            if slot.color == 0 {
+                *slot = SpritePixel {
+                    color,
+                    palette,
+                    behind_bg: sprite.attributes & 0x80 != 0,
+                };
+            }
+        }
+    }
+}
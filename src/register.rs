@@ -18,11 +18,20 @@ pub enum CpuFlags {
     C = 0b00010000, //carry flag
 }
 
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Registers {
+    /// The registers as the DMG boot ROM leaves them for a cartridge with a
+    /// nonzero header checksum. `boot::post_boot_registers` covers the other
+    /// models and cartridges.
     pub fn new() -> Registers {
         Registers {
             a: 0x01,
-            f: CpuFlags::Z as u8 | CpuFlags::N as u8 | CpuFlags::H as u8 | CpuFlags::C as u8,
+            f: CpuFlags::Z as u8 | CpuFlags::H as u8 | CpuFlags::C as u8,
             b: 0x00,
             c: 0x13,
             d: 0x00,
@@ -39,13 +48,13 @@ impl Registers {
     }
 
     pub fn get_bc(&self) -> u16 {
-        return ((self.b as u16) << 8) | (self.c as u16);
+        ((self.b as u16) << 8) | (self.c as u16)
     }
     pub fn get_de(&self) -> u16 {
-        return ((self.d as u16) << 8) | (self.e as u16);
+        ((self.d as u16) << 8) | (self.e as u16)
     }
     pub fn get_hl(&self) -> u16 {
-        return ((self.h as u16) << 8) | (self.l as u16);
+        ((self.h as u16) << 8) | (self.l as u16)
     }
 
     pub fn set_af(&mut self, value: u16) {
@@ -77,6 +86,21 @@ impl Registers {
             self.f &= !mask
         }
     }
+    /// All zero, as at power on before the boot ROM runs.
+    pub fn zeroed() -> Registers {
+        Registers {
+            a: 0,
+            f: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+            pc: 0,
+            sp: 0,
+        }
+    }
     pub fn increment_pc(&mut self, value: u16) {
         self.pc = self.pc.wrapping_add(value);
     }
@@ -97,8 +121,8 @@ mod tests {
         assert_eq!(regs.l, 0x4D);
         assert_eq!(regs.pc, 0x0100);
         assert_eq!(regs.sp, 0xFFFE);
-        // Flags should be all set initially (Z, N, H, C)
-        assert_eq!(regs.f & 0xF0, 0xF0);
+        // Z, H and C set, N clear
+        assert_eq!(regs.f, 0xB0);
     }
 
     #[test]
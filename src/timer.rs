@@ -0,0 +1,173 @@
+/// DIV/TIMA/TMA/TAC (0xFF04-0xFF07).
+///
+/// DIV is the upper byte of a 16-bit counter that advances every T-cycle.
+/// TIMA increments on the falling edge of the counter bit selected by TAC,
+/// AND-ed with the enable bit, which is what makes writes to DIV and TAC
+/// able to bump TIMA on real hardware.
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    reload_pending: bool,
+    interrupt: bool,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            reload_pending: false,
+            interrupt: false,
+        }
+    }
+
+    /// Advances the timer by one M-cycle (four T-cycles).
+    pub fn tick(&mut self) {
+        if self.reload_pending {
+            // TIMA reads 0x00 for one M-cycle after overflowing, then gets TMA
+            self.reload_pending = false;
+            self.tima = self.tma;
+            self.interrupt = true;
+        }
+        let before = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        if before && !self.signal() {
+            self.increment_tima();
+        }
+    }
+
+    /// The internal counter DIV is the upper byte of.
+    pub fn counter(&self) -> u16 {
+        self.counter
+    }
+
+    /// Sets the internal counter DIV is the upper byte of, without the
+    /// falling-edge effects of a DIV write.
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+
+    /// Returns whether TIMA overflowed since the last call and clears it.
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt)
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => self.tac | 0xF8,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        let before = self.signal();
+        match address {
+            0xFF04 => self.counter = 0,
+            0xFF05 => {
+                // writing during the reload cycle cancels the reload and the interrupt
+                self.tima = value;
+                self.reload_pending = false;
+            }
+            0xFF06 => self.tma = value,
+            0xFF07 => self.tac = value & 0x07,
+            _ => {}
+        }
+        if before && !self.signal() {
+            self.increment_tima();
+        }
+    }
+
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            _ => 7,
+        };
+        self.tac & 0x04 != 0 && self.counter & (1 << bit) != 0
+    }
+
+    fn increment_tima(&mut self) {
+        let (value, overflow) = self.tima.overflowing_add(1);
+        self.tima = value;
+        self.reload_pending = overflow;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_div_increments_every_64_m_cycles() {
+        let mut timer = Timer::new();
+        for _ in 0..63 {
+            timer.tick();
+        }
+        assert_eq!(timer.read(0xFF04), 0x00);
+        timer.tick();
+        assert_eq!(timer.read(0xFF04), 0x01);
+        timer.write(0xFF04, 0x55);
+        assert_eq!(timer.read(0xFF04), 0x00);
+    }
+
+    #[test]
+    fn test_tima_fastest_rate() {
+        let mut timer = Timer::new();
+        timer.write(0xFF07, 0x05); // enabled, 16 T-cycles
+        for _ in 0..8 {
+            timer.tick();
+        }
+        assert_eq!(timer.read(0xFF05), 2);
+    }
+
+    #[test]
+    fn test_tima_overflow_reloads_one_cycle_late() {
+        let mut timer = Timer::new();
+        timer.write(0xFF06, 0xAB);
+        timer.write(0xFF05, 0xFF);
+        timer.write(0xFF07, 0x05);
+        for _ in 0..4 {
+            timer.tick();
+        }
+        assert_eq!(timer.read(0xFF05), 0x00);
+        assert!(!timer.take_interrupt());
+        timer.tick();
+        assert_eq!(timer.read(0xFF05), 0xAB);
+        assert!(timer.take_interrupt());
+        assert!(!timer.take_interrupt());
+    }
+
+    #[test]
+    fn test_div_reset_falling_edge_increments_tima() {
+        let mut timer = Timer::new();
+        timer.write(0xFF07, 0x05);
+        timer.tick();
+        timer.tick(); // counter = 8, bit 3 high
+        assert_eq!(timer.read(0xFF05), 0);
+        timer.write(0xFF04, 0);
+        assert_eq!(timer.read(0xFF05), 1);
+    }
+
+    #[test]
+    fn test_tac_reads_upper_bits_set() {
+        let mut timer = Timer::new();
+        timer.write(0xFF07, 0xFF);
+        assert_eq!(timer.read(0xFF07), 0xFF);
+        timer.write(0xFF07, 0x00);
+        assert_eq!(timer.read(0xFF07), 0xF8);
+    }
+}